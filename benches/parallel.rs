@@ -0,0 +1,39 @@
+//! Compares single-threaded and multi-threaded decoding of a large
+//! TPIU capture. Run with `cargo bench --bench parallel`.
+
+extern crate arm_coresight_decoder;
+
+use std::io::Cursor;
+use std::time::Instant;
+use arm_coresight_decoder::tpiu::types::*;
+use arm_coresight_decoder::tpiu::parser::Parser;
+use arm_coresight_decoder::tpiu::demux::{Demux, DemuxIter, SourceType, SourcePacket};
+use arm_coresight_decoder::tpiu::parallel::ParallelDecoder;
+
+fn main() {
+    let sample = include_bytes!("../testdata/etm_itm_tpiu.bin");
+    let data: Vec<u8> = sample.iter().cloned().cycle().take(sample.len() * 200).collect();
+
+    let mut demux = Demux::new();
+    demux.add_source(TraceSourceID(1), SourceType::ITM);
+
+    let start = Instant::now();
+    let expected: Vec<(TraceSourceID, SourcePacket)> =
+        DemuxIter::new(Parser::new(Box::new(Cursor::new(data.clone()))), demux.clone()).collect();
+    let single = start.elapsed();
+
+    println!("{} MB, {} packets", data.len() >> 20, expected.len());
+    println!("single-threaded: {:.3} s", single.as_secs_f64());
+
+    let mut decoder = ParallelDecoder::new(demux);
+    let available = decoder.threads();
+    for &threads in &[1, 2, 4, 8, available] {
+        decoder.set_threads(threads);
+        let start = Instant::now();
+        let result = decoder.decode(&data);
+        let parallel = start.elapsed();
+        assert!(result == expected, "parallel output differs");
+        println!("{} threads: {:.3} s, speedup {:.2}x", threads, parallel.as_secs_f64(),
+                 single.as_secs_f64() / parallel.as_secs_f64());
+    }
+}
@@ -0,0 +1,533 @@
+#ifndef ARM_CORESIGHT_DECODER_H
+#define ARM_CORESIGHT_DECODER_H
+
+/* Generated from src/ffi.rs with cbindgen, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of the API functions.
+ */
+typedef enum AcdStatus {
+  AcdStatus_Ok = 0,
+  /**
+   * A handle, data or callback argument was NULL.
+   */
+  AcdStatus_NullPointer = -1,
+  /**
+   * An argument was out of range.
+   */
+  AcdStatus_InvalidArgument = -2,
+  /**
+   * The callback returned nonzero. The rest of the packets from
+   * the same call are not reported, but the decoder stays usable.
+   */
+  AcdStatus_Aborted = -3,
+  /**
+   * Internal error in the decoder. The handle should be freed.
+   */
+  AcdStatus_Panic = -4,
+} AcdStatus;
+
+typedef enum AcdTimestampSync {
+  AcdTimestampSync_Synchronous = 0,
+  AcdTimestampSync_TimestampDelayed = 1,
+  AcdTimestampSync_DataDelayed = 2,
+  AcdTimestampSync_BothDelayed = 3,
+} AcdTimestampSync;
+
+typedef enum AcdExceptionEvent {
+  AcdExceptionEvent_Enter = 1,
+  AcdExceptionEvent_Exit = 2,
+  AcdExceptionEvent_Resume = 3,
+} AcdExceptionEvent;
+
+/**
+ * Protocol of a trace source in the demux.
+ */
+typedef enum AcdSourceType {
+  AcdSourceType_Itm = 0,
+  AcdSourceType_Stm = 1,
+  AcdSourceType_Raw = 2,
+} AcdSourceType;
+
+/**
+ * TPIU decoder handle that also decodes the data of each source.
+ */
+typedef struct AcdDemux AcdDemux;
+
+/**
+ * ITM packet decoder handle.
+ */
+typedef struct AcdItmDecoder AcdItmDecoder;
+
+/**
+ * TPIU frame decoder handle.
+ */
+typedef struct AcdTpiuDecoder AcdTpiuDecoder;
+
+/**
+ * Data value with its size in bytes: 1, 2 or 4.
+ */
+typedef struct AcdDataValue {
+  uint32_t value;
+  uint8_t size;
+} AcdDataValue;
+
+typedef struct AcdEventCounterFlags {
+  bool cpicnt;
+  bool exccnt;
+  bool sleepcnt;
+  bool lsucnt;
+  bool foldcnt;
+  bool postcnt;
+} AcdEventCounterFlags;
+
+/**
+ * Mirrors `ITMPacket`.
+ */
+typedef enum AcdItmPacket_Tag {
+  AcdItmPacket_Synchronization,
+  AcdItmPacket_Overflow,
+  AcdItmPacket_LocalTimestamp,
+  AcdItmPacket_GlobalTimestamp,
+  AcdItmPacket_SoftwarePageNumber,
+  AcdItmPacket_Software,
+  AcdItmPacket_EventCounter,
+  AcdItmPacket_ProgramCounter,
+  AcdItmPacket_SleepMode,
+  AcdItmPacket_Exception,
+  AcdItmPacket_DataTracePC,
+  AcdItmPacket_DataTraceOffset,
+  AcdItmPacket_DataTraceReadData,
+  AcdItmPacket_DataTraceWriteData,
+  AcdItmPacket_Extension,
+  AcdItmPacket_Reserved,
+  AcdItmPacket_Invalid,
+} AcdItmPacket_Tag;
+
+typedef struct AcdItmPacket_LocalTimestamp_Body {
+  enum AcdTimestampSync sync;
+  uint32_t delta;
+} AcdItmPacket_LocalTimestamp_Body;
+
+typedef struct AcdItmPacket_GlobalTimestamp_Body {
+  uint64_t timestamp;
+  uint64_t known_mask;
+  bool wrap;
+  bool clock_change;
+} AcdItmPacket_GlobalTimestamp_Body;
+
+typedef struct AcdItmPacket_SoftwarePageNumber_Body {
+  uint32_t page;
+} AcdItmPacket_SoftwarePageNumber_Body;
+
+typedef struct AcdItmPacket_Software_Body {
+  uint32_t port;
+  struct AcdDataValue value;
+} AcdItmPacket_Software_Body;
+
+typedef struct AcdItmPacket_EventCounter_Body {
+  struct AcdEventCounterFlags flags;
+} AcdItmPacket_EventCounter_Body;
+
+typedef struct AcdItmPacket_ProgramCounter_Body {
+  uint32_t address;
+} AcdItmPacket_ProgramCounter_Body;
+
+typedef struct AcdItmPacket_Exception_Body {
+  enum AcdExceptionEvent event;
+  uint32_t number;
+} AcdItmPacket_Exception_Body;
+
+typedef struct AcdItmPacket_DataTracePC_Body {
+  uint32_t comparator;
+  uint32_t address;
+} AcdItmPacket_DataTracePC_Body;
+
+typedef struct AcdItmPacket_DataTraceOffset_Body {
+  uint32_t comparator;
+  uint32_t offset;
+} AcdItmPacket_DataTraceOffset_Body;
+
+typedef struct AcdItmPacket_DataTraceReadData_Body {
+  uint32_t comparator;
+  struct AcdDataValue value;
+} AcdItmPacket_DataTraceReadData_Body;
+
+typedef struct AcdItmPacket_DataTraceWriteData_Body {
+  uint32_t comparator;
+  struct AcdDataValue value;
+} AcdItmPacket_DataTraceWriteData_Body;
+
+typedef struct AcdItmPacket_Extension_Body {
+  uint32_t data;
+  uint8_t bitcount;
+  uint8_t source;
+} AcdItmPacket_Extension_Body;
+
+typedef struct AcdItmPacket_Reserved_Body {
+  uint8_t header;
+} AcdItmPacket_Reserved_Body;
+
+typedef struct AcdItmPacket_Invalid_Body {
+  const char *message;
+} AcdItmPacket_Invalid_Body;
+
+typedef struct AcdItmPacket {
+  AcdItmPacket_Tag tag;
+  union {
+    AcdItmPacket_LocalTimestamp_Body local_timestamp;
+    AcdItmPacket_GlobalTimestamp_Body global_timestamp;
+    AcdItmPacket_SoftwarePageNumber_Body software_page_number;
+    AcdItmPacket_Software_Body software;
+    AcdItmPacket_EventCounter_Body event_counter;
+    AcdItmPacket_ProgramCounter_Body program_counter;
+    AcdItmPacket_Exception_Body exception;
+    AcdItmPacket_DataTracePC_Body data_trace_pc;
+    AcdItmPacket_DataTraceOffset_Body data_trace_offset;
+    AcdItmPacket_DataTraceReadData_Body data_trace_read_data;
+    AcdItmPacket_DataTraceWriteData_Body data_trace_write_data;
+    AcdItmPacket_Extension_Body extension;
+    AcdItmPacket_Reserved_Body reserved;
+    AcdItmPacket_Invalid_Body invalid;
+  };
+} AcdItmPacket;
+
+/**
+ * Called with the user pointer, the offset of the packet in the
+ * bytes pushed to the decoder, and the packet. Returning nonzero
+ * stops decoding.
+ */
+typedef int (*AcdItmCallback)(void *user, uint64_t offset, const struct AcdItmPacket *packet);
+
+/**
+ * Byte buffer owned by the decoder.
+ */
+typedef struct AcdBytes {
+  const uint8_t *data;
+  size_t length;
+} AcdBytes;
+
+/**
+ * Mirrors `TPIUPacket`.
+ */
+typedef enum AcdTpiuPacket_Tag {
+  AcdTpiuPacket_FrameSynchronization,
+  AcdTpiuPacket_HalfwordSynchronization,
+  AcdTpiuPacket_Data,
+  AcdTpiuPacket_Trigger,
+  AcdTpiuPacket_Null,
+  AcdTpiuPacket_Reserved,
+  AcdTpiuPacket_Invalid,
+} AcdTpiuPacket_Tag;
+
+typedef struct AcdTpiuPacket_Data_Body {
+  uint8_t source;
+  struct AcdBytes data;
+} AcdTpiuPacket_Data_Body;
+
+typedef struct AcdTpiuPacket_Trigger_Body {
+  struct AcdBytes data;
+} AcdTpiuPacket_Trigger_Body;
+
+typedef struct AcdTpiuPacket_Null_Body {
+  struct AcdBytes data;
+} AcdTpiuPacket_Null_Body;
+
+typedef struct AcdTpiuPacket_Reserved_Body {
+  struct AcdBytes data;
+} AcdTpiuPacket_Reserved_Body;
+
+typedef struct AcdTpiuPacket_Invalid_Body {
+  const char *message;
+} AcdTpiuPacket_Invalid_Body;
+
+typedef struct AcdTpiuPacket {
+  AcdTpiuPacket_Tag tag;
+  union {
+    AcdTpiuPacket_Data_Body data;
+    AcdTpiuPacket_Trigger_Body trigger;
+    AcdTpiuPacket_Null_Body null;
+    AcdTpiuPacket_Reserved_Body reserved;
+    AcdTpiuPacket_Invalid_Body invalid;
+  };
+} AcdTpiuPacket;
+
+/**
+ * Called like `AcdItmCallback`, with the offset of the TPIU frame.
+ */
+typedef int (*AcdTpiuCallback)(void *user, uint64_t offset, const struct AcdTpiuPacket *packet);
+
+/**
+ * Mirrors `STMPacket`. Timestamps are valid if `has_timestamp`
+ * is set, and `bits` gives the size of data values.
+ */
+typedef enum AcdStmPacket_Tag {
+  AcdStmPacket_Null,
+  AcdStmPacket_Async,
+  AcdStmPacket_Version,
+  AcdStmPacket_Master,
+  AcdStmPacket_Channel,
+  AcdStmPacket_Data,
+  AcdStmPacket_Flag,
+  AcdStmPacket_Trigger,
+  AcdStmPacket_NullTimestamp,
+  AcdStmPacket_Frequency,
+  AcdStmPacket_MasterError,
+  AcdStmPacket_GlobalError,
+  AcdStmPacket_Reserved,
+  AcdStmPacket_Invalid,
+} AcdStmPacket_Tag;
+
+typedef struct AcdStmPacket_Version_Body {
+  uint8_t version;
+} AcdStmPacket_Version_Body;
+
+typedef struct AcdStmPacket_Master_Body {
+  uint16_t master;
+} AcdStmPacket_Master_Body;
+
+typedef struct AcdStmPacket_Channel_Body {
+  uint16_t channel;
+} AcdStmPacket_Channel_Body;
+
+typedef struct AcdStmPacket_Data_Body {
+  uint16_t master;
+  uint16_t channel;
+  uint64_t value;
+  uint8_t bits;
+  bool marker;
+  bool has_timestamp;
+  uint64_t timestamp;
+} AcdStmPacket_Data_Body;
+
+typedef struct AcdStmPacket_Flag_Body {
+  uint16_t master;
+  uint16_t channel;
+  bool has_timestamp;
+  uint64_t timestamp;
+} AcdStmPacket_Flag_Body;
+
+typedef struct AcdStmPacket_Trigger_Body {
+  uint16_t master;
+  uint16_t channel;
+  uint8_t value;
+  bool has_timestamp;
+  uint64_t timestamp;
+} AcdStmPacket_Trigger_Body;
+
+typedef struct AcdStmPacket_NullTimestamp_Body {
+  uint64_t timestamp;
+} AcdStmPacket_NullTimestamp_Body;
+
+typedef struct AcdStmPacket_Frequency_Body {
+  uint32_t frequency;
+} AcdStmPacket_Frequency_Body;
+
+typedef struct AcdStmPacket_MasterError_Body {
+  uint8_t value;
+} AcdStmPacket_MasterError_Body;
+
+typedef struct AcdStmPacket_GlobalError_Body {
+  uint8_t value;
+} AcdStmPacket_GlobalError_Body;
+
+typedef struct AcdStmPacket_Reserved_Body {
+  uint16_t opcode;
+} AcdStmPacket_Reserved_Body;
+
+typedef struct AcdStmPacket_Invalid_Body {
+  const char *message;
+} AcdStmPacket_Invalid_Body;
+
+typedef struct AcdStmPacket {
+  AcdStmPacket_Tag tag;
+  union {
+    AcdStmPacket_Version_Body version;
+    AcdStmPacket_Master_Body master;
+    AcdStmPacket_Channel_Body channel;
+    AcdStmPacket_Data_Body data;
+    AcdStmPacket_Flag_Body flag;
+    AcdStmPacket_Trigger_Body trigger;
+    AcdStmPacket_NullTimestamp_Body null_timestamp;
+    AcdStmPacket_Frequency_Body frequency;
+    AcdStmPacket_MasterError_Body master_error;
+    AcdStmPacket_GlobalError_Body global_error;
+    AcdStmPacket_Reserved_Body reserved;
+    AcdStmPacket_Invalid_Body invalid;
+  };
+} AcdStmPacket;
+
+/**
+ * Mirrors `SourcePacket`, a packet from one TPIU trace source.
+ */
+typedef enum AcdSourcePacket_Tag {
+  AcdSourcePacket_Itm,
+  AcdSourcePacket_Stm,
+  AcdSourcePacket_Raw,
+  AcdSourcePacket_Trigger,
+} AcdSourcePacket_Tag;
+
+typedef struct AcdSourcePacket_Itm_Body {
+  struct AcdItmPacket packet;
+} AcdSourcePacket_Itm_Body;
+
+typedef struct AcdSourcePacket_Stm_Body {
+  struct AcdStmPacket packet;
+} AcdSourcePacket_Stm_Body;
+
+typedef struct AcdSourcePacket_Raw_Body {
+  struct AcdBytes data;
+} AcdSourcePacket_Raw_Body;
+
+typedef struct AcdSourcePacket_Trigger_Body {
+  struct AcdBytes data;
+} AcdSourcePacket_Trigger_Body;
+
+typedef struct AcdSourcePacket {
+  AcdSourcePacket_Tag tag;
+  union {
+    AcdSourcePacket_Itm_Body itm;
+    AcdSourcePacket_Stm_Body stm;
+    AcdSourcePacket_Raw_Body raw;
+    AcdSourcePacket_Trigger_Body trigger;
+  };
+} AcdSourcePacket;
+
+/**
+ * Called like `AcdItmCallback`, with the trace source ID and the
+ * offset of the TPIU frame that completed the packet.
+ */
+typedef int (*AcdSourceCallback)(void *user,
+                                 uint64_t offset,
+                                 uint8_t source,
+                                 const struct AcdSourcePacket *packet);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Version of the C API, incremented on incompatible changes.
+ */
+uint32_t acd_abi_version(void);
+
+/**
+ * Version of the library, as a static string.
+ */
+const char *acd_version(void);
+
+/**
+ * Creates an ITM decoder. The stream must start at a packet
+ * boundary, e.g. at a synchronization packet.
+ */
+struct AcdItmDecoder *acd_itm_decoder_new(void);
+
+/**
+ * # Safety
+ * The decoder must come from `acd_itm_decoder_new()`, or be NULL.
+ */
+void acd_itm_decoder_free(struct AcdItmDecoder *decoder);
+
+/**
+ * Drops partially received data and restarts offsets from zero.
+ *
+ * # Safety
+ * The decoder must be a valid handle or NULL.
+ */
+enum AcdStatus acd_itm_decoder_reset(struct AcdItmDecoder *decoder);
+
+/**
+ * Decodes the pushed bytes and calls the callback for each complete
+ * packet. A partial packet at the end is kept for the next call.
+ *
+ * # Safety
+ * The decoder must be a valid handle and data must point to length
+ * bytes. The decoder may not be used from the callback.
+ */
+enum AcdStatus acd_itm_decoder_push(struct AcdItmDecoder *decoder,
+                                    const uint8_t *data,
+                                    size_t length,
+                                    AcdItmCallback callback,
+                                    void *user);
+
+/**
+ * Creates a TPIU decoder. The stream must start at a frame boundary,
+ * and data before the first ID change belongs to the given source.
+ */
+struct AcdTpiuDecoder *acd_tpiu_decoder_new(uint8_t source);
+
+/**
+ * # Safety
+ * The decoder must come from `acd_tpiu_decoder_new()`, or be NULL.
+ */
+void acd_tpiu_decoder_free(struct AcdTpiuDecoder *decoder);
+
+/**
+ * Drops partially received frames and restores the initial source.
+ *
+ * # Safety
+ * The decoder must be a valid handle or NULL.
+ */
+enum AcdStatus acd_tpiu_decoder_reset(struct AcdTpiuDecoder *decoder);
+
+/**
+ * Decodes the complete frames and calls the callback for each packet.
+ *
+ * # Safety
+ * The decoder must be a valid handle and data must point to length
+ * bytes. The decoder may not be used from the callback.
+ */
+enum AcdStatus acd_tpiu_decoder_push(struct AcdTpiuDecoder *decoder,
+                                     const uint8_t *data,
+                                     size_t length,
+                                     AcdTpiuCallback callback,
+                                     void *user);
+
+/**
+ * Creates a demux with the initial source like in
+ * `acd_tpiu_decoder_new()`. Data of each source is passed as raw
+ * data until it is configured with `acd_demux_add_source()`.
+ */
+struct AcdDemux *acd_demux_new(uint8_t source);
+
+/**
+ * # Safety
+ * The demux must come from `acd_demux_new()`, or be NULL.
+ */
+void acd_demux_free(struct AcdDemux *demux);
+
+/**
+ * Selects the protocol of a trace source ID, from 1 to 0x6F.
+ *
+ * # Safety
+ * The demux must be a valid handle or NULL.
+ */
+enum AcdStatus acd_demux_add_source(struct AcdDemux *demux,
+                                    uint8_t source,
+                                    enum AcdSourceType source_type);
+
+/**
+ * Decodes the complete frames and the packets of the configured
+ * sources, and calls the callback for each packet.
+ *
+ * # Safety
+ * The demux must be a valid handle and data must point to length
+ * bytes. The demux may not be used from the callback.
+ */
+enum AcdStatus acd_demux_push(struct AcdDemux *demux,
+                              const uint8_t *data,
+                              size_t length,
+                              AcdSourceCallback callback,
+                              void *user);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ARM_CORESIGHT_DECODER_H */
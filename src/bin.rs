@@ -1,51 +1,167 @@
 extern crate arm_coresight_decoder;
 
-use arm_coresight_decoder::itm::parser;
+use arm_coresight_decoder::itm::parser::Parser as ITMParser;
 use arm_coresight_decoder::itm::heuristics;
-//use arm_coresight_decoder::tpiu::parser;
-use std::io::{Read,BufRead,Write};
+use arm_coresight_decoder::itm::timestamp::Timestamper;
+use arm_coresight_decoder::itm::schema::{Schema, SignalDecoder};
+use arm_coresight_decoder::tpiu::parser::Parser as TPIUParser;
+use arm_coresight_decoder::tpiu::types::TraceSourceID;
+use arm_coresight_decoder::decoder::Decoder;
+use arm_coresight_decoder::export::record::{Record, demuxed_itm_records, demuxed_itm_signal_records};
+#[cfg(feature = "serde")]
+use arm_coresight_decoder::export::jsonl::JsonLinesWriter;
+#[cfg(feature = "serde")]
+use arm_coresight_decoder::export::csv::CsvWriter;
+use std::fmt::Debug;
+use std::io::{Read, BufRead, Write, Error};
 
-pub fn main() {
+const USAGE: &str = "Usage: arm_coresight_decoder [--tpiu [--source ID]] [--format debug|jsonl|csv] \
+                     [--schema ports.txt] < input";
+
+/// Writes the packets in the selected output format.
+enum Output<W: Write> {
+    Debug(W),
+    #[cfg(feature = "serde")]
+    JsonLines(JsonLinesWriter<W>),
+    #[cfg(feature = "serde")]
+    Csv(Box<CsvWriter<W>>),
+}
+
+impl<W: Write> Output<W> {
+    fn new(format: &str, output: W) -> Result<Output<W>, String> {
+        match format {
+            "debug" => Ok(Output::Debug(output)),
+            #[cfg(feature = "serde")]
+            "jsonl" => JsonLinesWriter::new(output).map(Output::JsonLines).map_err(|e| e.to_string()),
+            #[cfg(feature = "serde")]
+            "csv" => CsvWriter::new(output).map(|w| Output::Csv(Box::new(w))).map_err(|e| e.to_string()),
+            #[cfg(not(feature = "serde"))]
+            "jsonl" | "csv" => Err(String::from("Built without the serde feature")),
+            _ => Err(String::from(USAGE)),
+        }
+    }
+
+    fn write<P: Debug>(&mut self, record: &Record, packet: &P) -> Result<(), Error> {
+        match *self {
+            Output::Debug(ref mut output) => {
+                writeln!(output, "0x{:08x}: {:?}", record.offset.unwrap_or(0), packet)
+            },
+            #[cfg(feature = "serde")]
+            Output::JsonLines(ref mut writer) => writer.write(record),
+            #[cfg(feature = "serde")]
+            Output::Csv(ref mut writer) => writer.write(record),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match *self {
+            Output::Debug(ref mut output) => output.flush(),
+            #[cfg(feature = "serde")]
+            Output::JsonLines(ref mut writer) => writer.flush(),
+            #[cfg(feature = "serde")]
+            Output::Csv(ref mut writer) => writer.flush(),
+        }
+    }
+}
+
+/// Command line options. With `--tpiu` and `--source`, the ITM
+/// packets of the trace source are output instead of the frames.
+struct Args {
+    tpiu: bool,
+    source: Option<TraceSourceID>,
+    format: String,
+    schema: Option<Schema>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args { tpiu: false, source: None, format: String::from("debug"), schema: None };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--tpiu" => args.tpiu = true,
+            "--source" => {
+                let id = iter.next().ok_or(USAGE)?;
+                args.source = Some(TraceSourceID(id.parse().map_err(|_| format!("Invalid source ID {}", id))?));
+            },
+            "--format" => args.format = iter.next().ok_or(USAGE)?,
+            "--schema" => {
+                let path = iter.next().ok_or(USAGE)?;
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+                args.schema = Some(Schema::parse(&text).map_err(|e| format!("{}: {}", path, e))?);
+            },
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    if args.source.is_some() && !args.tpiu {
+        return Err(String::from("--source selects a trace source of --tpiu input"));
+    }
+    if args.tpiu && args.source.is_none() && args.schema.is_some() {
+        return Err(String::from("--schema applies to ITM input, use --source to decode ITM from --tpiu"));
+    }
+    Ok(args)
+}
+
+fn run() -> Result<u32, String> {
+    let args = parse_args()?;
+    let stdout = std::io::stdout();
+    let mut output = Output::new(&args.format, std::io::BufWriter::new(stdout.lock()))?;
     let mut input = std::io::BufReader::new(std::io::stdin());
-    let mut output = std::io::BufWriter::new(std::io::stdout());
-    // let mut packetcount: u32 = 0;
-
-    //let mut parser = parser::Parser::new(Box::new(input));
-
-//    for packet in parser.by_ref() {
-  //      writeln!(&mut output, "0x{:08x}: {:?}", parser.position(), packet).unwrap();
-    //}
-
-    //if let Some(ref e) = parser.error() {
-    //    output.flush().ok();
-    //    if e.kind() != std::io::ErrorKind::UnexpectedEof
-    //    {
-    //        eprintln!("Error: {}", e);
-    //    }
-   // }
-
-     let start = heuristics::find_starting_point(input.fill_buf().unwrap());
-     println!("Start {}", start);
-     let mut input = ReadPos::new(input);
-     input.read(&mut vec![0; start]).ok();
-
-     loop {
-         let packet = parser::parse_one(&mut input);
-         match packet {
-             Ok(packet) => {
-                 packetcount += 1;
-                 writeln!(&mut output, "0x{:08x}: {:?}", input.position(), packet).unwrap();
-             },
-             Err(e) => {
-                 output.flush().ok();
-                 if e.kind() != std::io::ErrorKind::UnexpectedEof
-                 {
-                     eprintln!("Error: {}", e);
-                 }
-                 break;
-             }
-         }
-    }
-
-    println!("Total packets: {}", packetcount);
+    let mut packetcount: u32 = 0;
+
+    if let (true, Some(source)) = (args.tpiu, args.source) {
+        let mut parser = TPIUParser::new(Box::new(input));
+        let records: Box<dyn Iterator<Item=Record>> = match args.schema {
+            Some(schema) => Box::new(demuxed_itm_signal_records(&mut parser, source, schema)),
+            None => Box::new(demuxed_itm_records(&mut parser, source)),
+        };
+        for record in records {
+            if record.kind != "Signal" {
+                packetcount += 1;
+            }
+            output.write(&record, &record).map_err(|e| e.to_string())?;
+        }
+        if let Some(e) = parser.error() {
+            return Err(e.to_string());
+        }
+    } else if args.tpiu {
+        let mut parser = TPIUParser::new(Box::new(input));
+        while let Some(packet) = parser.next() {
+            packetcount += 1;
+            let record = Record::from_tpiu(Some(parser.packet_offset() as u64), &packet);
+            output.write(&record, &packet).map_err(|e| e.to_string())?;
+        }
+        if let Some(e) = parser.error() {
+            return Err(e.to_string());
+        }
+    } else {
+        let mut signals = args.schema.map(SignalDecoder::new);
+        let start = heuristics::find_starting_point(input.fill_buf().map_err(|e| e.to_string())?);
+        input.consume(start);
+        let mut parser = ITMParser::new(input.by_ref());
+        for (time, (offset, packet)) in Timestamper::new((&mut parser).with_offsets()) {
+            packetcount += 1;
+            let record = Record::from_itm(Some((start + offset) as u64), Some(time), None, &packet);
+            output.write(&record, &packet).map_err(|e| e.to_string())?;
+            for sample in signals.as_mut().map(|s| s.push(time, &packet)).unwrap_or_default() {
+                let record = Record::from_signal(record.offset, None, &sample);
+                output.write(&record, &sample).map_err(|e| e.to_string())?;
+            }
+        }
+        if let Some(e) = parser.error() {
+            return Err(e.to_string());
+        }
+    }
+
+    output.flush().map_err(|e| e.to_string())?;
+    Ok(packetcount)
+}
+
+pub fn main() {
+    match run() {
+        Ok(packetcount) => eprintln!("Total packets: {}", packetcount),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,140 @@
+//! Common interface of the protocol parsers, so that generic tools
+//! such as demuxing, indexing and output can work with any protocol.
+
+use std::io::Error;
+
+/// Protocol parser that reads its input and returns the decoded
+/// packets as an iterator. Iteration stops at the end of input or at
+/// a read error, which is then available from `error()`.
+pub trait Decoder: Iterator<Item = <Self as Decoder>::Packet> {
+    /// Packet type returned by the parser.
+    type Packet;
+
+    /// Settings given when creating the parser.
+    type Config: Clone + Default;
+
+    fn config(&self) -> &Self::Config;
+
+    /// Number of input bytes read so far.
+    fn position(&self) -> usize;
+
+    /// Input offset of the first byte of the last returned packet.
+    /// For packets decoded from within a frame, this is the offset of
+    /// the frame.
+    fn packet_offset(&self) -> usize;
+
+    /// Error that stopped the iteration, other than end of input.
+    fn error(&self) -> Option<&Error>;
+
+    /// Discards any partially decoded data and returns to the initial
+    /// state given by the configuration. The input is not rewound, so
+    /// this is useful after seeking the input to a sync point.
+    fn reset(&mut self);
+
+    /// Resets the parser and skips input until after the next
+    /// synchronization packet of the protocol. Returns false if the
+    /// input ended before a synchronization packet was found.
+    fn resync(&mut self) -> bool;
+
+    /// Pairs each packet with its `packet_offset()`.
+    fn with_offsets(self) -> WithOffsets<Self> where Self: Sized {
+        WithOffsets { decoder: self }
+    }
+}
+
+/// Allows using a parser through a generic adapter and checking its
+/// `error()` afterwards.
+impl<D: Decoder> Decoder for &mut D {
+    type Packet = D::Packet;
+    type Config = D::Config;
+
+    fn config(&self) -> &D::Config {
+        (**self).config()
+    }
+
+    fn position(&self) -> usize {
+        (**self).position()
+    }
+
+    fn packet_offset(&self) -> usize {
+        (**self).packet_offset()
+    }
+
+    fn error(&self) -> Option<&Error> {
+        (**self).error()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn resync(&mut self) -> bool {
+        (**self).resync()
+    }
+}
+
+/// Iterator adapter returned by `Decoder::with_offsets()`.
+pub struct WithOffsets<D> {
+    decoder: D,
+}
+
+impl<D: Decoder> WithOffsets<D> {
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+}
+
+impl<D: Decoder> Iterator for WithOffsets<D> {
+    type Item = (usize, D::Packet);
+    fn next(&mut self) -> Option<(usize, D::Packet)> {
+        let packet = self.decoder.next()?;
+        Some((self.decoder.packet_offset(), packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use ::itm::types::*;
+    use ::itm::parser::Parser as ITMParser;
+    use ::tpiu::types::*;
+    use ::tpiu::parser::{Parser as TPIUParser, Config as TPIUConfig};
+
+    /// Generic code that works with any decoder.
+    fn resynced<D: Decoder>(mut decoder: D) -> (bool, Vec<(usize, D::Packet)>) {
+        let found = decoder.resync();
+        (found, decoder.with_offsets().collect())
+    }
+
+    #[test]
+    fn test_itm() {
+        let data = vec![0x17, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x41, 0x70];
+        let (found, packets) = resynced(ITMParser::new(Cursor::new(data)));
+        assert!(found);
+        assert_eq!(packets, vec![
+            (9, ITMPacket::Software(InstrumentationPort(0), DataValue::U8(0x41))),
+            (11, ITMPacket::Overflow),
+        ]);
+
+        let (found, packets) = resynced(ITMParser::new(Cursor::new(vec![0x00, 0x00, 0x80])));
+        assert!(!found);
+        assert!(packets.is_empty());
+    }
+
+    #[test]
+    fn test_tpiu() {
+        let mut data = vec![0x55; 5];
+        data.extend(&[0xFF, 0xFF, 0xFF, 0x7F]);
+        data.extend(&[0x03, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70,
+                      0x80, 0x90, 0xA0, 0xB0, 0xC0, 0xD0, 0xE0, 0x00]);
+
+        let config = TPIUConfig { source: TraceSourceID(2) };
+        let (found, packets) = resynced(TPIUParser::with_config(Box::new(Cursor::new(data)), config));
+        assert!(found);
+        assert_eq!(packets, vec![
+            (9, TPIUPacket::Data(TraceSourceID(1), vec![0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70,
+                                                        0x80, 0x90, 0xA0, 0xB0, 0xC0, 0xD0, 0xE0])),
+        ]);
+    }
+}
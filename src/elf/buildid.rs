@@ -0,0 +1,16 @@
+//! Reads the GNU build ID note, which identifies the exact build
+//! of the firmware that produced a trace.
+
+extern crate object;
+use self::object::Object;
+
+use std::io::{Error, ErrorKind};
+
+/// Returns the build ID bytes, or None if the file has no build ID.
+pub fn build_id(data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let file = object::File::parse(data)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let id = file.build_id()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(id.map(|id| id.to_vec()))
+}
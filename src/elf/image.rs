@@ -0,0 +1,109 @@
+//! Program memory contents, for decoding the instructions that
+//! the instruction trace refers to.
+
+extern crate object;
+use self::object::{Object, ObjectSegment};
+
+use std::io::{Error, ErrorKind};
+use ::itm::types::{Address, ExceptionNumber};
+use ::flow::thumb;
+use ::flow::types::BranchKind;
+
+/// Memory regions with known contents.
+#[derive(Debug, Clone, Default)]
+pub struct Image {
+    regions: Vec<(Address, Vec<u8>)>,
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image { regions: Vec::new() }
+    }
+
+    /// Loads the contents of the loadable segments of an ELF file.
+    pub fn from_elf(data: &[u8]) -> Result<Image, Error> {
+        let file = object::File::parse(data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        let mut image = Image::new();
+        for segment in file.segments() {
+            let contents = segment.data()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+            if !contents.is_empty() {
+                image.add_region(Address(segment.address() as u32), contents.to_vec());
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn add_region(&mut self, address: Address, data: Vec<u8>) {
+        self.regions.push((address, data));
+        self.regions.sort_by_key(|r| r.0);
+    }
+
+    /// Reads bytes from the image, if all of them are known.
+    pub fn read(&self, address: Address, length: usize) -> Option<&[u8]> {
+        let end = self.regions.partition_point(|r| r.0 <= address);
+        let &(start, ref data) = self.regions[..end].last()?;
+        let offset = (address.0 - start.0) as usize;
+        data.get(offset..offset + length)
+    }
+
+    pub fn read_u16(&self, address: Address) -> Option<u16> {
+        self.read(address, 2).map(|b| b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    pub fn read_u32(&self, address: Address) -> Option<u32> {
+        self.read(address, 4).map(|b| {
+            b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+        })
+    }
+
+    /// Finds the exception number whose handler is at the given
+    /// address, by searching the vector table.
+    pub fn exception_number(&self, vector_table: Address, handler: Address) -> Option<ExceptionNumber> {
+        // Entry 0 is the initial stack pointer, and there are
+        // at most 496 external interrupts.
+        (1..512).map(|n| (n, self.read_u32(Address(vector_table.0 + n * 4))))
+            .take_while(|&(_, entry)| entry.is_some())
+            .find(|&(_, entry)| entry == Some(handler.0 | 1))
+            .map(|(n, _)| ExceptionNumber(n))
+    }
+
+    /// Size of the Thumb instruction at address.
+    pub fn instruction_size(&self, address: Address) -> Option<u32> {
+        self.read_u16(address).map(thumb::instruction_size)
+    }
+
+    /// Returns true if there is a conditional branch instruction at address.
+    pub fn is_conditional_branch(&self, address: Address) -> Option<bool> {
+        let first = self.read_u16(address)?;
+        let second = self.read_u16(Address(address.0 + 2)).unwrap_or(0);
+        Some(thumb::is_conditional_branch(first, second))
+    }
+
+    /// Classifies the branch instruction at address, see
+    /// `thumb::classify_branch`.
+    pub fn classify_branch(&self, address: Address) -> Option<BranchKind> {
+        let first = self.read_u16(address)?;
+        let second = self.read_u16(Address(address.0 + 2)).unwrap_or(0);
+        thumb::classify_branch(first, second)
+    }
+
+    /// Lists the addresses of instructions from start up to end.
+    /// Stops early if the image does not cover the whole range.
+    pub fn instructions(&self, start: Address, end: Address) -> Vec<Address> {
+        let mut result = Vec::new();
+        let mut address = start;
+        while address < end {
+            match self.instruction_size(address) {
+                Some(size) => {
+                    result.push(address);
+                    address = Address(address.0 + size);
+                },
+                None => break
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,149 @@
+//! Maps addresses to source file and line through the DWARF
+//! line number table.
+
+extern crate object;
+extern crate gimli;
+use self::object::{Object, ObjectSection};
+
+use std::io::{Error, ErrorKind};
+use ::itm::types::Address;
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    address: Address,
+    file: usize,
+    line: u32,
+    end_sequence: bool,
+}
+
+/// Line number table for the whole program.
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    rows: Vec<Row>,
+}
+
+fn dwarf_error(e: gimli::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+impl LineTable {
+    pub fn new() -> LineTable {
+        LineTable { files: Vec::new(), rows: Vec::new() }
+    }
+
+    /// Loads the line number programs of all compilation units.
+    /// Returns an empty table if the ELF file has no debug info.
+    pub fn from_elf(data: &[u8]) -> Result<LineTable, Error> {
+        let file = object::File::parse(data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        let sections = gimli::DwarfSections::load(|id| -> Result<&[u8], Error> {
+            match file.section_by_name(id.name()) {
+                Some(section) => section.data()
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string())),
+                None => Ok(&[])
+            }
+        })?;
+        let dwarf = sections.borrow(|s| gimli::EndianSlice::new(s, gimli::LittleEndian));
+
+        let mut table = LineTable::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next().map_err(dwarf_error)? {
+            let unit = dwarf.unit(header).map_err(dwarf_error)?;
+            let program = match unit.line_program {
+                Some(ref program) => program.clone(),
+                None => continue
+            };
+
+            let mut file_indexes = Vec::new();
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row().map_err(dwarf_error)? {
+                let file_index = row.file_index() as usize;
+                if file_indexes.len() <= file_index {
+                    file_indexes.resize(file_index + 1, None);
+                }
+
+                if file_indexes[file_index].is_none() {
+                    let path = match row.file(header) {
+                        Some(entry) => {
+                            let name = dwarf.attr_string(&unit, entry.path_name())
+                                .map_err(dwarf_error)?.to_string_lossy().into_owned();
+                            let dir = match entry.directory(header) {
+                                Some(dir) => dwarf.attr_string(&unit, dir)
+                                    .map_err(dwarf_error)?.to_string_lossy().into_owned(),
+                                None => String::new()
+                            };
+                            if dir.is_empty() || name.starts_with('/') {
+                                name
+                            } else {
+                                format!("{}/{}", dir, name)
+                            }
+                        },
+                        None => String::from("???")
+                    };
+                    file_indexes[file_index] = Some(table.file_index(path));
+                }
+
+                table.rows.push(Row {
+                    address: Address(row.address() as u32),
+                    file: file_indexes[file_index].unwrap(),
+                    line: row.line().map(|l| l.get() as u32).unwrap_or(0),
+                    end_sequence: row.end_sequence(),
+                });
+            }
+        }
+
+        // Sequence end rows go before rows starting at the same address.
+        table.rows.sort_by_key(|r| (r.address, !r.end_sequence));
+        Ok(table)
+    }
+
+    fn file_index(&mut self, path: String) -> usize {
+        match self.files.iter().position(|f| *f == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Lists the start address, source file and line number of
+    /// every row in the table.
+    pub fn entries(&self) -> impl Iterator<Item=(Address, &str, u32)> {
+        self.rows.iter().filter(|r| !r.end_sequence)
+            .map(move |r| (r.address, &self.files[r.file][..], r.line))
+    }
+
+    /// Returns source file path and line number for address.
+    pub fn lookup(&self, address: Address) -> Option<(&str, u32)> {
+        let end = self.rows.partition_point(|r| r.address <= address);
+        let row = self.rows[..end].last()?;
+        if row.end_sequence {
+            None
+        } else {
+            Some((&self.files[row.file], row.line))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_elf() {
+        let table = LineTable::from_elf(include_bytes!("../../testdata/lines.elf")).unwrap();
+        assert_eq!(table.lookup(Address(0x1000)), Some(("/src/fixture.c", 10)));
+        assert_eq!(table.lookup(Address(0x100f)), Some(("/src/fixture.c", 12)));
+        assert_eq!(table.lookup(Address(0x1036)), Some(("/src/fixture.c", 19)));
+        assert_eq!(table.lookup(Address(0x1037)), None);
+        assert_eq!(table.lookup(Address(0xfff)), None);
+        assert_eq!(table.entries().map(|e| e.2).collect::<Vec<u32>>(), vec![10, 11, 12, 13, 14, 17, 18, 18, 19]);
+    }
+}
@@ -0,0 +1,6 @@
+//! Reads debug information from the firmware ELF file.
+
+pub mod symbols;
+pub mod image;
+pub mod lines;
+pub mod buildid;
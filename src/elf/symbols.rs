@@ -0,0 +1,98 @@
+//! Function symbol table for mapping addresses to names.
+
+extern crate object;
+use self::object::{Architecture, Object, ObjectSymbol, SymbolKind};
+
+use std::io::{Error, ErrorKind};
+use ::itm::types::Address;
+
+/// A function symbol from the ELF symbol table.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: Address,
+    pub size: u32,
+}
+
+impl Symbol {
+    pub fn contains(&self, address: Address) -> bool {
+        address >= self.address && (address.0 - self.address.0) < self.size.max(1)
+    }
+}
+
+/// Sorted list of function symbols.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> SymbolTable {
+        symbols.sort_by_key(|s| s.address);
+        SymbolTable { symbols }
+    }
+
+    /// Loads the function symbols from ELF file contents.
+    pub fn from_elf(data: &[u8]) -> Result<SymbolTable, Error> {
+        let file = object::File::parse(data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        // Thumb function addresses have the lowest bit set.
+        let mask = if file.architecture() == Architecture::Arm { !1 } else { !0 };
+        let symbols = file.symbols().filter(|s| {
+            s.kind() == SymbolKind::Text && s.is_definition()
+        }).filter_map(|s| {
+            let name = s.name().ok()?;
+            Some(Symbol {
+                name: String::from(name),
+                address: Address(s.address() as u32 & mask),
+                size: s.size() as u32,
+            })
+        }).collect();
+
+        Ok(SymbolTable::new(symbols))
+    }
+
+    /// Finds the function that contains the address.
+    pub fn lookup(&self, address: Address) -> Option<&Symbol> {
+        let end = self.symbols.partition_point(|s| s.address <= address);
+        if end == 0 {
+            return None;
+        }
+
+        // Several symbols can share an address, prefer one that has a size.
+        let start = self.symbols[end - 1].address;
+        self.symbols[..end].iter().rev()
+            .take_while(|s| s.address == start)
+            .find(|s| s.contains(address))
+    }
+
+    /// Name of the function containing address, or the address in
+    /// hex if it is not covered by any symbol.
+    pub fn name(&self, address: Address) -> String {
+        match self.lookup(address) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("0x{:08x}", address.0),
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_elf() {
+        let table = SymbolTable::from_elf(include_bytes!("../../testdata/lines.elf")).unwrap();
+        assert_eq!(table.symbols(), &[
+            Symbol { name: String::from("step"), address: Address(0x1000), size: 29 },
+            Symbol { name: String::from("loop"), address: Address(0x101d), size: 26 },
+        ]);
+        assert_eq!(table.name(Address(0x1020)), "loop");
+        assert_eq!(table.name(Address(0x1037)), "0x00001037");
+    }
+}
@@ -0,0 +1,107 @@
+//! Converts program flow events from the instruction trace decoders
+//! to trace events. Exception entries and returns are reported both
+//! as a branch and as an exception event. The active exceptions are
+//! tracked, so that a return also resumes the preempted handler.
+
+use std::collections::VecDeque;
+use ::itm::types::ExceptionNumber;
+use ::flow::types::*;
+use ::tpiu::types::TraceSourceID;
+use super::types::*;
+
+/// Iterator adapter from `FlowEvent`s to trace events. Time is taken
+/// from the timestamp events of the flow.
+pub struct FlowEvents<I> {
+    events: I,
+    source: TraceSourceID,
+    core: CoreID,
+    time: u64,
+    /// Active exceptions, the innermost last.
+    exceptions: Vec<ExceptionNumber>,
+    output: VecDeque<TraceEvent>,
+}
+
+impl<I: Iterator<Item=FlowEvent>> FlowEvents<I> {
+    pub fn new(events: I, source: TraceSourceID, core: CoreID) -> FlowEvents<I> {
+        FlowEvents { events, source, core, time: 0, exceptions: Vec::new(), output: VecDeque::new() }
+    }
+
+    fn emit(&mut self, kind: EventKind) {
+        self.output.push_back(TraceEvent { time: self.time, source: self.source, core: self.core, kind });
+    }
+
+    fn convert(&mut self, event: FlowEvent) {
+        match event {
+            FlowEvent::Range(range) => self.emit(EventKind::InstructionRange(range)),
+            FlowEvent::Branch(branch) => {
+                self.emit(EventKind::Branch(branch));
+                match branch.kind {
+                    BranchKind::Exception(exc) => {
+                        self.exceptions.push(exc);
+                        self.emit(EventKind::ExceptionEnter(exc));
+                    },
+                    // Returns from handlers entered before the start
+                    // of the trace are not known.
+                    BranchKind::ExceptionReturn => if let Some(exc) = self.exceptions.pop() {
+                        self.emit(EventKind::ExceptionExit(exc));
+                        if let Some(&preempted) = self.exceptions.last() {
+                            self.emit(EventKind::ExceptionResume(preempted));
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            FlowEvent::Timestamp(time) => self.time = time,
+            FlowEvent::Discontinuity => {
+                self.exceptions.clear();
+                self.emit(EventKind::Overflow);
+            },
+            FlowEvent::Cycles(_) => {},
+        }
+    }
+}
+
+impl<I: Iterator<Item=FlowEvent>> Iterator for FlowEvents<I> {
+    type Item = TraceEvent;
+    fn next(&mut self) -> Option<TraceEvent> {
+        while self.output.is_empty() {
+            let event = self.events.next()?;
+            self.convert(event);
+        }
+        self.output.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::itm::types::Address;
+
+    fn branch(source: u32, destination: u32, kind: BranchKind) -> FlowEvent {
+        FlowEvent::Branch(BranchRecord { source: Address(source), destination: Address(destination), kind, taken: true })
+    }
+
+    #[test]
+    fn test_nested_exceptions() {
+        let events = vec![
+            branch(0x100, 0x200, BranchKind::Exception(ExceptionNumber(15))),
+            FlowEvent::Timestamp(10),
+            branch(0x202, 0x300, BranchKind::Exception(ExceptionNumber(16))),
+            branch(0x304, 0xFFFFFFF9, BranchKind::ExceptionReturn),
+            FlowEvent::Timestamp(20),
+            branch(0x206, 0xFFFFFFF9, BranchKind::ExceptionReturn),
+            branch(0x104, 0xFFFFFFF9, BranchKind::ExceptionReturn),
+        ];
+
+        let kinds: Vec<(u64, EventKind)> = FlowEvents::new(events.into_iter(), TraceSourceID(1), CoreID(0))
+            .filter(|e| !matches!(e.kind, EventKind::Branch(_)))
+            .map(|e| (e.time, e.kind)).collect();
+        assert_eq!(kinds, vec![
+            (0, EventKind::ExceptionEnter(ExceptionNumber(15))),
+            (10, EventKind::ExceptionEnter(ExceptionNumber(16))),
+            (10, EventKind::ExceptionExit(ExceptionNumber(16))),
+            (10, EventKind::ExceptionResume(ExceptionNumber(15))),
+            (20, EventKind::ExceptionExit(ExceptionNumber(15))),
+        ]);
+    }
+}
@@ -0,0 +1,154 @@
+//! Converts timestamped ITM packets to trace events. The data trace
+//! packets of one watchpoint match are combined into one event.
+
+use std::collections::VecDeque;
+use ::itm::types::*;
+use ::itm::timestamp::Timestamper;
+use ::tpiu::types::TraceSourceID;
+use super::types::*;
+
+/// Iterator adapter from `(time, ITMPacket)` pairs, as returned by
+/// `Timestamper`, to trace events.
+pub struct ITMEvents<I> {
+    packets: I,
+    source: TraceSourceID,
+    core: CoreID,
+    page: u32,
+    /// Data trace match waiting for the rest of its packets.
+    access: Option<(u64, DataAccess)>,
+    output: VecDeque<TraceEvent>,
+}
+
+impl<I: Iterator<Item=ITMPacket>> ITMEvents<Timestamper<I>> {
+    /// Reconstructs the timestamps of the packets and converts them.
+    pub fn from_packets(packets: I, source: TraceSourceID, core: CoreID) -> ITMEvents<Timestamper<I>> {
+        ITMEvents::new(Timestamper::new(packets), source, core)
+    }
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> ITMEvents<I> {
+    /// Source and core identify the ITM in the events.
+    pub fn new(packets: I, source: TraceSourceID, core: CoreID) -> ITMEvents<I> {
+        ITMEvents { packets, source, core, page: 0, access: None, output: VecDeque::new() }
+    }
+
+    fn emit(&mut self, time: u64, kind: EventKind) {
+        self.output.push_back(TraceEvent { time, source: self.source, core: self.core, kind });
+    }
+
+    fn flush(&mut self) {
+        if let Some((time, access)) = self.access.take() {
+            self.emit(time, EventKind::DataAccess(access));
+        }
+    }
+
+    /// Returns the pending data trace match for the comparator, or
+    /// starts a new one if the field was already set.
+    fn access(&mut self, time: u64, comparator: ComparatorIndex, has_field: fn(&DataAccess) -> bool)
+              -> &mut DataAccess {
+        if self.access.as_ref().is_some_and(|(_, a)| a.comparator != comparator || has_field(a)) {
+            self.flush();
+        }
+        &mut self.access.get_or_insert((time, DataAccess {
+            comparator, pc: None, offset: None, value: None, write: false
+        })).1
+    }
+
+    fn convert(&mut self, time: u64, packet: ITMPacket) {
+        match packet {
+            ITMPacket::DataTracePC(comp, pc) => self.access(time, comp, |a| a.pc.is_some()).pc = Some(pc),
+            ITMPacket::DataTraceOffset(comp, offset) => {
+                self.access(time, comp, |a| a.offset.is_some()).offset = Some(offset)
+            },
+            ITMPacket::DataTraceReadData(comp, value) |
+            ITMPacket::DataTraceWriteData(comp, value) => {
+                let write = matches!(packet, ITMPacket::DataTraceWriteData(..));
+                let access = self.access(time, comp, |a| a.value.is_some());
+                access.value = Some(value);
+                access.write = write;
+                // Value is the last packet of a match.
+                self.flush();
+            },
+            packet => {
+                self.flush();
+                self.convert_other(time, packet);
+            }
+        }
+    }
+
+    fn convert_other(&mut self, time: u64, packet: ITMPacket) {
+        let kind = match packet {
+            ITMPacket::SoftwarePageNumber(page) => {
+                self.page = page.0;
+                return;
+            },
+            ITMPacket::Software(port, value) => EventKind::SoftwareWrite(SoftwareWrite {
+                channel: self.page + port.0,
+                value: value.to_u32() as u64,
+                bits: value.bit_width(),
+                marker: false,
+            }),
+            ITMPacket::Exception(ExceptionEvent::Enter, exc) => EventKind::ExceptionEnter(exc),
+            ITMPacket::Exception(ExceptionEvent::Exit, exc) => EventKind::ExceptionExit(exc),
+            ITMPacket::Exception(ExceptionEvent::Resume, exc) => EventKind::ExceptionResume(exc),
+            ITMPacket::ProgramCounter(pc) => EventKind::PCSample(Some(pc)),
+            ITMPacket::SleepMode => EventKind::PCSample(None),
+            ITMPacket::EventCounter(flags) => EventKind::CounterWrap(flags),
+            ITMPacket::Overflow => EventKind::Overflow,
+            _ => return,
+        };
+        self.emit(time, kind);
+    }
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> Iterator for ITMEvents<I> {
+    type Item = TraceEvent;
+    fn next(&mut self) -> Option<TraceEvent> {
+        while self.output.is_empty() {
+            match self.packets.next() {
+                Some((time, packet)) => self.convert(time, packet),
+                None => {
+                    self.flush();
+                    break;
+                }
+            }
+        }
+        self.output.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let packets = vec![
+            ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(37)),
+            ITMPacket::SoftwarePageNumber(InstrumentationPort(32)),
+            ITMPacket::Software(InstrumentationPort(3), DataValue::U16(0x1234)),
+            ITMPacket::LocalTimestamp(TimestampSync::Synchronous, LocalTimestampDelta(10)),
+            ITMPacket::DataTracePC(ComparatorIndex(1), Address(0x08000100)),
+            ITMPacket::DataTraceWriteData(ComparatorIndex(1), DataValue::U32(5)),
+            ITMPacket::DataTraceOffset(ComparatorIndex(2), Address(0x10)),
+            ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(37)),
+            ITMPacket::LocalTimestamp(TimestampSync::Synchronous, LocalTimestampDelta(5)),
+        ];
+
+        let kinds: Vec<(u64, EventKind)> = ITMEvents::from_packets(packets.into_iter(), TraceSourceID(1), CoreID(0))
+            .map(|e| (e.time, e.kind)).collect();
+        assert_eq!(kinds, vec![
+            (10, EventKind::ExceptionEnter(ExceptionNumber(37))),
+            (10, EventKind::SoftwareWrite(SoftwareWrite { channel: 35, value: 0x1234, bits: 16, marker: false })),
+            (15, EventKind::DataAccess(DataAccess {
+                comparator: ComparatorIndex(1), pc: Some(Address(0x08000100)), offset: None,
+                value: Some(DataValue::U32(5)), write: true
+            })),
+            (15, EventKind::DataAccess(DataAccess {
+                comparator: ComparatorIndex(2), pc: None, offset: Some(Address(0x10)),
+                value: None, write: false
+            })),
+            (15, EventKind::ExceptionExit(ExceptionNumber(37))),
+        ]);
+    }
+}
@@ -0,0 +1,9 @@
+//! Protocol independent trace events. The adapters in the submodules
+//! turn the decoded packets of each protocol into `TraceEvent`s, so
+//! that timelines, exports and profiling are written once for all
+//! trace sources.
+
+pub mod types;
+pub mod itm;
+pub mod stm;
+pub mod flow;
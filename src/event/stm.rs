@@ -0,0 +1,95 @@
+//! Converts STM packets to trace events. The STM master becomes the
+//! core of the event, and packets without a timestamp get the time
+//! of the previous timestamp.
+
+use ::stm::types::*;
+use ::tpiu::types::TraceSourceID;
+use super::types::*;
+
+/// Iterator adapter from STM packets to trace events.
+pub struct STMEvents<I> {
+    packets: I,
+    source: TraceSourceID,
+    time: u64,
+}
+
+impl<I: Iterator<Item=STMPacket>> STMEvents<I> {
+    pub fn new(packets: I, source: TraceSourceID) -> STMEvents<I> {
+        STMEvents { packets, source, time: 0 }
+    }
+
+    fn update_time(&mut self, timestamp: Option<u64>) -> u64 {
+        if let Some(timestamp) = timestamp {
+            self.time = timestamp;
+        }
+        self.time
+    }
+
+    fn convert(&mut self, packet: STMPacket) -> Option<TraceEvent> {
+        let (timestamp, master, kind) = match packet {
+            STMPacket::Data { master, channel, value, marker, timestamp } => {
+                (timestamp, master, EventKind::SoftwareWrite(SoftwareWrite {
+                    channel: channel.0 as u32,
+                    value: value.to_u64(),
+                    bits: value.bit_width(),
+                    marker,
+                }))
+            },
+            STMPacket::Flag { master, channel, timestamp } => {
+                (timestamp, master, EventKind::Marker { channel: channel.0 as u32 })
+            },
+            STMPacket::Trigger { master, channel, value, timestamp } => {
+                (timestamp, master, EventKind::Trigger { channel: channel.0 as u32, value: value as u32 })
+            },
+            STMPacket::MasterError(_) | STMPacket::GlobalError(_) => (None, MasterID(0), EventKind::Overflow),
+            STMPacket::NullTimestamp(timestamp) => {
+                self.update_time(Some(timestamp));
+                return None;
+            },
+            _ => return None,
+        };
+        let time = self.update_time(timestamp);
+        Some(TraceEvent { time, source: self.source, core: CoreID(master.0 as u32), kind })
+    }
+}
+
+impl<I: Iterator<Item=STMPacket>> Iterator for STMEvents<I> {
+    type Item = TraceEvent;
+    fn next(&mut self) -> Option<TraceEvent> {
+        loop {
+            let packet = self.packets.next()?;
+            if let Some(event) = self.convert(packet) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let packets = vec![
+            STMPacket::Async,
+            STMPacket::Master(MasterID(2)),
+            STMPacket::Data { master: MasterID(2), channel: ChannelID(5), value: STMDataValue::D16(0x1234),
+                              marker: true, timestamp: Some(100) },
+            STMPacket::Flag { master: MasterID(2), channel: ChannelID(6), timestamp: None },
+            STMPacket::NullTimestamp(150),
+            STMPacket::MasterError(1),
+        ];
+
+        let events: Vec<TraceEvent> = STMEvents::new(packets.into_iter(), TraceSourceID(2)).collect();
+        assert_eq!(events, vec![
+            TraceEvent { time: 100, source: TraceSourceID(2), core: CoreID(2),
+                         kind: EventKind::SoftwareWrite(SoftwareWrite {
+                             channel: 5, value: 0x1234, bits: 16, marker: true }) },
+            TraceEvent { time: 100, source: TraceSourceID(2), core: CoreID(2),
+                         kind: EventKind::Marker { channel: 6 } },
+            TraceEvent { time: 150, source: TraceSourceID(2), core: CoreID(0),
+                         kind: EventKind::Overflow },
+        ]);
+    }
+}
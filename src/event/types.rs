@@ -0,0 +1,98 @@
+//! Trace event types shared by all protocols.
+
+use ::itm::types::{Address, ExceptionNumber, ComparatorIndex, DataValue, EventCounterFlags};
+use ::tpiu::types::TraceSourceID;
+use ::flow::types::{InstructionRange, BranchRecord};
+
+/// Single event from any trace source.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// Absolute time in ticks of the timestamp clock of the source.
+    pub time: u64,
+
+    /// TPIU trace source ID, or 0 if the trace was not multiplexed.
+    pub source: TraceSourceID,
+
+    /// Processor or bus master that caused the event.
+    pub core: CoreID,
+
+    pub kind: EventKind,
+}
+
+/// Represents a processor core or other bus master.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CoreID(pub u32);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EventKind {
+    /// Exception handler started from beginning.
+    ExceptionEnter(ExceptionNumber),
+
+    /// Exception handler finished.
+    ExceptionExit(ExceptionNumber),
+
+    /// Exception handler continued after a higher priority
+    /// exception returned.
+    ExceptionResume(ExceptionNumber),
+
+    /// Value written by software to a stimulus port or channel.
+    SoftwareWrite(SoftwareWrite),
+
+    /// Flag written to a stimulus channel, without data.
+    Marker { channel: u32 },
+
+    /// Hardware or software trigger.
+    Trigger { channel: u32, value: u32 },
+
+    /// Periodic program counter sample, None when the processor
+    /// was sleeping.
+    PCSample(Option<Address>),
+
+    /// Memory access matched by a watchpoint comparator.
+    DataAccess(DataAccess),
+
+    /// Sequentially executed instructions.
+    InstructionRange(InstructionRange),
+
+    /// Taken or not taken branch.
+    Branch(BranchRecord),
+
+    /// Performance counters that wrapped around.
+    CounterWrap(EventCounterFlags),
+
+    /// Trace data was lost before this point.
+    Overflow,
+}
+
+/// Value written to a software instrumentation channel.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SoftwareWrite {
+    /// ITM stimulus port, including the page, or STM channel.
+    pub channel: u32,
+    pub value: u64,
+
+    /// Size of the write in bits.
+    pub bits: u8,
+
+    /// Write was marked as significant, e.g. start of a message.
+    pub marker: bool,
+}
+
+/// Watchpoint match from the data trace. The packets enabled for the
+/// comparator determine which of the fields are known.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct DataAccess {
+    pub comparator: ComparatorIndex,
+
+    /// Address of the instruction that made the access.
+    pub pc: Option<Address>,
+
+    /// Offset of the accessed address from the comparator address.
+    pub offset: Option<Address>,
+
+    /// Value read or written.
+    pub value: Option<DataValue>,
+
+    /// True for writes, valid only when the value is known.
+    pub write: bool,
+}
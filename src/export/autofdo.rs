@@ -0,0 +1,235 @@
+//! Generates profiles for profile guided optimization from
+//! instruction trace: the range and branch count text format read
+//! by `create_llvm_prof --profiler=text`, and the LLVM sample
+//! profile text format read by `clang -fprofile-sample-use`.
+
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+use ::itm::types::Address;
+use ::elf::symbols::SymbolTable;
+use ::elf::image::Image;
+use ::elf::lines::LineTable;
+use ::flow::types::*;
+
+/// Execution counts of instruction ranges and taken branches.
+#[derive(Debug, Clone, Default)]
+pub struct BranchProfile {
+    /// Ranges from first to last instruction address, inclusive.
+    ranges: BTreeMap<(Address, Address), u64>,
+    /// Taken branches from source to destination address.
+    branches: BTreeMap<(Address, Address), u64>,
+    /// Branch sources that are function calls.
+    calls: BTreeMap<(Address, Address), u64>,
+}
+
+/// Samples of one function in the LLVM sample profile.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FunctionSamples {
+    pub total: u64,
+    pub head: u64,
+    /// Samples per line offset from the start of the function.
+    pub lines: BTreeMap<u32, u64>,
+    /// Call target counts per line offset.
+    pub calls: BTreeMap<u32, BTreeMap<String, u64>>,
+}
+
+impl BranchProfile {
+    pub fn new() -> BranchProfile {
+        BranchProfile::default()
+    }
+
+    /// Adds a program flow event. The image is used for finding
+    /// the last instruction of each range.
+    pub fn add(&mut self, image: &Image, event: &FlowEvent) {
+        match *event {
+            FlowEvent::Range(ref range) => {
+                let last = image.instructions(range.start, range.end).last().cloned()
+                    .unwrap_or(Address(range.end.0.saturating_sub(2).max(range.start.0)));
+                *self.ranges.entry((range.start, last)).or_insert(0) += 1;
+            },
+            FlowEvent::Branch(ref branch) if branch.taken => {
+                let key = (branch.source, branch.destination);
+                *self.branches.entry(key).or_insert(0) += 1;
+                if branch.kind == BranchKind::Call {
+                    *self.calls.entry(key).or_insert(0) += 1;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Adds the counts from another trace.
+    pub fn merge(&mut self, other: &BranchProfile) {
+        for (key, count) in &other.ranges {
+            *self.ranges.entry(*key).or_insert(0) += *count;
+        }
+        for (key, count) in &other.branches {
+            *self.branches.entry(*key).or_insert(0) += *count;
+        }
+        for (key, count) in &other.calls {
+            *self.calls.entry(*key).or_insert(0) += *count;
+        }
+    }
+
+    /// Writes the range and branch counts in the text format read by
+    /// `create_llvm_prof`, which does the symbolization itself.
+    pub fn write_autofdo_text<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        writeln!(output, "{}", self.ranges.len())?;
+        for (&(start, end), count) in &self.ranges {
+            writeln!(output, "{:x}-{:x}:{}", start.0, end.0, count)?;
+        }
+
+        // Individual address samples are not used.
+        writeln!(output, "0")?;
+
+        writeln!(output, "{}", self.branches.len())?;
+        for (&(source, destination), count) in &self.branches {
+            writeln!(output, "{:x}->{:x}:{}", source.0, destination.0, count)?;
+        }
+        Ok(())
+    }
+
+    /// Aggregates the counts per function and source line offset.
+    /// Lines are relative to the line of the function entry address.
+    /// Instructions from other files, i.e. inlined code, are skipped
+    /// as the line table alone does not give their inlining context.
+    pub fn function_samples(&self, image: &Image, symbols: &SymbolTable,
+                            lines: &LineTable) -> BTreeMap<String, FunctionSamples> {
+        let mut result: BTreeMap<String, FunctionSamples> = BTreeMap::new();
+
+        // Line offset of address within its function.
+        let offset = |address: Address| -> Option<(String, u32)> {
+            let symbol = symbols.lookup(address)?;
+            let (function_file, function_line) = lines.lookup(symbol.address)?;
+            let (file, line) = lines.lookup(address)?;
+            if file == function_file && line >= function_line {
+                Some((symbol.name.clone(), line - function_line))
+            } else {
+                None
+            }
+        };
+
+        let mut instructions: BTreeMap<Address, u64> = BTreeMap::new();
+        for (&(start, end), count) in &self.ranges {
+            for address in image.instructions(start, Address(end.0 + 1)) {
+                *instructions.entry(address).or_insert(0) += *count;
+            }
+        }
+
+        for (address, &count) in &instructions {
+            if let Some((function, line)) = offset(*address) {
+                let samples = result.entry(function).or_default().lines.entry(line).or_insert(0);
+                *samples = (*samples).max(count);
+            }
+        }
+
+        for (&(source, destination), &count) in &self.calls {
+            let target = match symbols.lookup(destination) {
+                Some(symbol) => symbol,
+                None => continue
+            };
+            if target.address == destination {
+                result.entry(target.name.clone()).or_default().head += count;
+            }
+            if let Some((function, line)) = offset(source) {
+                *result.entry(function).or_default().calls.entry(line).or_default()
+                    .entry(target.name.clone()).or_insert(0) += count;
+            }
+        }
+
+        for samples in result.values_mut() {
+            samples.total = samples.lines.values().sum();
+        }
+        result
+    }
+
+    /// Writes the profile in the LLVM sample profile text format.
+    pub fn write_llvm_profile<W: Write>(&self, image: &Image, symbols: &SymbolTable,
+                                        lines: &LineTable, output: &mut W) -> Result<(), Error> {
+        for (function, samples) in self.function_samples(image, symbols, lines) {
+            if samples.total == 0 {
+                continue;
+            }
+
+            writeln!(output, "{}:{}:{}", function, samples.total, samples.head)?;
+            for (line, count) in &samples.lines {
+                write!(output, " {}: {}", line, count)?;
+                if let Some(targets) = samples.calls.get(line) {
+                    for (target, calls) in targets {
+                        write!(output, " {}:{}", target, calls)?;
+                    }
+                }
+                writeln!(output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autofdo_text() {
+        let mut image = Image::new();
+        image.add_region(Address(0x100), vec![0x00, 0xbf, 0x00, 0xf0, 0x00, 0xf8]);
+        let mut profile = BranchProfile::new();
+        for _ in 0..2 {
+            profile.add(&image, &FlowEvent::Range(InstructionRange {
+                start: Address(0x100), end: Address(0x106), instructions: 2
+            }));
+            profile.add(&image, &FlowEvent::Branch(BranchRecord {
+                source: Address(0x102), destination: Address(0x200), kind: BranchKind::Call, taken: true
+            }));
+        }
+
+        let mut output = Vec::new();
+        profile.write_autofdo_text(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n100-102:2\n0\n1\n102->200:2\n");
+    }
+
+    #[test]
+    fn test_llvm_profile() {
+        // Thumb NOPs with a BL at 0x1010, at the addresses of the
+        // functions in testdata/lines.elf.
+        let mut code = Vec::new();
+        for _ in 0..28 {
+            code.extend(&[0x00, 0xbf]);
+        }
+        code[0x10..0x14].copy_from_slice(&[0x00, 0xf0, 0x00, 0xf8]);
+        let mut image = Image::new();
+        image.add_region(Address(0x1000), code);
+        let elf = include_bytes!("../../testdata/lines.elf");
+        let symbols = SymbolTable::from_elf(elf).unwrap();
+        let lines = LineTable::from_elf(elf).unwrap();
+
+        let mut profile = BranchProfile::new();
+        for _ in 0..2 {
+            profile.add(&image, &FlowEvent::Range(InstructionRange {
+                start: Address(0x1000), end: Address(0x1014), instructions: 9
+            }));
+            profile.add(&image, &FlowEvent::Branch(BranchRecord {
+                source: Address(0x1010), destination: Address(0x101d), kind: BranchKind::Call, taken: true
+            }));
+        }
+        profile.add(&image, &FlowEvent::Range(InstructionRange {
+            start: Address(0x101e), end: Address(0x1022), instructions: 2
+        }));
+
+        let samples = profile.function_samples(&image, &symbols, &lines);
+        assert_eq!(samples["step"].lines, vec![(0, 2), (1, 2), (2, 2)].into_iter().collect());
+        assert_eq!(samples["loop"].head, 2);
+
+        let mut output = Vec::new();
+        profile.write_llvm_profile(&image, &symbols, &lines, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+loop:1:2
+ 0: 1
+step:6:0
+ 0: 2
+ 1: 2
+ 2: 2 loop:2
+");
+    }
+}
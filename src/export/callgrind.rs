@@ -0,0 +1,348 @@
+//! Writes instruction trace execution counts in the callgrind
+//! format, for viewing in KCachegrind.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Write, Error};
+use ::itm::types::Address;
+use ::elf::symbols::SymbolTable;
+use ::elf::image::Image;
+use ::elf::lines::LineTable;
+use ::flow::types::*;
+use ::flow::callstack::{CallStack, Frame, MAX_DEPTH};
+
+/// Executed instruction and cycle counts.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Cost {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl Cost {
+    fn add(&mut self, other: Cost) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+
+    fn since(&self, start: Cost) -> Cost {
+        Cost {
+            instructions: self.instructions - start.instructions,
+            cycles: self.cycles - start.cycles,
+        }
+    }
+}
+
+/// Call edge totals from one call site to one function.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CallCost {
+    pub count: u64,
+    pub inclusive: Cost,
+}
+
+/// Call that has not returned yet.
+struct ActiveCall {
+    site: Address,
+    target: Address,
+    start: Cost,
+}
+
+/// Aggregates program flow into per-instruction costs and call edges.
+pub struct CallgrindProfiler<'a> {
+    image: &'a Image,
+    callstack: CallStack,
+    active: Vec<ActiveCall>,
+    costs: BTreeMap<Address, Cost>,
+    calls: BTreeMap<(Address, Address), CallCost>,
+    total: Cost,
+    last_instruction: Option<Address>,
+    last_timestamp: Option<u64>,
+    has_cycles: bool,
+}
+
+impl<'a> CallgrindProfiler<'a> {
+    /// The image is used for finding the instruction boundaries
+    /// inside the executed ranges.
+    pub fn new(image: &'a Image) -> CallgrindProfiler<'a> {
+        CallgrindProfiler {
+            image,
+            callstack: CallStack::new(),
+            active: Vec::new(),
+            costs: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            total: Cost::default(),
+            last_instruction: None,
+            last_timestamp: None,
+            has_cycles: false,
+        }
+    }
+
+    fn add_cost(&mut self, address: Address, cost: Cost) {
+        self.costs.entry(address).or_default().add(cost);
+        self.total.add(cost);
+    }
+
+    /// Cycle counts are given for a group of instructions, and are
+    /// attributed to the last instruction of the group.
+    fn add_cycles(&mut self, cycles: u64) {
+        self.has_cycles = true;
+        if let Some(address) = self.last_instruction {
+            self.add_cost(address, Cost { instructions: 0, cycles });
+        }
+    }
+
+    fn range(&mut self, range: &InstructionRange) {
+        let addresses = self.image.instructions(range.start, range.end);
+        if addresses.is_empty() {
+            // Code is not in the image, count the whole range on its start.
+            let cost = Cost { instructions: range.instructions as u64, cycles: 0 };
+            self.add_cost(range.start, cost);
+            self.last_instruction = Some(range.start);
+            return;
+        }
+
+        for &address in &addresses {
+            self.add_cost(address, Cost { instructions: 1, cycles: 0 });
+        }
+        self.last_instruction = addresses.last().cloned();
+    }
+
+    fn branch(&mut self, branch: &BranchRecord) {
+        // A call on a full stack drops the oldest frame, and the
+        // matching call can then never be completed.
+        let push = branch.taken && matches!(branch.kind, BranchKind::Call | BranchKind::Exception(_));
+        if push && self.callstack.frames().len() >= MAX_DEPTH && self.active.len() >= MAX_DEPTH {
+            self.active.remove(0);
+        }
+        self.callstack.branch(branch);
+
+        let depth = self.callstack.frames().len();
+        while self.active.len() > depth {
+            let call = self.active.pop().unwrap();
+            let edge = self.calls.entry((call.site, call.target)).or_default();
+            edge.count += 1;
+            edge.inclusive.add(self.total.since(call.start));
+        }
+
+        if self.active.len() < depth {
+            let site = match self.callstack.frames()[depth - 1] {
+                Frame::Call(site) | Frame::Exception(_, site) => site,
+            };
+            self.active.push(ActiveCall { site, target: branch.destination, start: self.total });
+        }
+    }
+
+    pub fn add(&mut self, event: &FlowEvent) {
+        match *event {
+            FlowEvent::Range(ref range) => self.range(range),
+            FlowEvent::Branch(ref branch) => self.branch(branch),
+            FlowEvent::Cycles(cycles) => self.add_cycles(cycles as u64),
+            FlowEvent::Timestamp(timestamp) => {
+                if let Some(previous) = self.last_timestamp {
+                    self.add_cycles(timestamp.saturating_sub(previous));
+                }
+                self.last_timestamp = Some(timestamp);
+            },
+            FlowEvent::Discontinuity => {
+                self.callstack.clear();
+                self.active.clear();
+                self.last_instruction = None;
+                self.last_timestamp = None;
+            }
+        }
+    }
+
+    pub fn costs(&self) -> &BTreeMap<Address, Cost> {
+        &self.costs
+    }
+
+    /// Call edges, indexed by call site and target address.
+    pub fn calls(&self) -> &BTreeMap<(Address, Address), CallCost> {
+        &self.calls
+    }
+
+    /// Writes the profile, using the symbols to group instructions to
+    /// functions and the line table for source positions.
+    pub fn write_to<W: Write>(&self, output: &mut W, symbols: &SymbolTable,
+                              lines: &LineTable) -> Result<(), Error> {
+        let mut names = NameCompressor::default();
+        let location = |address: Address| -> (String, u32) {
+            match lines.lookup(address) {
+                Some((file, line)) => (String::from(file), line),
+                None => (String::from("???"), 0)
+            }
+        };
+        let format_cost = |cost: &Cost| -> String {
+            if self.has_cycles {
+                format!("{} {}", cost.instructions, cost.cycles)
+            } else {
+                format!("{}", cost.instructions)
+            }
+        };
+
+        writeln!(output, "# callgrind format")?;
+        writeln!(output, "version: 1")?;
+        writeln!(output, "creator: arm_coresight_decoder")?;
+        writeln!(output, "positions: instr line")?;
+        writeln!(output, "events: Instr{}", if self.has_cycles {" Cycles"} else {""})?;
+        writeln!(output, "totals: {}", format_cost(&self.total))?;
+
+        // Group instructions and call sites by function.
+        let mut functions: BTreeMap<String, Vec<Address>> = BTreeMap::new();
+        for address in self.costs.keys().chain(self.calls.keys().map(|k| &k.0)) {
+            functions.entry(symbols.name(*address)).or_default().push(*address);
+        }
+
+        for (function, mut addresses) in functions {
+            addresses.sort();
+            addresses.dedup();
+
+            let function_file = location(addresses[0]).0;
+            writeln!(output)?;
+            writeln!(output, "fl={}", names.get("fl", &function_file))?;
+            writeln!(output, "fn={}", names.get("fn", &function))?;
+
+            let mut current_file = function_file.clone();
+            for address in addresses {
+                let (file, line) = location(address);
+                if file != current_file {
+                    let key = if file == function_file {"fe"} else {"fi"};
+                    writeln!(output, "{}={}", key, names.get("fl", &file))?;
+                    current_file = file;
+                }
+
+                if let Some(cost) = self.costs.get(&address) {
+                    writeln!(output, "0x{:08x} {} {}", address.0, line, format_cost(cost))?;
+                }
+
+                for (&(_, target), call) in self.calls.range((address, Address(0))..=(address, Address(u32::MAX))) {
+                    let (target_file, target_line) = location(target);
+                    writeln!(output, "cfi={}", names.get("fl", &target_file))?;
+                    writeln!(output, "cfn={}", names.get("fn", &symbols.name(target)))?;
+                    writeln!(output, "calls={} 0x{:08x} {}", call.count, target.0, target_line)?;
+                    writeln!(output, "0x{:08x} {} {}", address.0, line, format_cost(&call.inclusive))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Assigns numeric ids to file and function names, so that each
+/// name is written out in full only once.
+#[derive(Default)]
+struct NameCompressor {
+    ids: HashMap<(&'static str, String), usize>,
+}
+
+impl NameCompressor {
+    fn get(&mut self, kind: &'static str, name: &str) -> String {
+        let key = (kind, String::from(name));
+        if let Some(id) = self.ids.get(&key) {
+            return format!("({})", id);
+        }
+
+        let id = self.ids.len() + 1;
+        self.ids.insert(key, id);
+        format!("({}) {}", id, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elf::symbols::Symbol;
+
+    #[test]
+    fn test_call_costs() {
+        // main: two 16-bit instructions and a 32-bit BL,
+        // foo: two 16-bit instructions.
+        let mut image = Image::new();
+        image.add_region(Address(0x100), vec![0x00, 0xbf, 0x00, 0xbf, 0x00, 0xf0, 0x00, 0xf8, 0x00, 0xbf]);
+        image.add_region(Address(0x200), vec![0x00, 0xbf, 0x70, 0x47]);
+        let symbols = SymbolTable::new(vec![
+            Symbol { name: String::from("main"), address: Address(0x100), size: 0x10 },
+            Symbol { name: String::from("foo"), address: Address(0x200), size: 0x4 },
+        ]);
+
+        let mut profiler = CallgrindProfiler::new(&image);
+        profiler.add(&FlowEvent::Range(InstructionRange { start: Address(0x100), end: Address(0x108), instructions: 3 }));
+        profiler.add(&FlowEvent::Cycles(5));
+        profiler.add(&FlowEvent::Branch(BranchRecord {
+            source: Address(0x104), destination: Address(0x200), kind: BranchKind::Call, taken: true
+        }));
+        profiler.add(&FlowEvent::Range(InstructionRange { start: Address(0x200), end: Address(0x204), instructions: 2 }));
+        profiler.add(&FlowEvent::Cycles(3));
+        profiler.add(&FlowEvent::Branch(BranchRecord {
+            source: Address(0x202), destination: Address(0x108), kind: BranchKind::Return, taken: true
+        }));
+        profiler.add(&FlowEvent::Range(InstructionRange { start: Address(0x108), end: Address(0x10a), instructions: 1 }));
+
+        assert_eq!(profiler.costs()[&Address(0x104)], Cost { instructions: 1, cycles: 5 });
+        assert_eq!(profiler.costs()[&Address(0x202)], Cost { instructions: 1, cycles: 3 });
+        assert_eq!(profiler.calls()[&(Address(0x104), Address(0x200))],
+                   CallCost { count: 1, inclusive: Cost { instructions: 2, cycles: 3 } });
+
+        let mut output = Vec::new();
+        profiler.write_to(&mut output, &symbols, &LineTable::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "\
+# callgrind format
+version: 1
+creator: arm_coresight_decoder
+positions: instr line
+events: Instr Cycles
+totals: 6 8
+
+fl=(1) ???
+fn=(2) foo
+0x00000200 0 1 0
+0x00000202 0 1 3
+
+fl=(1)
+fn=(3) main
+0x00000100 0 1 0
+0x00000102 0 1 0
+0x00000104 0 1 5
+cfi=(1)
+cfn=(2)
+calls=1 0x00000200 0
+0x00000104 0 2 3
+0x00000108 0 1 0
+");
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // main calls foo, which recurses past the stack depth limit
+        // running one instruction at each level, then returns.
+        let mut image = Image::new();
+        image.add_region(Address(0x100), vec![0x00, 0xf0, 0x00, 0xf8]);
+        image.add_region(Address(0x200), vec![0x00, 0xf0, 0x00, 0xf8, 0x70, 0x47]);
+        let depth = MAX_DEPTH + 10;
+
+        let mut profiler = CallgrindProfiler::new(&image);
+        profiler.add(&FlowEvent::Branch(BranchRecord {
+            source: Address(0x100), destination: Address(0x200), kind: BranchKind::Call, taken: true
+        }));
+        for _ in 0..depth {
+            profiler.add(&FlowEvent::Range(InstructionRange { start: Address(0x200), end: Address(0x204), instructions: 1 }));
+            profiler.add(&FlowEvent::Branch(BranchRecord {
+                source: Address(0x200), destination: Address(0x200), kind: BranchKind::Call, taken: true
+            }));
+        }
+        profiler.add(&FlowEvent::Range(InstructionRange { start: Address(0x204), end: Address(0x206), instructions: 1 }));
+        for _ in 0..depth + 1 {
+            profiler.add(&FlowEvent::Branch(BranchRecord {
+                source: Address(0x204), destination: Address(0x204), kind: BranchKind::Return, taken: true
+            }));
+        }
+
+        // Only the innermost calls are known, the call from main and
+        // the outer recursion levels were dropped with the stack frames.
+        assert!(!profiler.calls().contains_key(&(Address(0x100), Address(0x200))));
+        let recursion = profiler.calls()[&(Address(0x200), Address(0x200))];
+        assert_eq!(recursion.count, MAX_DEPTH as u64);
+        let levels = MAX_DEPTH as u64;
+        assert_eq!(recursion.inclusive.instructions, levels * (levels + 1) / 2);
+    }
+}
@@ -0,0 +1,357 @@
+//! Collects code coverage from instruction trace and writes it
+//! as lcov tracefile or Cobertura XML.
+
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+use ::itm::types::Address;
+use ::elf::symbols::SymbolTable;
+use ::elf::image::Image;
+use ::elf::lines::LineTable;
+use ::flow::types::*;
+
+/// Outcome counts for a single branch instruction.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Execution counts per instruction address.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    executed: BTreeMap<Address, u64>,
+    branches: BTreeMap<Address, BranchCount>,
+}
+
+/// Coverage of one source line.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LineCoverage {
+    pub hits: u64,
+    pub branches: Vec<BranchCount>,
+}
+
+/// Line coverage grouped by source file and line number.
+pub type SourceCoverage = BTreeMap<String, BTreeMap<u32, LineCoverage>>;
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage { executed: BTreeMap::new(), branches: BTreeMap::new() }
+    }
+
+    /// Adds a program flow event. The image is used for finding the
+    /// instruction boundaries inside the executed ranges.
+    pub fn add(&mut self, image: &Image, event: &FlowEvent) {
+        match *event {
+            FlowEvent::Range(ref range) => {
+                for address in image.instructions(range.start, range.end) {
+                    *self.executed.entry(address).or_insert(0) += 1;
+                }
+            },
+            FlowEvent::Branch(ref branch) => {
+                // Unconditional branches are always taken, so they are
+                // not interesting for branch coverage.
+                let conditional = image.is_conditional_branch(branch.source).unwrap_or(!branch.taken);
+                if conditional && branch.kind == BranchKind::Jump {
+                    let count = self.branches.entry(branch.source).or_default();
+                    if branch.taken {
+                        count.taken += 1;
+                    } else {
+                        count.not_taken += 1;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Adds the counts from another trace.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (address, count) in &other.executed {
+            *self.executed.entry(*address).or_insert(0) += *count;
+        }
+        for (address, count) in &other.branches {
+            let entry = self.branches.entry(*address).or_default();
+            entry.taken += count.taken;
+            entry.not_taken += count.not_taken;
+        }
+    }
+
+    pub fn executed(&self) -> &BTreeMap<Address, u64> {
+        &self.executed
+    }
+
+    pub fn branches(&self) -> &BTreeMap<Address, BranchCount> {
+        &self.branches
+    }
+
+    /// Maps the instruction counts to source lines. Every line in
+    /// the line table is included, with zero hits if none of its
+    /// instructions were executed. Line hits are the count of the
+    /// most executed instruction on the line.
+    pub fn by_line(&self, lines: &LineTable) -> SourceCoverage {
+        let mut result = SourceCoverage::new();
+        for (_, file, line) in lines.entries() {
+            if line != 0 {
+                result.entry(String::from(file)).or_default().entry(line).or_default();
+            }
+        }
+
+        for (address, &count) in &self.executed {
+            if let Some((file, line)) = lines.lookup(*address) {
+                let entry = result.entry(String::from(file)).or_default().entry(line).or_default();
+                entry.hits = entry.hits.max(count);
+            }
+        }
+
+        for (address, &count) in &self.branches {
+            if let Some((file, line)) = lines.lookup(*address) {
+                result.entry(String::from(file)).or_default().entry(line).or_default()
+                    .branches.push(count);
+            }
+        }
+
+        result
+    }
+}
+
+fn branch_totals(line: &LineCoverage) -> (usize, usize) {
+    let found = line.branches.len() * 2;
+    let hit = line.branches.iter()
+        .map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize).sum();
+    (found, hit)
+}
+
+/// Writes lcov tracefile with function, line and branch coverage.
+pub fn write_lcov<W: Write>(coverage: &Coverage, lines: &LineTable, symbols: &SymbolTable,
+                            test_name: &str, output: &mut W) -> Result<(), Error> {
+    let by_line = coverage.by_line(lines);
+
+    // Function entry counts, grouped by the file the function starts in.
+    let mut functions: BTreeMap<&str, Vec<(u32, &str, u64)>> = BTreeMap::new();
+    for symbol in symbols.symbols() {
+        if let Some((file, line)) = lines.lookup(symbol.address) {
+            let hits = coverage.executed.get(&symbol.address).cloned().unwrap_or(0);
+            functions.entry(file).or_default().push((line, &symbol.name, hits));
+        }
+    }
+
+    for (file, file_lines) in &by_line {
+        writeln!(output, "TN:{}", test_name)?;
+        writeln!(output, "SF:{}", file)?;
+
+        let file_functions = functions.get(&file[..]).cloned().unwrap_or_default();
+        for &(line, name, _) in &file_functions {
+            writeln!(output, "FN:{},{}", line, name)?;
+        }
+        for &(_, name, hits) in &file_functions {
+            writeln!(output, "FNDA:{},{}", hits, name)?;
+        }
+        writeln!(output, "FNF:{}", file_functions.len())?;
+        writeln!(output, "FNH:{}", file_functions.iter().filter(|f| f.2 > 0).count())?;
+
+        let (mut branches_found, mut branches_hit) = (0, 0);
+        for (line, coverage) in file_lines {
+            for (block, branch) in coverage.branches.iter().enumerate() {
+                writeln!(output, "BRDA:{},{},0,{}", line, block, branch.taken)?;
+                writeln!(output, "BRDA:{},{},1,{}", line, block, branch.not_taken)?;
+            }
+            let (found, hit) = branch_totals(coverage);
+            branches_found += found;
+            branches_hit += hit;
+        }
+        writeln!(output, "BRF:{}", branches_found)?;
+        writeln!(output, "BRH:{}", branches_hit)?;
+
+        for (line, coverage) in file_lines {
+            writeln!(output, "DA:{},{}", line, coverage.hits)?;
+        }
+        writeln!(output, "LF:{}", file_lines.len())?;
+        writeln!(output, "LH:{}", file_lines.values().filter(|l| l.hits > 0).count())?;
+        writeln!(output, "end_of_record")?;
+    }
+
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn rate(hit: usize, found: usize) -> String {
+    if found == 0 {
+        String::from("1")
+    } else {
+        format!("{:.4}", hit as f64 / found as f64)
+    }
+}
+
+/// Totals of lines and branches, found and hit.
+fn totals<'a, I: Iterator<Item=&'a LineCoverage>>(lines: I) -> (usize, usize, usize, usize) {
+    lines.fold((0, 0, 0, 0), |(lf, lh, bf, bh), line| {
+        let (found, hit) = branch_totals(line);
+        (lf + 1, lh + (line.hits > 0) as usize, bf + found, bh + hit)
+    })
+}
+
+/// Writes Cobertura XML coverage report, with one class per source file.
+/// The timestamp of the report is given in seconds since the Unix epoch.
+pub fn write_cobertura<W: Write>(coverage: &Coverage, lines: &LineTable, timestamp: u64,
+                                 output: &mut W) -> Result<(), Error> {
+    let by_line = coverage.by_line(lines);
+    let (lf, lh, bf, bh) = totals(by_line.values().flat_map(|l| l.values()));
+
+    writeln!(output, "<?xml version=\"1.0\" ?>")?;
+    writeln!(output, "<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">")?;
+    writeln!(output, "<coverage line-rate=\"{}\" branch-rate=\"{}\" lines-covered=\"{}\" lines-valid=\"{}\" \
+                      branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"{}\" timestamp=\"{}\">",
+             rate(lh, lf), rate(bh, bf), lh, lf, bh, bf, env!("CARGO_PKG_VERSION"), timestamp)?;
+    writeln!(output, "  <sources><source>.</source></sources>")?;
+    writeln!(output, "  <packages>")?;
+    writeln!(output, "    <package name=\"firmware\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">",
+             rate(lh, lf), rate(bh, bf))?;
+    writeln!(output, "      <classes>")?;
+
+    for (file, file_lines) in &by_line {
+        let (lf, lh, bf, bh) = totals(file_lines.values());
+        let name = file.rsplit('/').next().unwrap_or(file);
+        writeln!(output, "        <class name=\"{}\" filename=\"{}\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">",
+                 escape(name), escape(file), rate(lh, lf), rate(bh, bf))?;
+        writeln!(output, "          <methods/>")?;
+        writeln!(output, "          <lines>")?;
+        for (line, coverage) in file_lines {
+            let (found, hit) = branch_totals(coverage);
+            if let Some(percent) = (hit * 100).checked_div(found) {
+                writeln!(output, "            <line number=\"{}\" hits=\"{}\" branch=\"true\" \
+                                  condition-coverage=\"{}% ({}/{})\"/>",
+                         line, coverage.hits, percent, hit, found)?;
+            } else {
+                writeln!(output, "            <line number=\"{}\" hits=\"{}\" branch=\"false\"/>",
+                         line, coverage.hits)?;
+            }
+        }
+        writeln!(output, "          </lines>")?;
+        writeln!(output, "        </class>")?;
+    }
+
+    writeln!(output, "      </classes>")?;
+    writeln!(output, "    </package>")?;
+    writeln!(output, "  </packages>")?;
+    writeln!(output, "</coverage>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        // NOP, BEQ, NOP, BL
+        let mut image = Image::new();
+        image.add_region(Address(0x100), vec![0x00, 0xbf, 0x01, 0xd0, 0x00, 0xbf, 0x00, 0xf0, 0x00, 0xf8]);
+        image
+    }
+
+    fn trace(taken: bool) -> Coverage {
+        let image = image();
+        let mut coverage = Coverage::new();
+        coverage.add(&image, &FlowEvent::Range(InstructionRange {
+            start: Address(0x100), end: Address(0x104), instructions: 2
+        }));
+        coverage.add(&image, &FlowEvent::Branch(BranchRecord {
+            source: Address(0x102), destination: Address(0x108), kind: BranchKind::Jump, taken
+        }));
+        coverage.add(&image, &FlowEvent::Branch(BranchRecord {
+            source: Address(0x106), destination: Address(0x200), kind: BranchKind::Jump, taken: true
+        }));
+        coverage
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut coverage = trace(true);
+        coverage.merge(&trace(false));
+
+        assert_eq!(coverage.executed()[&Address(0x100)], 2);
+        assert_eq!(coverage.executed().get(&Address(0x104)), None);
+        assert_eq!(coverage.branches().len(), 1);
+        assert_eq!(coverage.branches()[&Address(0x102)], BranchCount { taken: 1, not_taken: 1 });
+    }
+
+    /// Coverage of `step()` in testdata/lines.elf, with the branch at
+    /// line 11 taken once and `loop()` not executed.
+    fn fixture() -> (Coverage, LineTable, SymbolTable) {
+        let elf = include_bytes!("../../testdata/lines.elf");
+        let mut coverage = Coverage::new();
+        for &(address, count) in &[(0x1000, 2), (0x1007, 2), (0x100d, 1), (0x1015, 1), (0x101b, 2)] {
+            coverage.executed.insert(Address(address), count);
+        }
+        coverage.branches.insert(Address(0x100b), BranchCount { taken: 1, not_taken: 1 });
+        (coverage, LineTable::from_elf(elf).unwrap(), SymbolTable::from_elf(elf).unwrap())
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (coverage, lines, symbols) = fixture();
+        let mut output = Vec::new();
+        write_lcov(&coverage, &lines, &symbols, "unit", &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+TN:unit
+SF:/src/fixture.c
+FN:10,step
+FN:17,loop
+FNDA:2,step
+FNDA:0,loop
+FNF:2
+FNH:1
+BRDA:11,0,0,1
+BRDA:11,0,1,1
+BRF:2
+BRH:2
+DA:10,2
+DA:11,2
+DA:12,1
+DA:13,1
+DA:14,2
+DA:17,0
+DA:18,0
+DA:19,0
+LF:8
+LH:5
+end_of_record
+");
+    }
+
+    #[test]
+    fn test_cobertura() {
+        let (coverage, lines, _) = fixture();
+        let mut output = Vec::new();
+        write_cobertura(&coverage, &lines, 1700000000, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), format!("\
+<?xml version=\"1.0\" ?>
+<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">
+<coverage line-rate=\"0.6250\" branch-rate=\"1.0000\" lines-covered=\"5\" lines-valid=\"8\" \
+branches-covered=\"2\" branches-valid=\"2\" complexity=\"0\" version=\"{}\" timestamp=\"1700000000\">
+  <sources><source>.</source></sources>
+  <packages>
+    <package name=\"firmware\" line-rate=\"0.6250\" branch-rate=\"1.0000\" complexity=\"0\">
+      <classes>
+        <class name=\"fixture.c\" filename=\"/src/fixture.c\" line-rate=\"0.6250\" branch-rate=\"1.0000\" complexity=\"0\">
+          <methods/>
+          <lines>
+            <line number=\"10\" hits=\"2\" branch=\"false\"/>
+            <line number=\"11\" hits=\"2\" branch=\"true\" condition-coverage=\"100% (2/2)\"/>
+            <line number=\"12\" hits=\"1\" branch=\"false\"/>
+            <line number=\"13\" hits=\"1\" branch=\"false\"/>
+            <line number=\"14\" hits=\"2\" branch=\"false\"/>
+            <line number=\"17\" hits=\"0\" branch=\"false\"/>
+            <line number=\"18\" hits=\"0\" branch=\"false\"/>
+            <line number=\"19\" hits=\"0\" branch=\"false\"/>
+          </lines>
+        </class>
+      </classes>
+    </package>
+  </packages>
+</coverage>
+", env!("CARGO_PKG_VERSION")));
+    }
+}
@@ -0,0 +1,51 @@
+//! Writes records as CSV with a header row. The columns are the
+//! fields of the record schema, in the same order, and empty for
+//! fields that do not apply.
+
+extern crate csv;
+
+use std::io::{Write, Error};
+use super::record::Record;
+
+pub struct CsvWriter<W: Write> {
+    output: csv::Writer<W>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Starts the output with the header and the schema record.
+    pub fn new(output: W) -> Result<CsvWriter<W>, Error> {
+        let mut writer = CsvWriter { output: csv::Writer::from_writer(output) };
+        writer.write(&Record::schema())?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), Error> {
+        Ok(self.output.serialize(record)?)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.output.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tpiu::types::*;
+
+    #[test]
+    fn test_csv() {
+        let packet = TPIUPacket::Data(TraceSourceID(1), vec![0x01, 0x41]);
+        let mut writer = CsvWriter::new(Vec::new()).unwrap();
+        writer.write(&Record::from_tpiu(Some(32), &packet)).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output,
+            "offset,time,source,type,port,comparator,exception,event,address,value,bits,data,text,signal,signal_value,page\n\
+             ,,,Schema,,,,,,1,,,arm_coresight_decoder,,,\n\
+             32,,1,Data,,,,,,,,0141,,,,\n");
+    }
+}
@@ -0,0 +1,102 @@
+//! Renders folded stacks directly as an SVG flamegraph.
+
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+use super::folded::FoldedStacks;
+
+const WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const TITLE_HEIGHT: f64 = 32.0;
+const MIN_WIDTH: f64 = 0.1;
+
+#[derive(Default)]
+struct Node {
+    count: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn depth(&self) -> usize {
+        1 + self.children.values().map(|c| c.depth()).max().unwrap_or(0)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Picks a warm color deterministically from the frame name,
+/// so that the same function has the same color in every graph.
+fn color(name: &str) -> (u8, u8, u8) {
+    let hash = name.bytes().fold(5381u32, |h, b| h.wrapping_mul(33) ^ b as u32);
+    (205 + (hash % 50) as u8, ((hash >> 8) % 230) as u8, ((hash >> 16) % 55) as u8)
+}
+
+/// Dimensions shared by all frames of the graph.
+struct Layout {
+    height: f64,
+    scale: f64,
+    total: u64,
+}
+
+fn write_node<W: Write>(output: &mut W, layout: &Layout, name: &str, node: &Node,
+                        x: f64, depth: usize) -> Result<(), Error> {
+    let width = node.count as f64 * layout.scale;
+    if width < MIN_WIDTH {
+        return Ok(());
+    }
+
+    let y = layout.height - (depth + 1) as f64 * FRAME_HEIGHT;
+    let (r, g, b) = color(name);
+    writeln!(output, "<g><title>{} ({} samples, {:.2}%)</title>", escape(name), node.count,
+             node.count as f64 * 100.0 / layout.total as f64)?;
+    writeln!(output, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"rgb({},{},{})\" rx=\"2\"/>",
+             x, y, width, FRAME_HEIGHT - 1.0, r, g, b)?;
+
+    // Approximate character width for the 12px font.
+    let chars = ((width - 6.0) / 7.0) as usize;
+    if chars >= 3 {
+        let mut label: String = name.chars().take(chars).collect();
+        if label.len() < name.len() {
+            label = label.chars().take(chars - 2).collect::<String>() + "..";
+        }
+        writeln!(output, "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>", x + 3.0, y + FRAME_HEIGHT - 4.0, escape(&label))?;
+    }
+    writeln!(output, "</g>")?;
+
+    let mut child_x = x;
+    for (child_name, child) in &node.children {
+        write_node(output, layout, child_name, child, child_x, depth + 1)?;
+        child_x += child.count as f64 * layout.scale;
+    }
+    Ok(())
+}
+
+/// Writes the profile as a standalone SVG flamegraph, with the
+/// root frame at the bottom and callees stacked above callers.
+pub fn write_svg<W: Write>(stacks: &FoldedStacks, title: &str, output: &mut W) -> Result<(), Error> {
+    let mut root = Node::default();
+    for (stack, &count) in stacks.stacks() {
+        root.count += count;
+        let mut node = &mut root;
+        for frame in stack.split(';') {
+            node = node.children.entry(String::from(frame)).or_insert_with(Node::default);
+            node.count += count;
+        }
+    }
+
+    let height = root.depth() as f64 * FRAME_HEIGHT + TITLE_HEIGHT;
+    let scale = if root.count > 0 { WIDTH / root.count as f64 } else { 0.0 };
+
+    writeln!(output, "<?xml version=\"1.0\" standalone=\"no\"?>")?;
+    writeln!(output, "<svg version=\"1.1\" width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+             WIDTH, height)?;
+    writeln!(output, "<style>text {{ font-family: monospace; font-size: 12px; fill: black; }}</style>")?;
+    writeln!(output, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"white\"/>", WIDTH, height)?;
+    writeln!(output, "<text x=\"{}\" y=\"20\" text-anchor=\"middle\" style=\"font-size: 16px\">{}</text>",
+             WIDTH / 2.0, escape(title))?;
+    let layout = Layout { height, scale, total: root.count.max(1) };
+    write_node(output, &layout, "all", &root, 0.0, 0)?;
+    writeln!(output, "</svg>")?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! Builds profiles in the collapsed stack format used by
+//! Brendan Gregg's flamegraph tools: one line per unique stack,
+//! with frames separated by semicolons and followed by a count.
+
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+use ::itm::types::*;
+use ::elf::symbols::SymbolTable;
+use ::flow::types::*;
+use ::flow::callstack::{CallStack, Frame};
+
+/// Sample counts for each unique stack.
+#[derive(Debug, Clone, Default)]
+pub struct FoldedStacks {
+    stacks: BTreeMap<String, u64>,
+}
+
+impl FoldedStacks {
+    pub fn new() -> FoldedStacks {
+        FoldedStacks { stacks: BTreeMap::new() }
+    }
+
+    /// Adds weight to a stack given with outermost frame first.
+    pub fn add(&mut self, frames: &[String], weight: u64) {
+        if weight == 0 || frames.is_empty() {
+            return;
+        }
+
+        // Semicolons would split the frame in the output.
+        let key = frames.iter().map(|f| f.replace(';', ":"))
+            .collect::<Vec<String>>().join(";");
+        *self.stacks.entry(key).or_insert(0) += weight;
+    }
+
+    /// Adds all counts from another profile, e.g. from another
+    /// trace file.
+    pub fn merge(&mut self, other: &FoldedStacks) {
+        for (stack, count) in &other.stacks {
+            *self.stacks.entry(stack.clone()).or_insert(0) += *count;
+        }
+    }
+
+    pub fn stacks(&self) -> &BTreeMap<String, u64> {
+        &self.stacks
+    }
+
+    /// Writes the profile in the collapsed stack text format.
+    pub fn write_to<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        for (stack, count) in &self.stacks {
+            writeln!(output, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Profiles ITM periodic PC samples. Each sample is attributed to
+/// the function containing it, nested under the active exception
+/// handlers.
+pub struct PCSampleProfiler<'a> {
+    symbols: &'a SymbolTable,
+    exceptions: Vec<ExceptionNumber>,
+    stacks: FoldedStacks,
+}
+
+impl<'a> PCSampleProfiler<'a> {
+    pub fn new(symbols: &'a SymbolTable) -> PCSampleProfiler<'a> {
+        PCSampleProfiler {
+            symbols,
+            exceptions: Vec::new(),
+            stacks: FoldedStacks::new(),
+        }
+    }
+
+    pub fn add(&mut self, packet: &ITMPacket) {
+        match *packet {
+            ITMPacket::Exception(ExceptionEvent::Enter, number) => {
+                self.exceptions.retain(|&n| n != number);
+                self.exceptions.push(number);
+            },
+            ITMPacket::Exception(ExceptionEvent::Exit, number) => {
+                if let Some(pos) = self.exceptions.iter().rposition(|&n| n == number) {
+                    self.exceptions.truncate(pos);
+                }
+            },
+            ITMPacket::Exception(ExceptionEvent::Resume, number) => {
+                // Resume to thread mode, or to a preempted handler.
+                match self.exceptions.iter().rposition(|&n| n == number) {
+                    Some(pos) => self.exceptions.truncate(pos + 1),
+                    None if number.0 == 0 => self.exceptions.clear(),
+                    None => self.exceptions.push(number),
+                }
+            },
+            ITMPacket::ProgramCounter(address) => {
+                let mut frames: Vec<String> = self.exceptions.iter().map(|n| n.name()).collect();
+                frames.push(self.symbols.name(address));
+                self.stacks.add(&frames, 1);
+            },
+            ITMPacket::SleepMode => {
+                self.stacks.add(&[String::from("[sleep]")], 1);
+            },
+            ITMPacket::Overflow => {
+                // Exception events may have been lost.
+                self.exceptions.clear();
+            },
+            _ => {}
+        }
+    }
+
+    pub fn stacks(&self) -> &FoldedStacks {
+        &self.stacks
+    }
+
+    pub fn into_stacks(self) -> FoldedStacks {
+        self.stacks
+    }
+}
+
+/// Profiles reconstructed program flow from instruction trace.
+/// Each executed instruction counts as one sample for the full
+/// call stack.
+pub struct FlowProfiler<'a> {
+    symbols: &'a SymbolTable,
+    callstack: CallStack,
+    stacks: FoldedStacks,
+}
+
+impl<'a> FlowProfiler<'a> {
+    pub fn new(symbols: &'a SymbolTable) -> FlowProfiler<'a> {
+        FlowProfiler {
+            symbols,
+            callstack: CallStack::new(),
+            stacks: FoldedStacks::new(),
+        }
+    }
+
+    pub fn add(&mut self, event: &FlowEvent) {
+        match *event {
+            FlowEvent::Range(ref range) => {
+                let mut frames = Vec::new();
+                for frame in self.callstack.frames() {
+                    match *frame {
+                        Frame::Call(site) => frames.push(self.symbols.name(site)),
+                        Frame::Exception(number, site) => {
+                            frames.push(self.symbols.name(site));
+                            frames.push(number.name());
+                        }
+                    }
+                }
+                frames.push(self.symbols.name(range.start));
+                self.stacks.add(&frames, range.instructions as u64);
+            },
+            FlowEvent::Branch(ref branch) => {
+                self.callstack.branch(branch);
+            },
+            FlowEvent::Discontinuity => {
+                self.callstack.clear();
+            },
+            _ => {}
+        }
+    }
+
+    pub fn stacks(&self) -> &FoldedStacks {
+        &self.stacks
+    }
+
+    pub fn into_stacks(self) -> FoldedStacks {
+        self.stacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elf::symbols::Symbol;
+
+    fn symbols() -> SymbolTable {
+        SymbolTable::new(vec![
+            Symbol { name: String::from("main"), address: Address(0x100), size: 0x40 },
+            Symbol { name: String::from("foo"), address: Address(0x140), size: 0x20 },
+            Symbol { name: String::from("isr"), address: Address(0x200), size: 0x10 },
+        ])
+    }
+
+    fn output(stacks: &FoldedStacks) -> String {
+        let mut output = Vec::new();
+        stacks.write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_pc_samples() {
+        let symbols = symbols();
+        let mut profiler = PCSampleProfiler::new(&symbols);
+        profiler.add(&ITMPacket::ProgramCounter(Address(0x104)));
+        profiler.add(&ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(16)));
+        profiler.add(&ITMPacket::ProgramCounter(Address(0x204)));
+        profiler.add(&ITMPacket::ProgramCounter(Address(0x300)));
+        profiler.add(&ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(16)));
+        profiler.add(&ITMPacket::Exception(ExceptionEvent::Resume, ExceptionNumber(0)));
+        profiler.add(&ITMPacket::ProgramCounter(Address(0x108)));
+
+        assert_eq!(output(profiler.stacks()), "IRQ0;0x00000300 1\nIRQ0;isr 1\nmain 2\n");
+    }
+
+    #[test]
+    fn test_flow() {
+        let symbols = symbols();
+        let mut profiler = FlowProfiler::new(&symbols);
+        let range = |start, end, instructions| FlowEvent::Range(InstructionRange {
+            start: Address(start), end: Address(end), instructions
+        });
+        let branch = |source, destination, kind| FlowEvent::Branch(BranchRecord {
+            source: Address(source), destination: Address(destination), kind, taken: true
+        });
+
+        profiler.add(&range(0x100, 0x10a, 4));
+        profiler.add(&branch(0x108, 0x140, BranchKind::Call));
+        profiler.add(&range(0x140, 0x146, 3));
+        profiler.add(&branch(0x144, 0x200, BranchKind::Exception(ExceptionNumber(17))));
+        profiler.add(&range(0x200, 0x204, 2));
+        profiler.add(&branch(0x202, 0x144, BranchKind::ExceptionReturn));
+        profiler.add(&range(0x144, 0x148, 2));
+        profiler.add(&branch(0x146, 0x10a, BranchKind::Return));
+        profiler.add(&range(0x10a, 0x10c, 1));
+
+        assert_eq!(output(profiler.stacks()), "main 5\nmain;foo 5\nmain;foo;IRQ1;isr 2\n");
+    }
+}
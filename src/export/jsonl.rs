@@ -0,0 +1,62 @@
+//! Writes records as JSON Lines, one object per line, for use with
+//! tools such as jq and pandas. All fields of the schema are present
+//! in every object, with null for fields that do not apply.
+
+extern crate serde_json;
+
+use std::io::{Write, Error};
+use super::record::Record;
+
+pub struct JsonLinesWriter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// Starts the output with the schema record.
+    pub fn new(output: W) -> Result<JsonLinesWriter<W>, Error> {
+        let mut writer = JsonLinesWriter { output };
+        writer.write(&Record::schema())?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.output, record)?;
+        self.output.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::itm::types::*;
+
+    #[test]
+    fn test_jsonl() {
+        let packet = ITMPacket::Software(InstrumentationPort(3), DataValue::U8(0x61));
+        let mut writer = JsonLinesWriter::new(Vec::new()).unwrap();
+        writer.write(&Record::from_itm(Some(16), Some(100), None, &packet)).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines, vec![
+            "{\"offset\":null,\"time\":null,\"source\":null,\"type\":\"Schema\",\"port\":null,\
+             \"comparator\":null,\"exception\":null,\"event\":null,\"address\":null,\"value\":1,\
+             \"bits\":null,\"data\":null,\"text\":\"arm_coresight_decoder\",\"signal\":null,\
+             \"signal_value\":null,\"page\":null}",
+            "{\"offset\":16,\"time\":100,\"source\":null,\"type\":\"Software\",\"port\":3,\
+             \"comparator\":null,\"exception\":null,\"event\":null,\"address\":null,\"value\":97,\
+             \"bits\":8,\"data\":null,\"text\":null,\"signal\":null,\"signal_value\":null,\
+             \"page\":null}",
+        ]);
+
+        let record: Record = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(record.port, Some(3));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod vcd;
+pub mod folded;
+pub mod flamegraph;
+pub mod callgrind;
+pub mod coverage;
+pub mod autofdo;
+pub mod record;
+#[cfg(feature = "serde")]
+pub mod jsonl;
+#[cfg(feature = "serde")]
+pub mod csv;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
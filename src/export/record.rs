@@ -0,0 +1,334 @@
+//! Flat, protocol independent representation of decoded packets,
+//! used by the JSON Lines and CSV writers. Each packet becomes one
+//! record with the following fields, which are null or empty when
+//! they do not apply to the packet type:
+//!
+//! | Field          | Contents                                                   |
+//! |----------------|------------------------------------------------------------|
+//! | `offset`       | Byte offset of the packet in the input                     |
+//! | `time`         | Absolute ITM timestamp in timestamp clock ticks            |
+//! | `source`       | TPIU trace source ID                                       |
+//! | `type`         | Packet type, e.g. `Software` or `Data`                     |
+//! | `port`         | ITM stimulus port, before adding the page number           |
+//! | `comparator`   | DWT comparator index                                       |
+//! | `exception`    | Exception number                                           |
+//! | `event`        | `Enter`/`Exit`/`Resume`, timestamp sync, counter names     |
+//! | `address`      | Program counter or data address offset                     |
+//! | `value`        | Data value, timestamp, extension data or reserved header   |
+//! | `bits`         | Size of the value in bits                                  |
+//! | `data`         | Payload bytes in hex, or the known bits of a timestamp     |
+//! | `text`         | Exception name, enum value name or error message           |
+//! | `signal`       | Signal name from the port schema                           |
+//! | `signal_value` | Decoded signal value as a number                           |
+//! | `page`         | Stimulus port page of a `Signal` record                    |
+//!
+//! The first record of a file has type `Schema` and the schema
+//! version in `value`. Fields are only added at the end, and any
+//! other change increments the version.
+//!
+//! With a port schema, see `itm::schema`, each decoded value is a
+//! `Signal` record after the packet that completed it.
+//!
+//! `Record` implements `PartialEq` but not `Eq`, because of the
+//! floating point `signal_value`.
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+use ::itm::types::*;
+use ::itm::timestamp::Timestamper;
+use ::itm::schema::{Schema, SignalDecoder, Sample, SignalValue};
+use ::tpiu::types::*;
+use ::tpiu::demux::{Demux, SourcePacket, SourceType};
+use ::decoder::Decoder;
+
+/// Version of the record fields, given in the `Schema` record.
+pub const SCHEMA_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Record {
+    pub offset: Option<u64>,
+    pub time: Option<u64>,
+    pub source: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub kind: String,
+    pub port: Option<u32>,
+    pub comparator: Option<u32>,
+    pub exception: Option<u32>,
+    pub event: Option<String>,
+    pub address: Option<u32>,
+    pub value: Option<u64>,
+    pub bits: Option<u8>,
+    pub data: Option<String>,
+    pub text: Option<String>,
+    pub signal: Option<String>,
+    pub signal_value: Option<f64>,
+    pub page: Option<u32>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn counter_names(flags: &EventCounterFlags) -> String {
+    let names = [
+        (flags.cpicnt, "cpicnt"), (flags.exccnt, "exccnt"), (flags.sleepcnt, "sleepcnt"),
+        (flags.lsucnt, "lsucnt"), (flags.foldcnt, "foldcnt"), (flags.postcnt, "postcnt"),
+    ];
+    names.iter().filter(|n| n.0).map(|n| n.1).collect::<Vec<&str>>().join("|")
+}
+
+impl Record {
+    /// First record of an output file.
+    pub fn schema() -> Record {
+        Record {
+            kind: String::from("Schema"),
+            value: Some(SCHEMA_VERSION),
+            text: Some(String::from("arm_coresight_decoder")),
+            ..Record::default()
+        }
+    }
+
+    fn new(kind: &str, offset: Option<u64>, time: Option<u64>, source: Option<TraceSourceID>) -> Record {
+        Record {
+            offset, time,
+            source: source.map(|s| s.0),
+            kind: String::from(kind),
+            ..Record::default()
+        }
+    }
+
+    fn set_value(&mut self, value: &DataValue) {
+        self.value = Some(value.to_u32() as u64);
+        self.bits = Some(value.bit_width());
+    }
+
+    pub fn from_itm(offset: Option<u64>, time: Option<u64>, source: Option<TraceSourceID>,
+                    packet: &ITMPacket) -> Record {
+        let kind = match *packet {
+            ITMPacket::Synchronization => "Synchronization",
+            ITMPacket::Overflow => "Overflow",
+            ITMPacket::LocalTimestamp(..) => "LocalTimestamp",
+            ITMPacket::GlobalTimestamp(..) => "GlobalTimestamp",
+            ITMPacket::SoftwarePageNumber(..) => "SoftwarePageNumber",
+            ITMPacket::Software(..) => "Software",
+            ITMPacket::EventCounter(..) => "EventCounter",
+            ITMPacket::ProgramCounter(..) => "ProgramCounter",
+            ITMPacket::SleepMode => "SleepMode",
+            ITMPacket::Exception(..) => "Exception",
+            ITMPacket::DataTracePC(..) => "DataTracePC",
+            ITMPacket::DataTraceOffset(..) => "DataTraceOffset",
+            ITMPacket::DataTraceReadData(..) => "DataTraceReadData",
+            ITMPacket::DataTraceWriteData(..) => "DataTraceWriteData",
+            ITMPacket::Extension(..) => "Extension",
+            ITMPacket::Reserved(..) => "Reserved",
+            ITMPacket::Invalid(..) => "Invalid",
+        };
+
+        let mut record = Record::new(kind, offset, time, source);
+        match *packet {
+            ITMPacket::LocalTimestamp(sync, delta) => {
+                record.event = Some(format!("{:?}", sync));
+                record.value = Some(delta.0 as u64);
+            },
+            ITMPacket::GlobalTimestamp(ref value) => {
+                record.value = Some(value.timestamp);
+                record.data = Some(format!("{:016x}", value.known_mask));
+                record.event = match (value.wrap, value.clock_change) {
+                    (true, true) => Some(String::from("Wrap|ClockChange")),
+                    (true, false) => Some(String::from("Wrap")),
+                    (false, true) => Some(String::from("ClockChange")),
+                    (false, false) => None,
+                };
+            },
+            ITMPacket::SoftwarePageNumber(page) => record.port = Some(page.0),
+            ITMPacket::Software(port, ref value) => {
+                record.port = Some(port.0);
+                record.set_value(value);
+            },
+            ITMPacket::EventCounter(ref flags) => record.event = Some(counter_names(flags)),
+            ITMPacket::ProgramCounter(address) => record.address = Some(address.0),
+            ITMPacket::Exception(event, number) => {
+                record.exception = Some(number.0);
+                record.event = Some(format!("{:?}", event));
+                record.text = Some(number.name());
+            },
+            ITMPacket::DataTracePC(comp, address) |
+            ITMPacket::DataTraceOffset(comp, address) => {
+                record.comparator = Some(comp.0);
+                record.address = Some(address.0);
+            },
+            ITMPacket::DataTraceReadData(comp, ref value) |
+            ITMPacket::DataTraceWriteData(comp, ref value) => {
+                record.comparator = Some(comp.0);
+                record.set_value(value);
+            },
+            ITMPacket::Extension(ref info) => {
+                record.value = Some(info.data as u64);
+                record.bits = Some(info.bitcount);
+                record.event = Some(String::from(if info.source == 0 { "Software" } else { "Hardware" }));
+            },
+            ITMPacket::Reserved(header) => record.value = Some(header as u64),
+            ITMPacket::Invalid(ref message) => record.text = Some(message.clone()),
+            ITMPacket::Synchronization | ITMPacket::Overflow | ITMPacket::SleepMode => {},
+        }
+        record
+    }
+
+    pub fn from_signal(offset: Option<u64>, source: Option<TraceSourceID>, sample: &Sample) -> Record {
+        Record {
+            port: Some(sample.port.0 % 32),
+            page: Some(sample.port.0 / 32),
+            signal: Some(sample.name.clone()),
+            signal_value: Some(sample.value.to_f64()),
+            text: match sample.value {
+                SignalValue::Enum(_, ref name) => name.clone(),
+                _ => None,
+            },
+            ..Record::new("Signal", offset, Some(sample.time), source)
+        }
+    }
+
+    pub fn from_tpiu(offset: Option<u64>, packet: &TPIUPacket) -> Record {
+        match *packet {
+            TPIUPacket::FrameSynchronization => Record::new("FrameSynchronization", offset, None, None),
+            TPIUPacket::HalfwordSynchronization => Record::new("HalfwordSynchronization", offset, None, None),
+            TPIUPacket::Data(id, ref data) => Record {
+                data: Some(hex(data)),
+                ..Record::new("Data", offset, None, Some(id))
+            },
+            TPIUPacket::Trigger(ref data) => Record {
+                data: Some(hex(data)),
+                ..Record::new("Trigger", offset, None, Some(TraceSourceID(0x7D)))
+            },
+            TPIUPacket::Null(ref data) => Record {
+                data: Some(hex(data)),
+                ..Record::new("Null", offset, None, Some(TraceSourceID(0)))
+            },
+            TPIUPacket::Reserved(ref data) => Record {
+                data: Some(hex(data)),
+                ..Record::new("Reserved", offset, None, None)
+            },
+            TPIUPacket::Invalid(ref message) => Record {
+                text: Some(message.clone()),
+                ..Record::new("Invalid", offset, None, None)
+            },
+        }
+    }
+}
+
+/// Records of the ITM packets from a parser, with the input offsets
+/// and the absolute times reconstructed from the local timestamps.
+pub fn itm_records<D: Decoder<Packet=ITMPacket>>(decoder: D) -> impl Iterator<Item=Record> {
+    timed_records(decoder.with_offsets(), None)
+}
+
+/// Like `itm_records()`, with the `Signal` records of the ports in
+/// the schema.
+pub fn itm_signal_records<D: Decoder<Packet=ITMPacket>>(decoder: D, schema: Schema) -> impl Iterator<Item=Record> {
+    signal_records(decoder.with_offsets(), None, schema)
+}
+
+/// Records of the ITM packets of one trace source in a TPIU stream.
+/// The offsets are those of the TPIU frames.
+pub fn demuxed_itm_records<D: Decoder<Packet=TPIUPacket>>(decoder: D, source: TraceSourceID)
+                                                          -> impl Iterator<Item=Record> {
+    timed_records(demuxed_itm(decoder, source), Some(source))
+}
+
+/// Like `demuxed_itm_records()`, with the `Signal` records of the
+/// ports in the schema.
+pub fn demuxed_itm_signal_records<D: Decoder<Packet=TPIUPacket>>(decoder: D, source: TraceSourceID, schema: Schema)
+                                                                 -> impl Iterator<Item=Record> {
+    signal_records(demuxed_itm(decoder, source), Some(source), schema)
+}
+
+fn demuxed_itm<D: Decoder<Packet=TPIUPacket>>(decoder: D, source: TraceSourceID)
+                                              -> impl Iterator<Item=(usize, ITMPacket)> {
+    let mut demux = Demux::new();
+    demux.add_source(source, SourceType::ITM);
+    decoder.with_offsets().flat_map(move |(offset, packet)| {
+        demux.push(packet).into_iter().filter_map(move |(id, packet)| match packet {
+            SourcePacket::ITM(packet) if id == source => Some((offset, packet)),
+            _ => None,
+        })
+    })
+}
+
+fn timed_records<I: Iterator<Item=(usize, ITMPacket)>>(packets: I, source: Option<TraceSourceID>)
+                                                       -> impl Iterator<Item=Record> {
+    Timestamper::new(packets).map(move |(time, (offset, packet))| {
+        Record::from_itm(Some(offset as u64), Some(time), source, &packet)
+    })
+}
+
+fn signal_records<I: Iterator<Item=(usize, ITMPacket)>>(packets: I, source: Option<TraceSourceID>, schema: Schema)
+                                                        -> impl Iterator<Item=Record> {
+    let mut signals = SignalDecoder::new(schema);
+    Timestamper::new(packets).flat_map(move |(time, (offset, packet))| {
+        let offset = Some(offset as u64);
+        let mut records = vec![Record::from_itm(offset, Some(time), source, &packet)];
+        records.extend(signals.push(time, &packet).iter().map(|s| Record::from_signal(offset, source, s)));
+        records
+    })
+}
+
+/// Records of the TPIU packets from a parser, with the input offsets.
+pub fn tpiu_records<D: Decoder<Packet=TPIUPacket>>(decoder: D) -> impl Iterator<Item=Record> {
+    decoder.with_offsets().map(|(offset, packet)| Record::from_tpiu(Some(offset as u64), &packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use ::itm::parser::Parser;
+
+    #[test]
+    fn test_itm_records() {
+        let data = vec![0x02, 0x34, 0x12, 0x0E, 0x25, 0x10, 0x20, 0x17, 0x16, 0x02, 0x00, 0x08];
+        let records: Vec<Record> = itm_records(Parser::new(Cursor::new(data))).collect();
+        assert_eq!(records, vec![
+            Record { port: Some(0), value: Some(0x1234), bits: Some(16),
+                     ..Record::new("Software", Some(0), Some(2), None) },
+            Record { exception: Some(37), event: Some(String::from("Enter")), text: Some(String::from("IRQ21")),
+                     ..Record::new("Exception", Some(3), Some(2), None) },
+            Record { event: Some(String::from("Synchronous")), value: Some(2),
+                     ..Record::new("LocalTimestamp", Some(6), Some(2), None) },
+            Record { address: Some(0x08000216), ..Record::new("ProgramCounter", Some(7), Some(2), None) },
+        ]);
+    }
+
+    #[test]
+    fn test_demuxed_itm_records() {
+        // Frame with ITM on source 1, interleaved with data of source 2.
+        let data = vec![0xFF, 0xFF, 0xFF, 0x7F,
+                        0x03, 0x01, 0x40, 0x20, 0x05, 0x55, 0x03, 0x30,
+                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+        let parser = ::tpiu::parser::Parser::new(Box::new(Cursor::new(data)));
+        let records: Vec<Record> = demuxed_itm_records(parser, TraceSourceID(1)).collect();
+        let source = Some(TraceSourceID(1));
+        assert_eq!(records, vec![
+            Record { port: Some(0), value: Some(0x41), bits: Some(8),
+                     ..Record::new("Software", Some(4), Some(2), source) },
+            Record { event: Some(String::from("Synchronous")), value: Some(2),
+                     ..Record::new("LocalTimestamp", Some(4), Some(2), source) },
+            Record { event: Some(String::from("Synchronous")), value: Some(3),
+                     ..Record::new("LocalTimestamp", Some(4), Some(5), source) },
+        ]);
+    }
+
+    #[test]
+    fn test_signal_records() {
+        let data = vec![0x02, 0x00, 0xC0, 0x18, 0x09, 0x02];
+        let schema = Schema::parse("0 current q15\n33 state enum 1=Idle 2=Run").unwrap();
+        let records: Vec<Record> = itm_signal_records(Parser::new(Cursor::new(data)), schema)
+            .filter(|r| r.kind == "Signal").collect();
+        assert_eq!(records, vec![
+            Record { port: Some(0), page: Some(0), signal: Some(String::from("current")), signal_value: Some(-0.5),
+                     ..Record::new("Signal", Some(0), Some(0), None) },
+            Record { port: Some(1), page: Some(1), signal: Some(String::from("state")), signal_value: Some(2.0),
+                     text: Some(String::from("Run")), ..Record::new("Signal", Some(4), Some(0), None) },
+        ]);
+    }
+}
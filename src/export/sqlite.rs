@@ -0,0 +1,328 @@
+//! Writes a decoded ITM trace into an SQLite database for ad-hoc
+//! queries. Every packet has a row in `packets`, and the packet types
+//! of interest have their fields in separate tables that refer to it:
+//!
+//! - `exceptions`: handler intervals from entry to exit, with the
+//!   nesting depth at entry. Start or end is NULL if it is not in
+//!   the trace.
+//! - `software_writes`: stimulus port writes, with the page number
+//!   included in the port.
+//! - `pc_samples`: periodic PC samples, NULL address when sleeping.
+//! - `data_accesses`: data trace packets of the DWT comparators.
+//! - `timestamps`: local and global timestamp packets.
+//! - `signals`: decoded values of the ports in the schema given to
+//!   `set_schema()`, with the enum value names in `label`.
+//! - `metadata`: clocks and ELF build ID as key-value pairs.
+//!
+//! Times are absolute local timestamps in timestamp clock ticks. If
+//! the clock is known, the `exception_times` view gives the intervals
+//! in microseconds, e.g. for finding long handlers:
+//!
+//! ```sql
+//! SELECT * FROM exception_times WHERE exception = 37 AND duration_us > 50;
+//! ```
+
+extern crate rusqlite;
+use self::rusqlite::Connection;
+
+use std::io::Error;
+use std::path::Path;
+use ::itm::types::*;
+use ::itm::schema::{Schema, SignalDecoder, SignalValue};
+use super::record::Record;
+
+const SCHEMA: &str = "
+    CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT);
+    CREATE TABLE packets (
+        id INTEGER PRIMARY KEY, byte_offset INTEGER, time INTEGER, type TEXT NOT NULL);
+    CREATE TABLE exceptions (
+        id INTEGER PRIMARY KEY, exception INTEGER NOT NULL, name TEXT NOT NULL,
+        start_time INTEGER, end_time INTEGER, duration INTEGER, depth INTEGER NOT NULL,
+        enter_packet INTEGER REFERENCES packets(id), exit_packet INTEGER REFERENCES packets(id));
+    CREATE TABLE software_writes (
+        packet INTEGER PRIMARY KEY REFERENCES packets(id), time INTEGER,
+        port INTEGER NOT NULL, value INTEGER NOT NULL, bits INTEGER NOT NULL);
+    CREATE TABLE pc_samples (
+        packet INTEGER PRIMARY KEY REFERENCES packets(id), time INTEGER, address INTEGER);
+    CREATE TABLE data_accesses (
+        packet INTEGER PRIMARY KEY REFERENCES packets(id), time INTEGER, comparator INTEGER NOT NULL,
+        pc INTEGER, address_offset INTEGER, value INTEGER, bits INTEGER, is_write INTEGER);
+    CREATE TABLE timestamps (
+        packet INTEGER PRIMARY KEY REFERENCES packets(id), time INTEGER, type TEXT NOT NULL,
+        delta INTEGER, sync TEXT, value INTEGER, known_mask INTEGER);
+    CREATE TABLE signals (
+        id INTEGER PRIMARY KEY, packet INTEGER REFERENCES packets(id), time INTEGER,
+        port INTEGER NOT NULL, name TEXT NOT NULL, value REAL NOT NULL, label TEXT);
+    CREATE VIEW exception_times AS
+        SELECT exceptions.*,
+               start_time * 1000000.0 / clock.hz AS start_us,
+               end_time * 1000000.0 / clock.hz AS end_us,
+               duration * 1000000.0 / clock.hz AS duration_us
+        FROM exceptions,
+             (SELECT CAST(value AS REAL) AS hz FROM metadata WHERE key = 'timestamp_clock') AS clock;
+";
+
+/// Indexes are created after inserting the rows, which is faster.
+const INDEXES: &str = "
+    CREATE INDEX packets_time ON packets (time);
+    CREATE INDEX exceptions_number ON exceptions (exception, start_time);
+    CREATE INDEX exceptions_time ON exceptions (start_time, end_time);
+    CREATE INDEX software_writes_port ON software_writes (port, time);
+    CREATE INDEX software_writes_time ON software_writes (time);
+    CREATE INDEX pc_samples_time ON pc_samples (time);
+    CREATE INDEX data_accesses_comparator ON data_accesses (comparator, time);
+    CREATE INDEX timestamps_time ON timestamps (time);
+    CREATE INDEX signals_name ON signals (name, time);
+";
+
+fn sql_error(error: rusqlite::Error) -> Error {
+    Error::other(error)
+}
+
+/// Information stored in the metadata table.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// Frequency of the ITM local timestamp counter in Hz.
+    pub timestamp_clock: Option<u64>,
+
+    /// Processor clock frequency in Hz.
+    pub cpu_clock: Option<u64>,
+
+    /// GNU build ID of the firmware, see `elf::buildid`.
+    pub build_id: Option<Vec<u8>>,
+}
+
+/// Exception handler that has been entered but not exited.
+struct Active {
+    number: ExceptionNumber,
+    start: Option<u64>,
+    packet: Option<i64>,
+    depth: usize,
+}
+
+pub struct SqliteExporter {
+    conn: Connection,
+    page: u32,
+    active: Vec<Active>,
+    signals: Option<SignalDecoder>,
+}
+
+impl SqliteExporter {
+    /// Creates a new database file.
+    pub fn create<P: AsRef<Path>>(path: P, metadata: &Metadata) -> Result<SqliteExporter, Error> {
+        SqliteExporter::new(Connection::open(path).map_err(sql_error)?, metadata)
+    }
+
+    /// Creates the tables in an empty database. Rows are inserted in
+    /// one transaction, which is committed by `finish()`.
+    pub fn new(conn: Connection, metadata: &Metadata) -> Result<SqliteExporter, Error> {
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        conn.execute_batch("BEGIN").map_err(sql_error)?;
+
+        let mut values = vec![(String::from("schema_version"), String::from("1"))];
+        if let Some(clock) = metadata.timestamp_clock {
+            values.push((String::from("timestamp_clock"), clock.to_string()));
+        }
+        if let Some(clock) = metadata.cpu_clock {
+            values.push((String::from("cpu_clock"), clock.to_string()));
+        }
+        if let Some(ref id) = metadata.build_id {
+            let hex: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+            values.push((String::from("elf_build_id"), hex));
+        }
+        for (key, value) in values {
+            conn.execute("INSERT INTO metadata VALUES (?1, ?2)", (key, value)).map_err(sql_error)?;
+        }
+
+        Ok(SqliteExporter { conn, page: 0, active: Vec::new(), signals: None })
+    }
+
+    /// Decodes the writes to the ports in the schema into the
+    /// `signals` table.
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.signals = Some(SignalDecoder::new(schema));
+    }
+
+    fn insert<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<(), Error> {
+        self.conn.prepare_cached(sql).and_then(|mut s| s.execute(params)).map_err(sql_error)?;
+        Ok(())
+    }
+
+    fn close(&self, active: Active, end: Option<u64>, packet: Option<i64>) -> Result<(), Error> {
+        let duration = match (active.start, end) {
+            (Some(start), Some(end)) => Some((end - start) as i64),
+            _ => None,
+        };
+        self.insert("INSERT INTO exceptions (exception, name, start_time, end_time, duration, depth, \
+                     enter_packet, exit_packet) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    (active.number.0, active.number.name(), active.start.map(|t| t as i64),
+                     end.map(|t| t as i64), duration, active.depth as i64, active.packet, packet))
+    }
+
+    /// Closes the handlers from the top of the stack down to the
+    /// given one. Exits of the handlers above it were lost.
+    fn unwind(&mut self, index: usize, time: u64, packet: i64) -> Result<(), Error> {
+        while self.active.len() > index + 1 {
+            let lost = self.active.pop().unwrap();
+            self.close(lost, None, None)?;
+        }
+        let active = self.active.pop().unwrap();
+        self.close(active, Some(time), Some(packet))
+    }
+
+    fn exception(&mut self, event: ExceptionEvent, number: ExceptionNumber, time: u64, packet: i64)
+                 -> Result<(), Error> {
+        let index = self.active.iter().rposition(|a| a.number == number);
+        match (event, index) {
+            (ExceptionEvent::Enter, index) => {
+                if let Some(index) = index {
+                    // Re-entered without an exit, so the exit was lost.
+                    while self.active.len() > index {
+                        let lost = self.active.pop().unwrap();
+                        self.close(lost, None, None)?;
+                    }
+                }
+                let depth = self.active.len();
+                self.active.push(Active { number, start: Some(time), packet: Some(packet), depth });
+            },
+            (ExceptionEvent::Exit, Some(index)) => self.unwind(index, time, packet)?,
+            (ExceptionEvent::Exit, None) => {
+                // Entered before the start of the trace.
+                let depth = self.active.len();
+                self.close(Active { number, start: None, packet: None, depth }, Some(time), Some(packet))?;
+            },
+            (ExceptionEvent::Resume, _) => {},
+        }
+        Ok(())
+    }
+
+    /// Adds a packet, with its input offset and absolute time as
+    /// returned by `Timestamper`.
+    pub fn add(&mut self, offset: usize, time: u64, packet: &ITMPacket) -> Result<(), Error> {
+        let record = Record::from_itm(None, None, None, packet);
+        let t = time as i64;
+        self.insert("INSERT INTO packets (byte_offset, time, type) VALUES (?1, ?2, ?3)",
+                    (offset as i64, t, record.kind))?;
+        let id = self.conn.last_insert_rowid();
+
+        match *packet {
+            ITMPacket::SoftwarePageNumber(page) => self.page = page.0,
+            ITMPacket::Software(port, value) => {
+                self.insert("INSERT INTO software_writes VALUES (?1, ?2, ?3, ?4, ?5)",
+                            (id, t, self.page + port.0, value.to_u32(), record.bits))?;
+            },
+            ITMPacket::ProgramCounter(address) => {
+                self.insert("INSERT INTO pc_samples VALUES (?1, ?2, ?3)", (id, t, Some(address.0)))?;
+            },
+            ITMPacket::SleepMode => {
+                self.insert("INSERT INTO pc_samples VALUES (?1, ?2, ?3)", (id, t, None::<u32>))?;
+            },
+            ITMPacket::Exception(event, number) => self.exception(event, number, time, id)?,
+            ITMPacket::DataTracePC(comp, pc) => {
+                self.insert("INSERT INTO data_accesses (packet, time, comparator, pc) VALUES (?1, ?2, ?3, ?4)",
+                            (id, t, comp.0, pc.0))?;
+            },
+            ITMPacket::DataTraceOffset(comp, offset) => {
+                self.insert("INSERT INTO data_accesses (packet, time, comparator, address_offset) \
+                             VALUES (?1, ?2, ?3, ?4)", (id, t, comp.0, offset.0))?;
+            },
+            ITMPacket::DataTraceReadData(comp, value) |
+            ITMPacket::DataTraceWriteData(comp, value) => {
+                let write = matches!(*packet, ITMPacket::DataTraceWriteData(..));
+                self.insert("INSERT INTO data_accesses (packet, time, comparator, value, bits, is_write) \
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                            (id, t, comp.0, value.to_u32(), record.bits, write))?;
+            },
+            ITMPacket::LocalTimestamp(..) => {
+                self.insert("INSERT INTO timestamps (packet, time, type, delta, sync) VALUES (?1, ?2, ?3, ?4, ?5)",
+                            (id, t, "Local", record.value.map(|v| v as i64), record.event))?;
+            },
+            ITMPacket::GlobalTimestamp(ref value) => {
+                self.insert("INSERT INTO timestamps (packet, time, type, value, known_mask) \
+                             VALUES (?1, ?2, ?3, ?4, ?5)",
+                            (id, t, "Global", value.timestamp as i64, value.known_mask as i64))?;
+            },
+            _ => {},
+        }
+
+        let samples = self.signals.as_mut().map(|s| s.push(time, packet)).unwrap_or_default();
+        for sample in samples {
+            let label = match sample.value {
+                SignalValue::Enum(_, ref name) => name.clone(),
+                _ => None,
+            };
+            self.insert("INSERT INTO signals (packet, time, port, name, value, label) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        (id, sample.time as i64, sample.port.0, &sample.name, sample.value.to_f64(), label))?;
+        }
+        Ok(())
+    }
+
+    /// Stores the handlers that were still active at the end of the
+    /// trace, creates the indexes and commits the rows.
+    pub fn finish(mut self) -> Result<Connection, Error> {
+        while let Some(active) = self.active.pop() {
+            self.close(active, None, None)?;
+        }
+        self.conn.execute_batch(INDEXES).map_err(sql_error)?;
+        self.conn.execute_batch("COMMIT").map_err(sql_error)?;
+        Ok(self.conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queries() {
+        let metadata = Metadata { timestamp_clock: Some(1000000), cpu_clock: Some(64000000),
+                                  build_id: Some(vec![0xAB, 0xCD]) };
+        let mut exporter = SqliteExporter::new(Connection::open_in_memory().unwrap(), &metadata).unwrap();
+        exporter.set_schema(Schema::parse("3 mode enum 88=Busy").unwrap());
+        let packets = vec![
+            (0, ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(53))),
+            (10, ITMPacket::Software(InstrumentationPort(3), DataValue::U8(b'X'))),
+            (20, ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(15))),
+            (30, ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(15))),
+            (30, ITMPacket::Exception(ExceptionEvent::Resume, ExceptionNumber(53))),
+            (100, ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(53))),
+            (200, ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(53))),
+            (210, ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(53))),
+            (220, ITMPacket::ProgramCounter(Address(0x08000100))),
+            (230, ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(11))),
+        ];
+        for (offset, &(time, ref packet)) in packets.iter().enumerate() {
+            exporter.add(offset, time, packet).unwrap();
+        }
+        let conn = exporter.finish().unwrap();
+
+        // IRQ 37 entries longer than 50 us while port 3 said X.
+        let mut query = conn.prepare(
+            "SELECT e.start_time, e.duration_us FROM exception_times e
+             JOIN software_writes w ON w.time BETWEEN e.start_time AND e.end_time
+             WHERE e.name = 'IRQ37' AND e.duration_us > 50 AND w.port = 3 AND w.value = 88").unwrap();
+        let rows: Vec<(i64, f64)> = query.query_map((), |r| Ok((r.get(0)?, r.get(1)?))).unwrap()
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(rows, vec![(0, 100.0)]);
+
+        let mut query = conn.prepare("SELECT name, depth, end_time FROM exceptions ORDER BY id").unwrap();
+        let rows: Vec<(String, i64, Option<i64>)> = query.query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows, vec![
+            (String::from("SysTick"), 1, Some(30)),
+            (String::from("IRQ37"), 0, Some(100)),
+            (String::from("IRQ37"), 0, Some(210)),
+            (String::from("SVCall"), 0, None),
+        ]);
+
+        let build_id: String = conn.query_row("SELECT value FROM metadata WHERE key = 'elf_build_id'", (),
+                                              |r| r.get(0)).unwrap();
+        assert_eq!(build_id, "abcd");
+        let samples: i64 = conn.query_row("SELECT COUNT(*) FROM pc_samples", (), |r| r.get(0)).unwrap();
+        assert_eq!(samples, 1);
+        let signal: (String, f64, String) = conn.query_row("SELECT name, value, label FROM signals", (),
+                                                           |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).unwrap();
+        assert_eq!(signal, (String::from("mode"), 88.0, String::from("Busy")));
+    }
+}
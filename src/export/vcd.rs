@@ -0,0 +1,278 @@
+//! Writes ITM data trace and stimulus port values as a
+//! Value Change Dump, for viewing in e.g. GTKWave. Ports in the
+//! schema given to `set_schema()` are written as real valued signals
+//! with the signal names instead.
+
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+use ::itm::types::*;
+use ::itm::schema::{Schema, SignalDecoder};
+
+/// Configuration for the VCD output.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Frequency of the ITM local timestamp counter in Hz,
+    /// i.e. processor clock divided by the timestamp prescaler.
+    pub timestamp_clock: u64,
+}
+
+/// A named signal in the VCD output.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum Signal {
+    Port(InstrumentationPort),
+    Comparator(ComparatorIndex),
+    Exception(ExceptionNumber),
+    Overflow,
+
+    /// Index to the signal names from the schema.
+    Named(usize),
+}
+
+impl Signal {
+    fn name(&self) -> String {
+        match *self {
+            Signal::Port(port) => format!("port{}", port.0),
+            Signal::Comparator(comp) => format!("dwt_comp{}", comp.0),
+            Signal::Exception(exc) => format!("exc{}", exc.0),
+            Signal::Overflow => String::from("overflow"),
+            Signal::Named(index) => format!("signal{}", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Vector(u32),
+    Bit(bool),
+    Real(f64),
+    Event,
+}
+
+/// Returns the VCD identifier code for n:th signal.
+fn identifier(mut index: usize) -> String {
+    let mut result = String::new();
+    loop {
+        result.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return result;
+        }
+        index -= 1;
+    }
+}
+
+/// Selects timescale so that one unit is at most one timestamp tick.
+/// Returns the timescale string and the unit as a power of ten.
+fn timescale(clock: u64) -> (&'static str, u32) {
+    const UNITS: [&str; 16] = [
+        "1 s", "100 ms", "10 ms", "1 ms", "100 us", "10 us", "1 us",
+        "100 ns", "10 ns", "1 ns", "100 ps", "10 ps", "1 ps",
+        "100 fs", "10 fs", "1 fs"
+    ];
+    let mut exponent = 0;
+    while exponent < 15 && 10u64.pow(exponent) < clock {
+        exponent += 1;
+    }
+    (UNITS[exponent as usize], exponent)
+}
+
+/// Collects value changes from timestamped ITM packets and writes
+/// them out as VCD. The signals are only known after all packets
+/// have been seen, so the output is written at the end.
+pub struct VcdWriter {
+    config: Config,
+    widths: BTreeMap<Signal, u8>,
+    changes: Vec<(u64, Signal, Value)>,
+    page: u32,
+    signals: Option<SignalDecoder>,
+    names: Vec<String>,
+}
+
+impl VcdWriter {
+    pub fn new(config: Config) -> VcdWriter {
+        VcdWriter {
+            config,
+            widths: BTreeMap::new(),
+            changes: Vec::new(),
+            page: 0,
+            signals: None,
+            names: Vec::new(),
+        }
+    }
+
+    /// Decodes the writes to the ports in the schema into signals.
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.signals = Some(SignalDecoder::new(schema));
+    }
+
+    fn change(&mut self, time: u64, signal: Signal, width: u8, value: Value) {
+        let entry = self.widths.entry(signal).or_insert(width);
+        if *entry < width {
+            *entry = width;
+        }
+        self.changes.push((time, signal, value));
+    }
+
+    /// Adds a packet, with time given in timestamp clock ticks.
+    pub fn add(&mut self, time: u64, packet: &ITMPacket) {
+        let samples = match self.signals {
+            Some(ref mut signals) => signals.push(time, packet),
+            None => Vec::new(),
+        };
+
+        match *packet {
+            ITMPacket::SoftwarePageNumber(page) => self.page = page.0,
+            ITMPacket::Software(port, ref value) => {
+                let port = InstrumentationPort(self.page + port.0);
+                if self.signals.as_ref().and_then(|s| s.schema().get(port)).is_none() {
+                    self.change(time, Signal::Port(port), value.bit_width(), Value::Vector(value.to_u32()));
+                }
+            },
+            ITMPacket::DataTraceReadData(comp, ref value) |
+            ITMPacket::DataTraceWriteData(comp, ref value) => {
+                self.change(time, Signal::Comparator(comp), value.bit_width(), Value::Vector(value.to_u32()));
+            },
+            ITMPacket::Exception(event, exc) => {
+                let active = event != ExceptionEvent::Exit;
+                self.change(time, Signal::Exception(exc), 1, Value::Bit(active));
+            },
+            ITMPacket::Overflow => {
+                self.change(time, Signal::Overflow, 1, Value::Event);
+            },
+            _ => {}
+        }
+
+        for sample in samples {
+            let index = match self.names.iter().position(|n| *n == sample.name) {
+                Some(index) => index,
+                None => {
+                    self.names.push(sample.name);
+                    self.names.len() - 1
+                },
+            };
+            self.change(sample.time, Signal::Named(index), 64, Value::Real(sample.value.to_f64()));
+        }
+    }
+
+    /// Writes the VCD header and all value changes to output.
+    pub fn write_to<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        let (unit, exponent) = timescale(self.config.timestamp_clock);
+        let ids: BTreeMap<Signal, String> = self.widths.keys().enumerate()
+            .map(|(i, s)| (*s, identifier(i))).collect();
+
+        writeln!(output, "$version arm_coresight_decoder $end")?;
+        writeln!(output, "$timescale {} $end", unit)?;
+        writeln!(output, "$scope module itm $end")?;
+        for (signal, width) in &self.widths {
+            let (kind, name) = match *signal {
+                Signal::Overflow => ("event", signal.name()),
+                Signal::Named(index) => ("real", self.names[index].clone()),
+                _ => ("wire", signal.name()),
+            };
+            writeln!(output, "$var {} {} {} {} $end", kind, width, ids[signal], name)?;
+        }
+        writeln!(output, "$upscope $end")?;
+        writeln!(output, "$enddefinitions $end")?;
+
+        writeln!(output, "$dumpvars")?;
+        for (signal, width) in &self.widths {
+            match *signal {
+                Signal::Overflow | Signal::Named(_) => {},
+                Signal::Exception(_) => writeln!(output, "0{}", ids[signal])?,
+                _ => writeln!(output, "b{} {}", "x".repeat(*width as usize), ids[signal])?,
+            }
+        }
+        writeln!(output, "$end")?;
+
+        // Structs have the time of their first write, which can be
+        // before the changes added after it.
+        let mut changes: Vec<&(u64, Signal, Value)> = self.changes.iter().collect();
+        changes.sort_by_key(|c| c.0);
+
+        let mut previous = None;
+        for &&(time, signal, value) in &changes {
+            let time = (time as u128 * 10u128.pow(exponent) / self.config.timestamp_clock.max(1) as u128) as u64;
+            if previous != Some(time) {
+                writeln!(output, "#{}", time)?;
+                previous = Some(time);
+            }
+
+            match value {
+                Value::Vector(v) => writeln!(output, "b{:b} {}", v, ids[&signal])?,
+                Value::Bit(b) => writeln!(output, "{}{}", b as u8, ids[&signal])?,
+                Value::Real(v) => writeln!(output, "r{} {}", v, ids[&signal])?,
+                Value::Event => writeln!(output, "1{}", ids[&signal])?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timescale() {
+        assert_eq!(timescale(72_000_000), ("10 ns", 8));
+        assert_eq!(timescale(1_000_000), ("1 us", 6));
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(94), "!!");
+    }
+
+    #[test]
+    fn test_output() {
+        let mut writer = VcdWriter::new(Config { timestamp_clock: 1_000_000 });
+        writer.add(5, &ITMPacket::Software(InstrumentationPort(1), DataValue::U16(0x1234)));
+        writer.add(5, &ITMPacket::Exception(ExceptionEvent::Enter, ExceptionNumber(16)));
+        writer.add(7, &ITMPacket::DataTraceWriteData(ComparatorIndex(0), DataValue::U8(3)));
+        writer.add(9, &ITMPacket::Exception(ExceptionEvent::Exit, ExceptionNumber(16)));
+        writer.add(9, &ITMPacket::Overflow);
+
+        let mut output = Vec::new();
+        writer.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "\
+$version arm_coresight_decoder $end
+$timescale 1 us $end
+$scope module itm $end
+$var wire 16 ! port1 $end
+$var wire 8 \" dwt_comp0 $end
+$var wire 1 # exc16 $end
+$var event 1 $ overflow $end
+$upscope $end
+$enddefinitions $end
+$dumpvars
+bxxxxxxxxxxxxxxxx !
+bxxxxxxxx \"
+0#
+$end
+#5
+b1001000110100 !
+1#
+#7
+b11 \"
+#9
+0#
+1$
+");
+    }
+
+    #[test]
+    fn test_schema() {
+        let mut writer = VcdWriter::new(Config { timestamp_clock: 1_000_000 });
+        writer.set_schema(Schema::parse("1 speed f32\n2 pos struct x:i8 y:i8").unwrap());
+        writer.add(1, &ITMPacket::Software(InstrumentationPort(1), DataValue::U32(2.5f32.to_bits())));
+        writer.add(2, &ITMPacket::Software(InstrumentationPort(2), DataValue::U8(0xFF)));
+        writer.add(3, &ITMPacket::Software(InstrumentationPort(3), DataValue::U8(1)));
+        writer.add(4, &ITMPacket::Software(InstrumentationPort(2), DataValue::U8(3)));
+
+        let mut output = Vec::new();
+        writer.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("$var wire 8 ! port3 $end\n$var real 64 \" speed $end\n\
+                                 $var real 64 # pos.x $end\n$var real 64 $ pos.y $end\n"));
+        assert!(output.ends_with("#1\nr2.5 \"\n#2\nr-1 #\nr3 $\n#3\nb1 !\n"));
+    }
+}
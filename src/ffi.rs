@@ -0,0 +1,608 @@
+//! C API of the decoders. Each decoder is an opaque handle that is
+//! given the trace bytes as they arrive, and calls a callback for
+//! every packet decoded from them. Packets are passed as tagged
+//! unions that mirror the Rust enums. Pointers inside a packet are
+//! only valid until the callback returns.
+//!
+//! The header `include/arm_coresight_decoder.h` is generated from
+//! this file with cbindgen, and is checked by `tests/ffi.rs`. Types
+//! and variants are only added at the end, and any other change
+//! increments `acd_abi_version()`.
+
+use std::ffi::CString;
+use std::io::{Cursor, ErrorKind};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use ::itm::types::*;
+use ::itm::parser::parse_one;
+use ::stm::types::*;
+use ::tpiu::types::*;
+use ::tpiu::parser::{decode_frame, FRAME_SYNC, HALFWORD_SYNC};
+use ::tpiu::demux::{Demux, SourceType, SourcePacket};
+
+/// Result of the API functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AcdStatus {
+    Ok = 0,
+    /// A handle, data or callback argument was NULL.
+    NullPointer = -1,
+    /// An argument was out of range.
+    InvalidArgument = -2,
+    /// The callback returned nonzero. The rest of the packets from
+    /// the same call are not reported, but the decoder stays usable.
+    Aborted = -3,
+    /// Internal error in the decoder. The handle should be freed.
+    Panic = -4,
+}
+
+/// Byte buffer owned by the decoder.
+#[repr(C)]
+pub struct AcdBytes {
+    pub data: *const u8,
+    pub length: usize,
+}
+
+/// Data value with its size in bytes: 1, 2 or 4.
+#[repr(C)]
+pub struct AcdDataValue {
+    pub value: u32,
+    pub size: u8,
+}
+
+#[repr(C)]
+pub enum AcdTimestampSync {
+    Synchronous = 0,
+    TimestampDelayed = 1,
+    DataDelayed = 2,
+    BothDelayed = 3,
+}
+
+#[repr(C)]
+pub enum AcdExceptionEvent {
+    Enter = 1,
+    Exit = 2,
+    Resume = 3,
+}
+
+#[repr(C)]
+pub struct AcdEventCounterFlags {
+    pub cpicnt: bool,
+    pub exccnt: bool,
+    pub sleepcnt: bool,
+    pub lsucnt: bool,
+    pub foldcnt: bool,
+    pub postcnt: bool,
+}
+
+/// Mirrors `ITMPacket`.
+#[repr(C)]
+pub enum AcdItmPacket {
+    Synchronization,
+    Overflow,
+    LocalTimestamp { sync: AcdTimestampSync, delta: u32 },
+    GlobalTimestamp { timestamp: u64, known_mask: u64, wrap: bool, clock_change: bool },
+    SoftwarePageNumber { page: u32 },
+    Software { port: u32, value: AcdDataValue },
+    EventCounter { flags: AcdEventCounterFlags },
+    ProgramCounter { address: u32 },
+    SleepMode,
+    Exception { event: AcdExceptionEvent, number: u32 },
+    DataTracePC { comparator: u32, address: u32 },
+    DataTraceOffset { comparator: u32, offset: u32 },
+    DataTraceReadData { comparator: u32, value: AcdDataValue },
+    DataTraceWriteData { comparator: u32, value: AcdDataValue },
+    Extension { data: u32, bitcount: u8, source: u8 },
+    Reserved { header: u8 },
+    Invalid { message: *const c_char },
+}
+
+/// Mirrors `TPIUPacket`.
+#[repr(C)]
+pub enum AcdTpiuPacket {
+    FrameSynchronization,
+    HalfwordSynchronization,
+    Data { source: u8, data: AcdBytes },
+    Trigger { data: AcdBytes },
+    Null { data: AcdBytes },
+    Reserved { data: AcdBytes },
+    Invalid { message: *const c_char },
+}
+
+/// Mirrors `STMPacket`. Timestamps are valid if `has_timestamp`
+/// is set, and `bits` gives the size of data values.
+#[repr(C)]
+pub enum AcdStmPacket {
+    Null,
+    Async,
+    Version { version: u8 },
+    Master { master: u16 },
+    Channel { channel: u16 },
+    Data { master: u16, channel: u16, value: u64, bits: u8, marker: bool, has_timestamp: bool, timestamp: u64 },
+    Flag { master: u16, channel: u16, has_timestamp: bool, timestamp: u64 },
+    Trigger { master: u16, channel: u16, value: u8, has_timestamp: bool, timestamp: u64 },
+    NullTimestamp { timestamp: u64 },
+    Frequency { frequency: u32 },
+    MasterError { value: u8 },
+    GlobalError { value: u8 },
+    Reserved { opcode: u16 },
+    Invalid { message: *const c_char },
+}
+
+/// Mirrors `SourcePacket`, a packet from one TPIU trace source.
+#[repr(C)]
+pub enum AcdSourcePacket {
+    Itm { packet: AcdItmPacket },
+    Stm { packet: AcdStmPacket },
+    Raw { data: AcdBytes },
+    Trigger { data: AcdBytes },
+}
+
+/// Protocol of a trace source in the demux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AcdSourceType {
+    Itm = 0,
+    Stm = 1,
+    Raw = 2,
+}
+
+/// Called with the user pointer, the offset of the packet in the
+/// bytes pushed to the decoder, and the packet. Returning nonzero
+/// stops decoding.
+pub type AcdItmCallback = Option<extern "C" fn(user: *mut c_void, offset: u64,
+                                               packet: *const AcdItmPacket) -> c_int>;
+
+/// Called like `AcdItmCallback`, with the offset of the TPIU frame.
+pub type AcdTpiuCallback = Option<extern "C" fn(user: *mut c_void, offset: u64,
+                                                packet: *const AcdTpiuPacket) -> c_int>;
+
+/// Called like `AcdItmCallback`, with the trace source ID and the
+/// offset of the TPIU frame that completed the packet.
+pub type AcdSourceCallback = Option<extern "C" fn(user: *mut c_void, offset: u64, source: u8,
+                                                  packet: *const AcdSourcePacket) -> c_int>;
+
+/// Holds the C strings of the packets being reported. The string
+/// data stays in place when the vector grows.
+struct Strings(Vec<CString>);
+
+impl Strings {
+    fn add(&mut self, text: &str) -> *const c_char {
+        let string = CString::new(text.replace('\0', "")).unwrap_or_default();
+        self.0.push(string);
+        self.0.last().map_or(ptr::null(), |s| s.as_ptr())
+    }
+}
+
+fn bytes(data: &[u8]) -> AcdBytes {
+    AcdBytes { data: data.as_ptr(), length: data.len() }
+}
+
+fn data_value(value: &DataValue) -> AcdDataValue {
+    AcdDataValue { value: value.to_u32(), size: value.bit_width() / 8 }
+}
+
+fn itm_packet(packet: &ITMPacket, strings: &mut Strings) -> AcdItmPacket {
+    match *packet {
+        ITMPacket::Synchronization => AcdItmPacket::Synchronization,
+        ITMPacket::Overflow => AcdItmPacket::Overflow,
+        ITMPacket::LocalTimestamp(sync, delta) => AcdItmPacket::LocalTimestamp {
+            sync: match sync {
+                TimestampSync::Synchronous => AcdTimestampSync::Synchronous,
+                TimestampSync::TimestampDelayed => AcdTimestampSync::TimestampDelayed,
+                TimestampSync::DataDelayed => AcdTimestampSync::DataDelayed,
+                TimestampSync::BothDelayed => AcdTimestampSync::BothDelayed,
+            },
+            delta: delta.0,
+        },
+        ITMPacket::GlobalTimestamp(ref value) => AcdItmPacket::GlobalTimestamp {
+            timestamp: value.timestamp, known_mask: value.known_mask,
+            wrap: value.wrap, clock_change: value.clock_change,
+        },
+        ITMPacket::SoftwarePageNumber(page) => AcdItmPacket::SoftwarePageNumber { page: page.0 },
+        ITMPacket::Software(port, ref value) => AcdItmPacket::Software { port: port.0, value: data_value(value) },
+        ITMPacket::EventCounter(ref flags) => AcdItmPacket::EventCounter {
+            flags: AcdEventCounterFlags {
+                cpicnt: flags.cpicnt, exccnt: flags.exccnt, sleepcnt: flags.sleepcnt,
+                lsucnt: flags.lsucnt, foldcnt: flags.foldcnt, postcnt: flags.postcnt,
+            },
+        },
+        ITMPacket::ProgramCounter(address) => AcdItmPacket::ProgramCounter { address: address.0 },
+        ITMPacket::SleepMode => AcdItmPacket::SleepMode,
+        ITMPacket::Exception(event, number) => AcdItmPacket::Exception {
+            event: match event {
+                ExceptionEvent::Enter => AcdExceptionEvent::Enter,
+                ExceptionEvent::Exit => AcdExceptionEvent::Exit,
+                ExceptionEvent::Resume => AcdExceptionEvent::Resume,
+            },
+            number: number.0,
+        },
+        ITMPacket::DataTracePC(comp, address) => AcdItmPacket::DataTracePC { comparator: comp.0, address: address.0 },
+        ITMPacket::DataTraceOffset(comp, offset) => AcdItmPacket::DataTraceOffset { comparator: comp.0, offset: offset.0 },
+        ITMPacket::DataTraceReadData(comp, ref value) => {
+            AcdItmPacket::DataTraceReadData { comparator: comp.0, value: data_value(value) }
+        },
+        ITMPacket::DataTraceWriteData(comp, ref value) => {
+            AcdItmPacket::DataTraceWriteData { comparator: comp.0, value: data_value(value) }
+        },
+        ITMPacket::Extension(ref info) => AcdItmPacket::Extension {
+            data: info.data, bitcount: info.bitcount, source: info.source,
+        },
+        ITMPacket::Reserved(header) => AcdItmPacket::Reserved { header },
+        ITMPacket::Invalid(ref message) => AcdItmPacket::Invalid { message: strings.add(message) },
+    }
+}
+
+fn tpiu_packet(packet: &TPIUPacket, strings: &mut Strings) -> AcdTpiuPacket {
+    match *packet {
+        TPIUPacket::FrameSynchronization => AcdTpiuPacket::FrameSynchronization,
+        TPIUPacket::HalfwordSynchronization => AcdTpiuPacket::HalfwordSynchronization,
+        TPIUPacket::Data(id, ref data) => AcdTpiuPacket::Data { source: id.0, data: bytes(data) },
+        TPIUPacket::Trigger(ref data) => AcdTpiuPacket::Trigger { data: bytes(data) },
+        TPIUPacket::Null(ref data) => AcdTpiuPacket::Null { data: bytes(data) },
+        TPIUPacket::Reserved(ref data) => AcdTpiuPacket::Reserved { data: bytes(data) },
+        TPIUPacket::Invalid(ref message) => AcdTpiuPacket::Invalid { message: strings.add(message) },
+    }
+}
+
+fn stm_packet(packet: &STMPacket, strings: &mut Strings) -> AcdStmPacket {
+    match *packet {
+        STMPacket::Null => AcdStmPacket::Null,
+        STMPacket::Async => AcdStmPacket::Async,
+        STMPacket::Version(version) => AcdStmPacket::Version { version },
+        STMPacket::Master(master) => AcdStmPacket::Master { master: master.0 },
+        STMPacket::Channel(channel) => AcdStmPacket::Channel { channel: channel.0 },
+        STMPacket::Data { master, channel, value, marker, timestamp } => AcdStmPacket::Data {
+            master: master.0, channel: channel.0, value: value.to_u64(),
+            bits: value.bit_width(),
+            marker, has_timestamp: timestamp.is_some(), timestamp: timestamp.unwrap_or(0),
+        },
+        STMPacket::Flag { master, channel, timestamp } => AcdStmPacket::Flag {
+            master: master.0, channel: channel.0,
+            has_timestamp: timestamp.is_some(), timestamp: timestamp.unwrap_or(0),
+        },
+        STMPacket::Trigger { master, channel, value, timestamp } => AcdStmPacket::Trigger {
+            master: master.0, channel: channel.0, value,
+            has_timestamp: timestamp.is_some(), timestamp: timestamp.unwrap_or(0),
+        },
+        STMPacket::NullTimestamp(timestamp) => AcdStmPacket::NullTimestamp { timestamp },
+        STMPacket::Frequency(frequency) => AcdStmPacket::Frequency { frequency },
+        STMPacket::MasterError(value) => AcdStmPacket::MasterError { value },
+        STMPacket::GlobalError(value) => AcdStmPacket::GlobalError { value },
+        STMPacket::Reserved(opcode) => AcdStmPacket::Reserved { opcode },
+        STMPacket::Invalid(ref message) => AcdStmPacket::Invalid { message: strings.add(message) },
+    }
+}
+
+fn source_packet(packet: &SourcePacket, strings: &mut Strings) -> AcdSourcePacket {
+    match *packet {
+        SourcePacket::ITM(ref packet) => AcdSourcePacket::Itm { packet: itm_packet(packet, strings) },
+        SourcePacket::STM(ref packet) => AcdSourcePacket::Stm { packet: stm_packet(packet, strings) },
+        SourcePacket::Raw(ref data) => AcdSourcePacket::Raw { data: bytes(data) },
+        SourcePacket::Trigger(ref data) => AcdSourcePacket::Trigger { data: bytes(data) },
+    }
+}
+
+/// Splits pushed bytes to TPIU frames, like `tpiu::parser::Parser`.
+struct FrameStream {
+    initial: TraceSourceID,
+    source: TraceSourceID,
+    pending: Vec<u8>,
+    /// Offset of the first pending byte in the stream.
+    offset: u64,
+}
+
+impl FrameStream {
+    fn new(source: TraceSourceID) -> FrameStream {
+        FrameStream { initial: source, source, pending: Vec::new(), offset: 0 }
+    }
+
+    fn reset(&mut self) {
+        *self = FrameStream::new(self.initial);
+    }
+
+    /// Decodes the frames that have been received completely.
+    fn push(&mut self, data: &[u8]) -> Vec<(u64, TPIUPacket)> {
+        self.pending.extend_from_slice(data);
+        let mut output = Vec::new();
+        let mut pos = 0;
+        while self.pending.len() - pos >= 16 {
+            let offset = self.offset + pos as u64;
+            let rest = &self.pending[pos..];
+            if rest.starts_with(&FRAME_SYNC) {
+                output.push((offset, TPIUPacket::FrameSynchronization));
+                pos += 4;
+            } else if rest.starts_with(&HALFWORD_SYNC) {
+                output.push((offset, TPIUPacket::HalfwordSynchronization));
+                pos += 2;
+            } else {
+                let mut frame = [0; 16];
+                frame.copy_from_slice(&rest[..16]);
+                output.extend(decode_frame(&frame, &mut self.source).into_iter().map(|p| (offset, p)));
+                pos += 16;
+            }
+        }
+        self.pending.drain(..pos);
+        self.offset += pos as u64;
+        output
+    }
+}
+
+/// ITM packet decoder handle.
+pub struct AcdItmDecoder {
+    buffer: Vec<u8>,
+    /// Offset of the first buffered byte in the stream.
+    offset: u64,
+}
+
+/// TPIU frame decoder handle.
+pub struct AcdTpiuDecoder {
+    frames: FrameStream,
+}
+
+/// TPIU decoder handle that also decodes the data of each source.
+pub struct AcdDemux {
+    frames: FrameStream,
+    demux: Demux,
+}
+
+/// Runs the body of an API function, converting a panic to an
+/// error instead of unwinding into C.
+fn guard<F: FnOnce() -> AcdStatus>(f: F) -> AcdStatus {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(AcdStatus::Panic)
+}
+
+unsafe fn input<'a>(data: *const u8, length: usize) -> Option<&'a [u8]> {
+    if length == 0 {
+        Some(&[])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(data, length))
+    }
+}
+
+fn callback_status(result: c_int) -> Result<(), AcdStatus> {
+    if result == 0 { Ok(()) } else { Err(AcdStatus::Aborted) }
+}
+
+/// Version of the C API, incremented on incompatible changes.
+#[no_mangle]
+pub extern "C" fn acd_abi_version() -> u32 {
+    1
+}
+
+/// Version of the library, as a static string.
+#[no_mangle]
+pub extern "C" fn acd_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Creates an ITM decoder. The stream must start at a packet
+/// boundary, e.g. at a synchronization packet.
+#[no_mangle]
+pub extern "C" fn acd_itm_decoder_new() -> *mut AcdItmDecoder {
+    Box::into_raw(Box::new(AcdItmDecoder { buffer: Vec::new(), offset: 0 }))
+}
+
+/// # Safety
+/// The decoder must come from `acd_itm_decoder_new()`, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_itm_decoder_free(decoder: *mut AcdItmDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Drops partially received data and restarts offsets from zero.
+///
+/// # Safety
+/// The decoder must be a valid handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_itm_decoder_reset(decoder: *mut AcdItmDecoder) -> AcdStatus {
+    match decoder.as_mut() {
+        Some(decoder) => {
+            decoder.buffer.clear();
+            decoder.offset = 0;
+            AcdStatus::Ok
+        },
+        None => AcdStatus::NullPointer,
+    }
+}
+
+/// Decodes the pushed bytes and calls the callback for each complete
+/// packet. A partial packet at the end is kept for the next call.
+///
+/// # Safety
+/// The decoder must be a valid handle and data must point to length
+/// bytes. The decoder may not be used from the callback.
+#[no_mangle]
+pub unsafe extern "C" fn acd_itm_decoder_push(decoder: *mut AcdItmDecoder, data: *const u8, length: usize,
+                                              callback: AcdItmCallback, user: *mut c_void) -> AcdStatus {
+    let (decoder, data, callback) = match (decoder.as_mut(), input(data, length), callback) {
+        (Some(decoder), Some(data), Some(callback)) => (decoder, data, callback),
+        _ => return AcdStatus::NullPointer,
+    };
+
+    guard(|| {
+        decoder.buffer.extend_from_slice(data);
+        let mut packets = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let mut cursor = Cursor::new(&decoder.buffer[consumed..]);
+            let packet = match parse_one(&mut cursor) {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => ITMPacket::Invalid(e.to_string()),
+            };
+            packets.push((decoder.offset + consumed as u64, packet));
+            consumed += cursor.position() as usize;
+        }
+        decoder.buffer.drain(..consumed);
+        decoder.offset += consumed as u64;
+
+        let mut strings = Strings(Vec::new());
+        let result = packets.iter().try_for_each(|&(offset, ref packet)| {
+            let packet = itm_packet(packet, &mut strings);
+            callback_status(callback(user, offset, &packet))
+        });
+        result.err().unwrap_or(AcdStatus::Ok)
+    })
+}
+
+/// Creates a TPIU decoder. The stream must start at a frame boundary,
+/// and data before the first ID change belongs to the given source.
+#[no_mangle]
+pub extern "C" fn acd_tpiu_decoder_new(source: u8) -> *mut AcdTpiuDecoder {
+    Box::into_raw(Box::new(AcdTpiuDecoder { frames: FrameStream::new(TraceSourceID(source)) }))
+}
+
+/// # Safety
+/// The decoder must come from `acd_tpiu_decoder_new()`, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_tpiu_decoder_free(decoder: *mut AcdTpiuDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Drops partially received frames and restores the initial source.
+///
+/// # Safety
+/// The decoder must be a valid handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_tpiu_decoder_reset(decoder: *mut AcdTpiuDecoder) -> AcdStatus {
+    match decoder.as_mut() {
+        Some(decoder) => {
+            decoder.frames.reset();
+            AcdStatus::Ok
+        },
+        None => AcdStatus::NullPointer,
+    }
+}
+
+/// Decodes the complete frames and calls the callback for each packet.
+///
+/// # Safety
+/// The decoder must be a valid handle and data must point to length
+/// bytes. The decoder may not be used from the callback.
+#[no_mangle]
+pub unsafe extern "C" fn acd_tpiu_decoder_push(decoder: *mut AcdTpiuDecoder, data: *const u8, length: usize,
+                                               callback: AcdTpiuCallback, user: *mut c_void) -> AcdStatus {
+    let (decoder, data, callback) = match (decoder.as_mut(), input(data, length), callback) {
+        (Some(decoder), Some(data), Some(callback)) => (decoder, data, callback),
+        _ => return AcdStatus::NullPointer,
+    };
+
+    guard(|| {
+        let mut strings = Strings(Vec::new());
+        let result = decoder.frames.push(data).iter().try_for_each(|&(offset, ref packet)| {
+            let packet = tpiu_packet(packet, &mut strings);
+            callback_status(callback(user, offset, &packet))
+        });
+        result.err().unwrap_or(AcdStatus::Ok)
+    })
+}
+
+/// Creates a demux with the initial source like in
+/// `acd_tpiu_decoder_new()`. Data of each source is passed as raw
+/// data until it is configured with `acd_demux_add_source()`.
+#[no_mangle]
+pub extern "C" fn acd_demux_new(source: u8) -> *mut AcdDemux {
+    Box::into_raw(Box::new(AcdDemux { frames: FrameStream::new(TraceSourceID(source)), demux: Demux::new() }))
+}
+
+/// # Safety
+/// The demux must come from `acd_demux_new()`, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_demux_free(demux: *mut AcdDemux) {
+    if !demux.is_null() {
+        drop(Box::from_raw(demux));
+    }
+}
+
+/// Selects the protocol of a trace source ID, from 1 to 0x6F.
+///
+/// # Safety
+/// The demux must be a valid handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn acd_demux_add_source(demux: *mut AcdDemux, source: u8,
+                                              source_type: AcdSourceType) -> AcdStatus {
+    let demux = match demux.as_mut() {
+        Some(demux) => demux,
+        None => return AcdStatus::NullPointer,
+    };
+    if !(0x01..=0x6F).contains(&source) {
+        return AcdStatus::InvalidArgument;
+    }
+    let source_type = match source_type {
+        AcdSourceType::Itm => SourceType::ITM,
+        AcdSourceType::Stm => SourceType::STM,
+        AcdSourceType::Raw => SourceType::Raw,
+    };
+    demux.demux.add_source(TraceSourceID(source), source_type);
+    AcdStatus::Ok
+}
+
+/// Decodes the complete frames and the packets of the configured
+/// sources, and calls the callback for each packet.
+///
+/// # Safety
+/// The demux must be a valid handle and data must point to length
+/// bytes. The demux may not be used from the callback.
+#[no_mangle]
+pub unsafe extern "C" fn acd_demux_push(demux: *mut AcdDemux, data: *const u8, length: usize,
+                                        callback: AcdSourceCallback, user: *mut c_void) -> AcdStatus {
+    let (demux, data, callback) = match (demux.as_mut(), input(data, length), callback) {
+        (Some(demux), Some(data), Some(callback)) => (demux, data, callback),
+        _ => return AcdStatus::NullPointer,
+    };
+
+    guard(|| {
+        let mut strings = Strings(Vec::new());
+        let mut result = Ok(());
+        for (offset, packet) in demux.frames.push(data) {
+            for (id, packet) in demux.demux.push(packet) {
+                if result.is_ok() {
+                    let packet = source_packet(&packet, &mut strings);
+                    result = callback_status(callback(user, offset, id.0, &packet));
+                }
+            }
+        }
+        result.err().unwrap_or(AcdStatus::Ok)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn collect(user: *mut c_void, offset: u64, packet: *const AcdItmPacket) -> c_int {
+        let packets = unsafe { &mut *(user as *mut Vec<(u64, u32)>) };
+        if let AcdItmPacket::Software { port, ref value } = unsafe { &*packet } {
+            packets.push((offset, *port << 16 | value.value));
+        }
+        (packets.len() >= 3) as c_int
+    }
+
+    #[test]
+    fn test_itm_push() {
+        let data = [0x01, 0x41, 0x0A, 0x34, 0x12, 0x01, 0x42, 0x01, 0x43];
+        let mut packets: Vec<(u64, u32)> = Vec::new();
+        let user = &mut packets as *mut Vec<(u64, u32)> as *mut c_void;
+        unsafe {
+            let decoder = acd_itm_decoder_new();
+            // Split in the middle of the second packet.
+            assert_eq!(acd_itm_decoder_push(decoder, data.as_ptr(), 3, Some(collect), user), AcdStatus::Ok);
+            assert_eq!(packets, vec![(0, 0x41)]);
+            assert_eq!(acd_itm_decoder_push(decoder, data[3..].as_ptr(), 6, Some(collect), user),
+                       AcdStatus::Aborted);
+            assert_eq!(packets, vec![(0, 0x41), (2, 0x11234), (5, 0x42)]);
+            assert_eq!(acd_itm_decoder_push(decoder, ptr::null(), 1, Some(collect), user),
+                       AcdStatus::NullPointer);
+            acd_itm_decoder_free(decoder);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Tracks the function call stack from branch records.
+
+use ::itm::types::{Address, ExceptionNumber};
+use super::types::*;
+
+/// Limit for stack depth, in case returns are lost and the
+/// stack would grow without bound. Beyond it the oldest frame is
+/// dropped.
+pub const MAX_DEPTH: usize = 1024;
+
+/// Entry in the call stack.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Frame {
+    /// Function call from the given call site.
+    Call(Address),
+
+    /// Exception that preempted the code at the given address.
+    Exception(ExceptionNumber, Address),
+}
+
+/// Call stack, with outermost frame first. Returns that do not
+/// match a known call are ignored, as the trace may start in the
+/// middle of a function.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack { frames: Vec::new() }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Forgets the stack contents, e.g. after trace discontinuity.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() >= MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Updates the stack according to a branch. Returns the frame
+    /// that was removed from the stack, if any.
+    pub fn branch(&mut self, branch: &BranchRecord) -> Option<Frame> {
+        if !branch.taken {
+            return None;
+        }
+
+        match branch.kind {
+            BranchKind::Jump => None,
+            BranchKind::Call => {
+                self.push(Frame::Call(branch.source));
+                None
+            },
+            BranchKind::Exception(number) => {
+                self.push(Frame::Exception(number, branch.source));
+                None
+            },
+            BranchKind::Return => {
+                match self.frames.last() {
+                    Some(&Frame::Call(_)) => self.frames.pop(),
+                    _ => None
+                }
+            },
+            BranchKind::ExceptionReturn => {
+                // Unwinds also any calls that were not returned from,
+                // e.g. when the handler was left by longjmp.
+                match self.frames.iter().rposition(|f| matches!(*f, Frame::Exception(_, _))) {
+                    Some(pos) => {
+                        let frame = self.frames[pos];
+                        self.frames.truncate(pos);
+                        Some(frame)
+                    },
+                    None => None
+                }
+            }
+        }
+    }
+}
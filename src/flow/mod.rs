@@ -0,0 +1,7 @@
+//! Program flow model shared by the instruction trace decoders.
+//! Decoders turn their protocol specific packets into these events,
+//! and the analyses work on the events only.
+
+pub mod types;
+pub mod callstack;
+pub mod thumb;
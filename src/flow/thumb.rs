@@ -0,0 +1,64 @@
+//! Thumb instruction set decoding, as much as is needed for
+//! following program flow.
+
+use super::types::BranchKind;
+
+/// Returns the size in bytes of the Thumb instruction that starts
+/// with the given halfword.
+pub fn instruction_size(halfword: u16) -> u32 {
+    match halfword >> 11 {
+        0b11101 ..= 0b11111 => 4,
+        _ => 2
+    }
+}
+
+/// Returns true if the instruction is a conditional branch:
+/// B<cond>, B<cond>.W, CBZ or CBNZ. The second halfword is only
+/// used for 32-bit instructions.
+pub fn is_conditional_branch(first: u16, second: u16) -> bool {
+    // Condition codes 0b1110 and 0b1111 encode other instructions.
+    let conditional = |cond: u16| cond < 0b1110;
+
+    if first & 0xF000 == 0xD000 {
+        conditional((first >> 8) & 0xF)
+    } else if first & 0xF500 == 0xB100 {
+        true
+    } else if first & 0xF800 == 0xF000 && second & 0xD000 == 0x8000 {
+        conditional((first >> 6) & 0xF)
+    } else {
+        false
+    }
+}
+
+/// Classifies instructions that can write to PC. Returns None for
+/// instructions that always continue to the next instruction.
+pub fn classify_branch(first: u16, second: u16) -> Option<BranchKind> {
+    if is_conditional_branch(first, second) {
+        return Some(BranchKind::Jump);
+    }
+
+    match first {
+        // BX LR, MOV PC, LR
+        0x4770 | 0x46F7 => Some(BranchKind::Return),
+        // BLX Rm
+        f if f & 0xFF87 == 0x4780 => Some(BranchKind::Call),
+        // BX Rm, MOV PC, Rm, ADD PC, Rm
+        f if f & 0xFF87 == 0x4700 || f & 0xFF87 == 0x4687 || f & 0xFF87 == 0x4487 => Some(BranchKind::Jump),
+        // POP {..., PC}
+        f if f & 0xFF00 == 0xBD00 => Some(BranchKind::Return),
+        // B
+        f if f & 0xF800 == 0xE000 => Some(BranchKind::Jump),
+        // BL
+        f if f & 0xF800 == 0xF000 && second & 0xD000 == 0xD000 => Some(BranchKind::Call),
+        // B.W
+        f if f & 0xF800 == 0xF000 && second & 0xD000 == 0x9000 => Some(BranchKind::Jump),
+        // POP.W / LDMIA.W SP! with PC in register list
+        0xE8BD if second & 0x8000 != 0 => Some(BranchKind::Return),
+        // TBB, TBH
+        f if f & 0xFFF0 == 0xE8D0 && second & 0xFFE0 == 0xF000 => Some(BranchKind::Jump),
+        // LDR.W PC, [...]
+        f if f & 0xFFF0 == 0xF8D0 && second >> 12 == 0xF => Some(BranchKind::Jump),
+        f if f & 0xFFF0 == 0xF850 && second >> 12 == 0xF => Some(BranchKind::Jump),
+        _ => None
+    }
+}
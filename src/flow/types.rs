@@ -0,0 +1,74 @@
+//! Program flow events reconstructed from instruction trace.
+
+use ::itm::types::{Address, ExceptionNumber};
+
+/// Reconstructed program flow event.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FlowEvent {
+    /// A sequence of instructions was executed without branches
+    /// in between.
+    Range(InstructionRange),
+
+    /// A branch instruction was executed, either taken or not taken.
+    /// Taken branches occur between two ranges, from the last
+    /// instruction of the previous range to the start of the next.
+    Branch(BranchRecord),
+
+    /// Processor cycles spent since the previous cycle count,
+    /// in cycle-accurate trace.
+    Cycles(u32),
+
+    /// Absolute timestamp value from the trace source.
+    Timestamp(u64),
+
+    /// Trace data was lost and program flow is unknown until
+    /// the next address.
+    Discontinuity,
+}
+
+/// Range of sequentially executed instructions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct InstructionRange {
+    /// Address of the first instruction.
+    pub start: Address,
+
+    /// Address after the last instruction.
+    pub end: Address,
+
+    /// Number of instructions executed.
+    pub instructions: u32,
+}
+
+/// Single branch from source address to destination.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct BranchRecord {
+    /// Address of the branch instruction.
+    pub source: Address,
+
+    /// Target address of the branch.
+    pub destination: Address,
+
+    pub kind: BranchKind,
+
+    /// False for conditional branches that were not taken.
+    pub taken: bool,
+}
+
+/// Type of branch, used for call stack tracking.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum BranchKind {
+    /// Jump within or between functions, including tail calls.
+    Jump,
+
+    /// Function call that stores return address in LR.
+    Call,
+
+    /// Return from function.
+    Return,
+
+    /// Exception entry, source is the preempted instruction.
+    Exception(ExceptionNumber),
+
+    /// Return from exception handler.
+    ExceptionReturn,
+}
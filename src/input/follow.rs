@@ -0,0 +1,328 @@
+//! Follows a capture file that is still being written, like `tail -f`.
+//! At end of file the reader waits for more data instead of stopping,
+//! so that a half-written packet is completed by the next write.
+//! Named pipes are followed the same way, waiting for a new writer
+//! when the previous one closes.
+//!
+//! If the file is truncated, it is read again from the start. If it
+//! is replaced by a new file with the same name, the rest of the old
+//! file is read and then the new file is opened.
+
+use std::io::{Read, Seek, SeekFrom, Cursor, Error, ErrorKind};
+use std::fs::{self, File, Metadata};
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+use ::itm::types::ITMPacket;
+use ::itm::parser::parse_one;
+
+/// Reason why reading started again from the beginning of a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Restart {
+    Truncated,
+    Replaced,
+}
+
+/// Result of one read from the followed file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Chunk {
+    Data(Vec<u8>),
+    Restart(Restart),
+}
+
+/// Identifies the file independent of its name.
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+pub struct FollowReader {
+    path: PathBuf,
+    file: File,
+    identity: Option<(u64, u64)>,
+    position: u64,
+    interval: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl FollowReader {
+    /// Opens the file, waiting until it exists.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FollowReader, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = FollowReader::wait_open(&path, Duration::from_millis(100), None)?;
+        let identity = identity(&file.metadata()?);
+        Ok(FollowReader {
+            path, file, identity, position: 0,
+            interval: Duration::from_millis(100), idle_timeout: None
+        })
+    }
+
+    fn wait_open(path: &Path, interval: Duration, timeout: Option<Duration>) -> Result<File, Error> {
+        let start = Instant::now();
+        loop {
+            let expired = timeout.is_some_and(|t| start.elapsed() >= t);
+            match File::open(path) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound && !expired => thread::sleep(interval),
+                result => return result
+            }
+        }
+    }
+
+    /// Sets how often the file is checked for new data.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Ends the stream if no new data arrives within the timeout.
+    /// By default the file is followed forever.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Position in the current file.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Checks whether the file was truncated or replaced.
+    fn check_restart(&mut self) -> Result<Option<Restart>, Error> {
+        let current = self.file.metadata()?;
+        if current.is_file() && current.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            return Ok(Some(Restart::Truncated));
+        }
+
+        let named = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        if self.identity.is_some() && identity(&named) != self.identity {
+            self.file = FollowReader::wait_open(&self.path, self.interval, Some(self.interval))?;
+            self.identity = identity(&self.file.metadata()?);
+            self.position = 0;
+            return Ok(Some(Restart::Replaced));
+        }
+        Ok(None)
+    }
+
+    /// Waits for the next data or restart. Returns None when the idle
+    /// timeout expires.
+    pub fn read_chunk(&mut self) -> Result<Option<Chunk>, Error> {
+        let start = Instant::now();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let count = match self.file.read(&mut buffer) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => result?
+            };
+            if count > 0 {
+                self.position += count as u64;
+                return Ok(Some(Chunk::Data(buffer[..count].to_vec())));
+            }
+
+            if let Some(restart) = self.check_restart()? {
+                return Ok(Some(Chunk::Restart(restart)));
+            }
+            if self.idle_timeout.is_some_and(|t| start.elapsed() >= t) {
+                return Ok(None);
+            }
+            thread::sleep(self.interval);
+        }
+    }
+}
+
+/// Reads the data across restarts, for use with parsers that take a
+/// `Read`. Use `FollowParser` for ITM to resynchronize at restarts.
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let start = Instant::now();
+        loop {
+            let count = self.file.read(buf)?;
+            if count > 0 || buf.is_empty() {
+                self.position += count as u64;
+                return Ok(count);
+            }
+            if self.check_restart()?.is_none() {
+                if self.idle_timeout.is_some_and(|t| start.elapsed() >= t) {
+                    return Ok(0);
+                }
+                thread::sleep(self.interval);
+            }
+        }
+    }
+}
+
+/// ITM packet, or a marker that decoding restarted from a new file.
+#[derive(Debug, Eq, PartialEq)]
+pub enum FollowPacket {
+    ITM(ITMPacket),
+    Restart(Restart),
+}
+
+/// Decodes ITM packets from a followed file. Bytes of a partially
+/// written packet are kept until the rest of it arrives, and dropped
+/// if the file restarts.
+pub struct FollowParser {
+    reader: FollowReader,
+    buffer: Vec<u8>,
+    output: VecDeque<FollowPacket>,
+    error: Option<Error>,
+}
+
+impl FollowParser {
+    pub fn new(reader: FollowReader) -> FollowParser {
+        FollowParser { reader, buffer: Vec::new(), output: VecDeque::new(), error: None }
+    }
+
+    pub fn reader(&mut self) -> &mut FollowReader {
+        &mut self.reader
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    fn parse(&mut self) {
+        let mut consumed = 0;
+        loop {
+            let mut cursor = Cursor::new(&self.buffer[consumed..]);
+            match parse_one(&mut cursor) {
+                Ok(packet) => {
+                    consumed += cursor.position() as usize;
+                    self.output.push_back(FollowPacket::ITM(packet));
+                },
+                Err(_) => break
+            }
+        }
+        self.buffer.drain(..consumed);
+    }
+}
+
+impl Iterator for FollowParser {
+    type Item = FollowPacket;
+    fn next(&mut self) -> Option<FollowPacket> {
+        while self.output.is_empty() {
+            match self.reader.read_chunk() {
+                Ok(Some(Chunk::Data(data))) => {
+                    self.buffer.extend(data);
+                    self.parse();
+                },
+                Ok(Some(Chunk::Restart(restart))) => {
+                    self.buffer.clear();
+                    self.output.push_back(FollowPacket::Restart(restart));
+                },
+                Ok(None) => return None,
+                Err(e) => {self.error = Some(e); return None}
+            }
+        }
+        self.output.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::fs::OpenOptions;
+    use ::itm::types::*;
+
+    #[test]
+    fn test_follow() {
+        let path = ::std::env::temp_dir().join(format!("follow_test_{}.bin", ::std::process::id()));
+        fs::write(&path, [0x01, 0x41, 0x17, 0x16]).unwrap();
+
+        let mut reader = FollowReader::open(&path).unwrap();
+        reader.set_interval(Duration::from_millis(5));
+        reader.set_idle_timeout(Some(Duration::from_millis(200)));
+        let mut parser = FollowParser::new(reader);
+        let software = |value| FollowPacket::ITM(ITMPacket::Software(InstrumentationPort(0), DataValue::U8(value)));
+        assert_eq!(parser.next(), Some(software(0x41)));
+
+        // Rest of the partial packet.
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0x02, 0x00, 0x08]).unwrap();
+                thread::sleep(Duration::from_millis(20));
+                fs::write(&path, [0x01, 0x42]).unwrap();
+            })
+        };
+
+        assert_eq!(parser.next(), Some(FollowPacket::ITM(ITMPacket::ProgramCounter(Address(0x08000216)))));
+        assert_eq!(parser.next(), Some(FollowPacket::Restart(Restart::Truncated)));
+        assert_eq!(parser.next(), Some(software(0x42)));
+        assert_eq!(parser.next(), None);
+        writer.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replaced() {
+        let path = ::std::env::temp_dir().join(format!("follow_replaced_{}.bin", ::std::process::id()));
+        let new = path.with_extension("new");
+        fs::write(&path, [0x01, 0x41]).unwrap();
+
+        let mut reader = FollowReader::open(&path).unwrap();
+        reader.set_interval(Duration::from_millis(5));
+        reader.set_idle_timeout(Some(Duration::from_millis(200)));
+        assert_eq!(reader.read_chunk().unwrap(), Some(Chunk::Data(vec![0x01, 0x41])));
+
+        // Data appended to the old file is read before switching.
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0x01, 0x42]).unwrap();
+        fs::write(&new, [0x01, 0x43]).unwrap();
+        fs::rename(&new, &path).unwrap();
+
+        assert_eq!(reader.read_chunk().unwrap(), Some(Chunk::Data(vec![0x01, 0x42])));
+        assert_eq!(reader.read_chunk().unwrap(), Some(Chunk::Restart(Restart::Replaced)));
+        assert_eq!(reader.read_chunk().unwrap(), Some(Chunk::Data(vec![0x01, 0x43])));
+        assert_eq!(reader.position(), 2);
+        assert_eq!(reader.read_chunk().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo() {
+        extern crate libc;
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = ::std::env::temp_dir().join(format!("follow_fifo_{}", ::std::process::id()));
+        let name = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+
+        // Two writers one after the other.
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                OpenOptions::new().write(true).open(&path).unwrap().write_all(&[0x01, 0x41]).unwrap();
+                thread::sleep(Duration::from_millis(50));
+                OpenOptions::new().write(true).open(&path).unwrap().write_all(&[0x01, 0x42]).unwrap();
+            })
+        };
+
+        let mut reader = FollowReader::open(&path).unwrap();
+        reader.set_interval(Duration::from_millis(5));
+        reader.set_idle_timeout(Some(Duration::from_millis(500)));
+        let mut parser = FollowParser::new(reader);
+        let software = |value| FollowPacket::ITM(ITMPacket::Software(InstrumentationPort(0), DataValue::U8(value)));
+        assert_eq!(parser.next(), Some(software(0x41)));
+        assert_eq!(parser.next(), Some(software(0x42)));
+        writer.join().unwrap();
+        assert_eq!(parser.next(), None);
+        assert!(parser.error().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
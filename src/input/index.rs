@@ -0,0 +1,414 @@
+//! Index of synchronization points in a large capture file, so that
+//! decoding can start near any offset or time without replaying the
+//! whole file. The file is memory mapped and the index is stored in
+//! a sidecar file next to it, named by appending `.idx`.
+//!
+//! Each sync point stores the decoder state needed to resume there:
+//! the active TPIU source ID, the ITM stimulus port page and the
+//! accumulated ITM local timestamp.
+//!
+//! ETM and PTM streams are indexed at A-sync packets only. Finding
+//! I-sync packets would need decoding of the ETM packets, which this
+//! crate does not do, and a decoder resuming at an A-sync waits for
+//! the next I-sync anyway.
+
+extern crate memmap2;
+extern crate byteorder;
+
+use std::io::{Read, Write, Cursor, Error, ErrorKind};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use self::memmap2::Mmap;
+use self::byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian as LE};
+use ::itm::types::{ITMPacket, InstrumentationPort};
+use ::itm::parser::{Parser as ITMParser, parse_one};
+use ::itm::timestamp::Timestamper;
+use ::itm::schema::{Schema, SignalDecoder};
+use ::tpiu::types::TraceSourceID;
+use ::tpiu::parser::{Parser as TPIUParser, decode_frame, FRAME_SYNC, HALFWORD_SYNC};
+
+const MAGIC: &[u8; 8] = b"ACDIDX01";
+
+/// Protocol at the top level of the capture file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// Raw ITM stream, e.g. SWO without the formatter.
+    ITM,
+    /// TPIU formatted frames.
+    TPIU,
+    /// Raw ETM or PTM stream.
+    ETM,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SyncKind {
+    /// Start of the file, with initial decoder state.
+    Start,
+    /// ITM synchronization packet.
+    ITM,
+    /// TPIU full frame synchronization.
+    TPIUFrame,
+    /// ETM/PTM alignment synchronization, at least five zero bytes
+    /// followed by 0x80.
+    ETMAsync,
+}
+
+/// Position where decoding can start.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SyncPoint {
+    pub offset: u64,
+    pub kind: SyncKind,
+    /// TPIU source ID active at the start of the next frame.
+    pub source: TraceSourceID,
+    /// ITM stimulus port page, as the number of its first port.
+    pub page: u8,
+    /// ITM local timestamp accumulated since the start of the file.
+    pub timestamp: Option<u64>,
+}
+
+impl SyncPoint {
+    /// Signal decoder for the ITM packets from this point onwards,
+    /// on the stimulus port page that was active here.
+    pub fn signal_decoder(&self, schema: Schema) -> SignalDecoder {
+        let mut decoder = SignalDecoder::new(schema);
+        decoder.set_page(InstrumentationPort(self.page as u32));
+        decoder
+    }
+}
+
+/// Sync points of one capture file, sorted by offset.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Index {
+    pub format: TraceFormat,
+    /// Length of the indexed file, for detecting a stale index.
+    pub length: u64,
+    pub points: Vec<SyncPoint>,
+}
+
+impl Index {
+    /// Scans the data for sync points, keeping at most one point per
+    /// `spacing` bytes to limit the index size.
+    pub fn build(data: &[u8], format: TraceFormat, spacing: u64) -> Index {
+        let start = SyncPoint {
+            offset: 0, kind: SyncKind::Start, source: TraceSourceID(0), page: 0,
+            timestamp: if format == TraceFormat::ITM { Some(0) } else { None }
+        };
+        let mut index = Index { format, length: data.len() as u64, points: vec![start] };
+        {
+            let mut add = |point: SyncPoint| {
+                let accept = match index.points.last() {
+                    Some(last) => point.offset >= last.offset + spacing,
+                    None => true
+                };
+                if accept {
+                    index.points.push(point);
+                }
+            };
+
+            match format {
+                TraceFormat::ITM => scan_itm(data, &mut add),
+                TraceFormat::TPIU => scan_tpiu(data, &mut add),
+                TraceFormat::ETM => scan_etm(data, &mut add),
+            }
+        }
+        index
+    }
+
+    /// Last sync point at or before the offset.
+    pub fn at_offset(&self, offset: u64) -> Option<&SyncPoint> {
+        let count = self.points.partition_point(|p| p.offset <= offset);
+        if count > 0 { Some(&self.points[count - 1]) } else { None }
+    }
+
+    /// Last sync point with timestamp at or before the time.
+    pub fn at_time(&self, time: u64) -> Option<&SyncPoint> {
+        self.points.iter().rev().find(|p| p.timestamp.is_some_and(|t| t <= time))
+    }
+
+    pub fn write_to<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        output.write_all(MAGIC)?;
+        output.write_u8(format_code(self.format))?;
+        output.write_u64::<LE>(self.length)?;
+        output.write_u64::<LE>(self.points.len() as u64)?;
+        for point in &self.points {
+            output.write_u64::<LE>(point.offset)?;
+            output.write_u8(kind_code(point.kind))?;
+            output.write_u8(point.source.0)?;
+            output.write_u8(point.page)?;
+            output.write_u8(point.timestamp.is_some() as u8)?;
+            output.write_u64::<LE>(point.timestamp.unwrap_or(0))?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> Result<Index, Error> {
+        let invalid = |msg| Error::new(ErrorKind::InvalidData, msg);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not an index file"));
+        }
+
+        let format = match input.read_u8()? {
+            0 => TraceFormat::ITM,
+            1 => TraceFormat::TPIU,
+            2 => TraceFormat::ETM,
+            _ => return Err(invalid("Unknown trace format"))
+        };
+        let length = input.read_u64::<LE>()?;
+        let count = input.read_u64::<LE>()?;
+        let mut points = Vec::new();
+        for _ in 0..count {
+            let offset = input.read_u64::<LE>()?;
+            let kind = match input.read_u8()? {
+                0 => SyncKind::Start,
+                1 => SyncKind::ITM,
+                2 => SyncKind::TPIUFrame,
+                3 => SyncKind::ETMAsync,
+                _ => return Err(invalid("Unknown sync kind"))
+            };
+            let source = TraceSourceID(input.read_u8()?);
+            let page = input.read_u8()?;
+            let has_timestamp = input.read_u8()? != 0;
+            let timestamp = input.read_u64::<LE>()?;
+            points.push(SyncPoint {
+                offset, kind, source, page,
+                timestamp: if has_timestamp { Some(timestamp) } else { None }
+            });
+        }
+        Ok(Index { format, length, points })
+    }
+}
+
+fn format_code(format: TraceFormat) -> u8 {
+    match format {
+        TraceFormat::ITM => 0,
+        TraceFormat::TPIU => 1,
+        TraceFormat::ETM => 2,
+    }
+}
+
+fn kind_code(kind: SyncKind) -> u8 {
+    match kind {
+        SyncKind::Start => 0,
+        SyncKind::ITM => 1,
+        SyncKind::TPIUFrame => 2,
+        SyncKind::ETMAsync => 3,
+    }
+}
+
+fn scan_itm<F: FnMut(SyncPoint)>(data: &[u8], add: &mut F) {
+    let mut cursor = Cursor::new(data);
+    let (mut time, mut page) = (0u64, 0u8);
+    loop {
+        let offset = cursor.position();
+        match parse_one(&mut cursor) {
+            Ok(ITMPacket::Synchronization) => add(SyncPoint {
+                offset, kind: SyncKind::ITM, source: TraceSourceID(0), page, timestamp: Some(time)
+            }),
+            Ok(ITMPacket::LocalTimestamp(_, delta)) => time += delta.0 as u64,
+            Ok(ITMPacket::SoftwarePageNumber(port)) => page = port.0 as u8,
+            Ok(_) => {},
+            Err(_) => break
+        }
+    }
+}
+
+fn scan_tpiu<F: FnMut(SyncPoint)>(data: &[u8], add: &mut F) {
+    let mut source = TraceSourceID(0);
+    let mut offset = 0;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        if rest.starts_with(&FRAME_SYNC) {
+            add(SyncPoint {
+                offset: offset as u64, kind: SyncKind::TPIUFrame, source, page: 0, timestamp: None
+            });
+            offset += 4;
+        } else if rest.starts_with(&HALFWORD_SYNC) {
+            offset += 2;
+        } else if rest.len() >= 16 {
+            let mut frame = [0u8; 16];
+            frame.copy_from_slice(&rest[..16]);
+            decode_frame(&frame, &mut source);
+            offset += 16;
+        } else {
+            break;
+        }
+    }
+}
+
+fn scan_etm<F: FnMut(SyncPoint)>(data: &[u8], add: &mut F) {
+    let mut zeros = 0;
+    for (offset, &byte) in data.iter().enumerate() {
+        if byte == 0x80 && zeros >= 5 {
+            add(SyncPoint {
+                offset: (offset - zeros) as u64, kind: SyncKind::ETMAsync,
+                source: TraceSourceID(0), page: 0, timestamp: None
+            });
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+}
+
+/// Reads from the memory mapped file, starting at some offset.
+pub struct MappedReader {
+    map: Arc<Mmap>,
+    position: usize,
+}
+
+impl Read for MappedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let rest = &self.map[self.position.min(self.map.len())..];
+        let count = rest.len().min(buf.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Memory mapped capture file along with its index.
+pub struct MappedTrace {
+    map: Arc<Mmap>,
+    index: Index,
+}
+
+/// Path of the sidecar index file.
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+impl MappedTrace {
+    /// Maps the file and loads the index from the sidecar file. If the
+    /// index is missing or does not match the file, it is rebuilt and saved.
+    pub fn open<P: AsRef<Path>>(path: P, format: TraceFormat) -> Result<MappedTrace, Error> {
+        let file = File::open(path.as_ref())?;
+        // The file must not be modified while mapped, as for any capture file.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let sidecar = index_path(path.as_ref());
+        let existing = File::open(&sidecar).and_then(|mut f| Index::read_from(&mut f)).ok();
+        let index = match existing {
+            Some(ref index) if index.format == format && index.length == map.len() as u64 => index.clone(),
+            _ => {
+                let index = Index::build(&map, format, 0x10000);
+                let mut output = Vec::new();
+                index.write_to(&mut output)?;
+                // Index is only a cache, so failure to save it is not fatal.
+                let _ = File::create(&sidecar).and_then(|mut f| f.write_all(&output));
+                index
+            }
+        };
+
+        Ok(MappedTrace { map: Arc::new(map), index })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Reader for the data starting at the offset.
+    pub fn reader_at(&self, offset: u64) -> MappedReader {
+        MappedReader { map: self.map.clone(), position: offset as usize }
+    }
+
+    /// ITM packets with timestamps, from a sync point onwards.
+    pub fn itm_packets(&self, point: &SyncPoint) -> Timestamper<ITMParser<MappedReader>> {
+        Timestamper::with_time(ITMParser::new(self.reader_at(point.offset)), point.timestamp.unwrap_or(0))
+    }
+
+    /// TPIU packets from a sync point onwards.
+    pub fn tpiu_packets(&self, point: &SyncPoint) -> TPIUParser {
+        let mut parser = TPIUParser::new(Box::new(self.reader_at(point.offset)));
+        parser.set_source(point.source);
+        parser
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DATA: &[u8] = include_bytes!("../../testdata/itm_only.bin");
+
+    /// Test data with synchronization packets inserted every 100 packets.
+    fn itm_data() -> Vec<u8> {
+        let mut cursor = Cursor::new(DATA);
+        let mut result = Vec::new();
+        let mut start = 0;
+        for count in 1.. {
+            if parse_one(&mut cursor).is_err() {
+                break;
+            }
+            if count % 100 == 0 {
+                let end = cursor.position() as usize;
+                result.extend_from_slice(&DATA[start..end]);
+                result.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x80]);
+                start = end;
+            }
+        }
+        result.extend_from_slice(&DATA[start..]);
+        result
+    }
+
+    #[test]
+    fn test_itm_index() {
+        let data = itm_data();
+        let index = Index::build(&data, TraceFormat::ITM, 0);
+        assert!(index.points.len() > 2);
+        assert_eq!(index.points[1].kind, SyncKind::ITM);
+
+        // Decoding from any sync point gives the same packets and
+        // times as decoding from the start.
+        let full: Vec<(u64, ITMPacket)> = Timestamper::new(ITMParser::new(Cursor::new(&data[..]))).collect();
+        let point = index.at_offset(data.len() as u64 / 2).unwrap();
+        assert_eq!(point.kind, SyncKind::ITM);
+        let partial: Vec<(u64, ITMPacket)> = Timestamper::with_time(
+            ITMParser::new(Cursor::new(&data[point.offset as usize..])), point.timestamp.unwrap()).collect();
+        assert_eq!(&full[full.len() - partial.len()..], &partial[..]);
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(Index::read_from(&mut Cursor::new(saved)).unwrap(), index);
+    }
+
+    #[test]
+    fn test_page() {
+        // Page 1, synchronization, and a write to port 0 on that page.
+        let data = [0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x07];
+        let index = Index::build(&data, TraceFormat::ITM, 0);
+        let point = index.at_offset(1).unwrap();
+        assert_eq!((point.kind, point.page), (SyncKind::ITM, 32));
+
+        let mut decoder = point.signal_decoder(Schema::parse("32 level u8").unwrap());
+        let samples: Vec<_> = Timestamper::new(ITMParser::new(Cursor::new(&data[1..])))
+            .flat_map(|(time, packet)| decoder.push(time, &packet)).collect();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].port, InstrumentationPort(32));
+    }
+
+    #[test]
+    fn test_sidecar() {
+        let path = ::std::env::temp_dir().join(format!("index_test_{}.bin", ::std::process::id()));
+        fs::write(&path, itm_data()).unwrap();
+
+        let trace = MappedTrace::open(&path, TraceFormat::ITM).unwrap();
+        let sidecar = index_path(&path);
+        assert!(sidecar.exists());
+        assert_eq!(MappedTrace::open(&path, TraceFormat::ITM).unwrap().index(), trace.index());
+
+        let point = *trace.index().at_offset(trace.data().len() as u64).unwrap();
+        let expected = Timestamper::new(ITMParser::new(Cursor::new(&itm_data()[point.offset as usize..]))).count();
+        assert_eq!(trace.itm_packets(&point).count(), expected);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+//! Input stages that recover the trace byte stream from
+//! different capture sources.
+
+pub mod samples;
+pub mod swo;
+pub mod parallel;
+pub mod tcp;
+pub mod orbuculum;
+pub mod follow;
+pub mod index;
+#[cfg(unix)]
+pub mod serial;
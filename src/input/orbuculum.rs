@@ -0,0 +1,293 @@
+//! Client for the Orbuculum trace server. The legacy protocol sends
+//! the raw TPIU stream from the probe. The OFLOW protocol sends the
+//! already demultiplexed data of each trace stream as COBS encoded
+//! frames, each terminated by a zero byte. A decoded frame contains:
+//!
+//! - stream tag (1 byte), which is the TPIU channel number
+//! - timestamp (8 bytes, little endian)
+//! - payload data
+//! - checksum (1 byte), so that the sum of all bytes is zero modulo 256
+
+use std::io::{Read, BufRead, BufReader, Error, ErrorKind};
+use ::tpiu::types::{TPIUPacket, TraceSourceID};
+use ::tpiu::parser::Parser as TPIUParser;
+use ::tpiu::demux::{Demux, SourcePacket};
+use super::tcp::{TcpConfig, TcpInput};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Protocol {
+    Legacy,
+    Oflow,
+}
+
+/// Decoded OFLOW frame.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OflowFrame {
+    pub tag: u8,
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+/// Decodes a Consistent Overhead Byte Stuffing frame, without
+/// the terminating zero.
+pub fn cobs_decode(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::with_capacity(input.len());
+    let mut pos = 0;
+    while pos < input.len() {
+        let code = input[pos] as usize;
+        if code == 0 || pos + code > input.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid COBS code"));
+        }
+        result.extend_from_slice(&input[pos + 1..pos + code]);
+        pos += code;
+        if code < 0xFF && pos < input.len() {
+            result.push(0);
+        }
+    }
+    Ok(result)
+}
+
+/// Encodes data as a COBS frame, without the terminating zero.
+pub fn cobs_encode(input: &[u8]) -> Vec<u8> {
+    let mut result = vec![0];
+    let mut code_pos = 0;
+    for &byte in input {
+        if byte != 0 {
+            result.push(byte);
+        }
+        if byte == 0 || result.len() - code_pos == 0xFF {
+            result[code_pos] = (result.len() - code_pos) as u8;
+            code_pos = result.len();
+            result.push(0);
+        }
+    }
+    result[code_pos] = (result.len() - code_pos) as u8;
+    result
+}
+
+impl OflowFrame {
+    /// Parses the contents of a decoded COBS frame.
+    pub fn parse(frame: &[u8]) -> Result<OflowFrame, Error> {
+        if frame.len() < 10 {
+            return Err(Error::new(ErrorKind::InvalidData, "Too short OFLOW frame"));
+        }
+        if frame.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "OFLOW checksum mismatch"));
+        }
+
+        let timestamp = frame[1..9].iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64);
+        Ok(OflowFrame { tag: frame[0], timestamp, data: frame[9..frame.len() - 1].to_vec() })
+    }
+
+    /// Encodes the frame, including the terminating zero.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = vec![self.tag];
+        frame.extend((0..8).map(|i| (self.timestamp >> (i * 8)) as u8));
+        frame.extend_from_slice(&self.data);
+        let sum = frame.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        frame.push(0u8.wrapping_sub(sum));
+
+        let mut result = cobs_encode(&frame);
+        result.push(0);
+        result
+    }
+}
+
+/// Reads OFLOW frames from a stream. Corrupted frames are skipped.
+pub struct OflowReader<R> {
+    input: BufReader<R>,
+    errors: usize,
+    error: Option<Error>,
+}
+
+impl<R: Read> OflowReader<R> {
+    pub fn new(input: R) -> OflowReader<R> {
+        OflowReader { input: BufReader::new(input), errors: 0, error: None }
+    }
+
+    /// Number of frames skipped due to errors.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<R: Read> Iterator for OflowReader<R> {
+    type Item = OflowFrame;
+    fn next(&mut self) -> Option<OflowFrame> {
+        loop {
+            let mut encoded = Vec::new();
+            match self.input.read_until(0, &mut encoded) {
+                Ok(0) => return None,
+                Ok(_) => {},
+                Err(e) => {self.error = Some(e); return None}
+            }
+
+            if encoded.last() != Some(&0) {
+                // Partial frame at end of stream.
+                return None;
+            }
+            encoded.pop();
+            if encoded.is_empty() {
+                continue;
+            }
+
+            match cobs_decode(&encoded).and_then(|frame| OflowFrame::parse(&frame)) {
+                Ok(frame) => return Some(frame),
+                Err(_) => self.errors += 1
+            }
+        }
+    }
+}
+
+/// TPIU packets from either protocol, with the OFLOW frame timestamp.
+enum Stream<R> {
+    Legacy(TPIUParser),
+    Oflow(OflowReader<R>),
+}
+
+impl<R: Read> Iterator for Stream<R> {
+    type Item = (Option<u64>, TPIUPacket);
+    fn next(&mut self) -> Option<(Option<u64>, TPIUPacket)> {
+        match *self {
+            Stream::Legacy(ref mut parser) => parser.next().map(|packet| (None, packet)),
+            Stream::Oflow(ref mut reader) => reader.next().map(|frame| {
+                (Some(frame.timestamp), TPIUPacket::Data(TraceSourceID(frame.tag), frame.data))
+            }),
+        }
+    }
+}
+
+/// Decodes the trace streams received from the server. The stream
+/// tags are mapped to decoders with the trace source IDs in the demux,
+/// e.g. ITM is usually on stream 1 and ETM on stream 2.
+///
+/// Each packet comes with the timestamp of the OFLOW frame that
+/// completed it, as set by the server. The legacy protocol has no
+/// timestamps.
+pub struct Client<R> {
+    stream: Stream<R>,
+    demux: Demux,
+    buffer: ::std::vec::IntoIter<(Option<u64>, TraceSourceID, SourcePacket)>,
+}
+
+impl<R: Read + 'static> Client<R> {
+    pub fn new(input: R, protocol: Protocol, demux: Demux) -> Client<R> {
+        let stream = match protocol {
+            Protocol::Legacy => Stream::Legacy(TPIUParser::new(Box::new(input))),
+            Protocol::Oflow => Stream::Oflow(OflowReader::new(input)),
+        };
+        Client { stream, demux, buffer: Vec::new().into_iter() }
+    }
+}
+
+impl Client<TcpInput> {
+    /// Connects to the server, reconnecting if it is restarted.
+    pub fn connect(config: TcpConfig, protocol: Protocol, demux: Demux) -> Result<Client<TcpInput>, Error> {
+        Ok(Client::new(TcpInput::connect(config)?, protocol, demux))
+    }
+}
+
+impl<R: Read> Iterator for Client<R> {
+    type Item = (Option<u64>, TraceSourceID, SourcePacket);
+    fn next(&mut self) -> Option<(Option<u64>, TraceSourceID, SourcePacket)> {
+        loop {
+            if let Some(packet) = self.buffer.next() {
+                return Some(packet);
+            }
+            let (timestamp, packet) = self.stream.next()?;
+            let output: Vec<_> = self.demux.push(packet).into_iter()
+                .map(|(id, packet)| (timestamp, id, packet)).collect();
+            self.buffer = output.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Write, Cursor};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use ::itm::parser::Parser;
+    use ::tpiu::demux::SourceType;
+    use ::tpiu::parser::Parser as TPIUParser;
+    use ::tpiu::demux::DemuxIter;
+
+    const DATA: &[u8] = include_bytes!("../../testdata/itm_only.bin");
+    const TPIU: &[u8] = include_bytes!("../../testdata/etm_itm_tpiu.bin");
+
+    #[test]
+    fn test_cobs() {
+        let data = vec![0x11, 0x00, 0x00, 0x22, 0x33, 0x00];
+        assert_eq!(cobs_encode(&data), vec![0x02, 0x11, 0x01, 0x03, 0x22, 0x33, 0x01]);
+        assert_eq!(cobs_decode(&cobs_encode(&data)).unwrap(), data);
+
+        let long: Vec<u8> = (1..=255).collect();
+        assert_eq!(cobs_decode(&cobs_encode(&long)).unwrap(), long);
+    }
+
+    #[test]
+    fn test_oflow_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for (i, chunk) in DATA.chunks(13).enumerate() {
+                let itm = OflowFrame { tag: 1, timestamp: i as u64 * 1000, data: chunk.to_vec() };
+                let etm = OflowFrame { tag: 2, timestamp: i as u64 * 1000, data: vec![0x00, 0x42] };
+                stream.write_all(&itm.encode()).unwrap();
+                stream.write_all(&etm.encode()).unwrap();
+            }
+        });
+
+        let mut demux = Demux::new();
+        demux.add_source(TraceSourceID(1), SourceType::ITM);
+        let config = TcpConfig { reconnect: false, retry_delay: Duration::from_millis(10),
+                                 max_retries: Some(2), ..TcpConfig::new(&address) };
+        let packets: Vec<(Option<u64>, TraceSourceID, SourcePacket)> =
+            Client::connect(config, Protocol::Oflow, demux).unwrap().collect();
+        server.join().unwrap();
+
+        let (itm, other): (Vec<_>, Vec<_>) = packets.into_iter().partition(|p| p.1 == TraceSourceID(1));
+        assert!(itm.windows(2).all(|w| w[0].0.is_some() && w[0].0 <= w[1].0));
+        let itm: Vec<SourcePacket> = itm.into_iter().map(|p| p.2).collect();
+        let expected: Vec<SourcePacket> = Parser::new(Cursor::new(DATA)).map(SourcePacket::ITM).collect();
+        assert_eq!(itm, expected);
+        assert_eq!(other.len(), DATA.chunks(13).count());
+        for (i, packet) in other.iter().enumerate() {
+            assert_eq!(*packet, (Some(i as u64 * 1000), TraceSourceID(2), SourcePacket::Raw(vec![0x00, 0x42])));
+        }
+    }
+
+    #[test]
+    fn test_legacy_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // Chunks that do not match the frame size.
+            let (mut stream, _) = listener.accept().unwrap();
+            for chunk in TPIU.chunks(37) {
+                stream.write_all(chunk).unwrap();
+            }
+        });
+
+        let mut demux = Demux::new();
+        demux.add_source(TraceSourceID(1), SourceType::ITM);
+        let config = TcpConfig { reconnect: false, retry_delay: Duration::from_millis(10),
+                                 max_retries: Some(2), ..TcpConfig::new(&address) };
+        let packets: Vec<(Option<u64>, TraceSourceID, SourcePacket)> =
+            Client::connect(config, Protocol::Legacy, demux.clone()).unwrap().collect();
+        server.join().unwrap();
+
+        let expected: Vec<(Option<u64>, TraceSourceID, SourcePacket)> =
+            DemuxIter::new(TPIUParser::new(Box::new(Cursor::new(TPIU))), demux)
+                .map(|(id, packet)| (None, id, packet)).collect();
+        assert!(expected.iter().any(|p| p.1 == TraceSourceID(1)));
+        assert_eq!(packets, expected);
+    }
+}
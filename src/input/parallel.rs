@@ -0,0 +1,268 @@
+//! Decodes the parallel trace port from logic analyzer samples of
+//! TRACECLK and TRACEDATA. The port transfers data on both clock
+//! edges, least significant bits first. Byte alignment is found from
+//! the full frame synchronization packet 0xFFFFFF7F, which the TPIU
+//! sends periodically and when idle.
+
+use std::io::{Read, BufRead, Error, ErrorKind};
+use std::collections::VecDeque;
+
+/// Sync packet as 32 bits received least significant bit first.
+const SYNC: u32 = 0x7FFF_FFFF;
+
+/// Location of the trace port signals in each sample.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PortConfig {
+    /// Number of TRACEDATA pins: 1, 2 or 4.
+    pub width: u8,
+
+    /// Bit of TRACECLK, or None if every sample is taken on a clock edge.
+    pub clock: Option<usize>,
+
+    /// Bit of TRACEDATA[0], followed by the other data pins.
+    pub data: usize,
+}
+
+/// Assembles port data into bytes.
+pub struct Assembler {
+    config: PortConfig,
+    clock: Option<bool>,
+    window: u32,
+    byte: u8,
+    bits: u8,
+    synced: bool,
+    resyncs: usize,
+    output: VecDeque<u8>,
+}
+
+impl Assembler {
+    pub fn new(config: PortConfig) -> Assembler {
+        Assembler {
+            config, clock: None, window: 0, byte: 0, bits: 0,
+            synced: false, resyncs: 0, output: VecDeque::new()
+        }
+    }
+
+    /// Processes one logic analyzer sample. Data is taken when the
+    /// clock changes, as the port is double data rate.
+    pub fn push_sample(&mut self, sample: u32) {
+        match self.config.clock {
+            Some(bit) => {
+                let clock = sample & (1 << bit) != 0;
+                if self.clock.is_some() && self.clock != Some(clock) {
+                    self.push_edge(sample >> self.config.data);
+                }
+                self.clock = Some(clock);
+            },
+            None => self.push_edge(sample >> self.config.data),
+        }
+    }
+
+    /// Processes the TRACEDATA value of one clock edge.
+    pub fn push_edge(&mut self, data: u32) {
+        for i in 0..self.config.width {
+            self.push_bit(data & (1 << i) != 0);
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        self.window = (self.window >> 1) | ((bit as u32) << 31);
+
+        if self.synced {
+            self.byte |= (bit as u8) << self.bits;
+            self.bits += 1;
+            if self.bits == 8 {
+                self.output.push_back(self.byte);
+                self.byte = 0;
+                self.bits = 0;
+            }
+        }
+
+        if self.window == SYNC && !(self.synced && self.bits == 0) {
+            // Found sync at a new alignment, restart from it.
+            if self.synced {
+                self.resyncs += 1;
+            }
+            self.synced = true;
+            self.byte = 0;
+            self.bits = 0;
+            self.output.extend(&[0xFF, 0xFF, 0xFF, 0x7F]);
+        }
+    }
+
+    /// True after the first synchronization packet.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Number of times the alignment changed after the first sync.
+    pub fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    /// Takes the assembled bytes.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.output.drain(..).collect()
+    }
+}
+
+/// Format of the sample file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SampleFormat {
+    /// Raw samples of unitsize bytes, little endian.
+    Binary(usize),
+
+    /// One sample per line, one column per signal. If the first line
+    /// is a header with TRACECLK and TRACEDATA0 columns, those are used
+    /// instead of the configured bits.
+    CSV,
+}
+
+/// Reads the aligned trace bytes from a sample file, for passing to
+/// `tpiu::parser::Parser`.
+pub struct PortReader<R> {
+    input: R,
+    format: SampleFormat,
+    assembler: Assembler,
+    first_line: bool,
+}
+
+impl<R: BufRead> PortReader<R> {
+    pub fn new(input: R, format: SampleFormat, config: PortConfig) -> PortReader<R> {
+        PortReader { input, format, assembler: Assembler::new(config), first_line: true }
+    }
+
+    pub fn assembler(&self) -> &Assembler {
+        &self.assembler
+    }
+
+    /// Reads the next sample, or returns None at end of file.
+    fn read_sample(&mut self) -> Result<Option<u32>, Error> {
+        match self.format {
+            SampleFormat::Binary(unitsize) => {
+                let mut sample = vec![0; unitsize.max(1)];
+                match self.input.read_exact(&mut sample) {
+                    Ok(()) => {},
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e)
+                }
+                Ok(Some(sample.iter().take(4).rev().fold(0, |v, &b| (v << 8) | b as u32)))
+            },
+            SampleFormat::CSV => loop {
+                let mut line = String::new();
+                if self.input.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+
+                let line = line.trim();
+                if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                    continue;
+                }
+
+                let columns: Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+                if self.first_line {
+                    self.first_line = false;
+                    if self.parse_header(&columns) {
+                        continue;
+                    }
+                }
+
+                let mut sample = 0;
+                for (index, value) in columns.iter().enumerate().take(32) {
+                    if *value != "0" {
+                        sample |= 1 << index;
+                    }
+                }
+                return Ok(Some(sample));
+            }
+        }
+    }
+
+    /// Takes signal columns from the header line, returns false if
+    /// the line is not a header.
+    fn parse_header(&mut self, columns: &[&str]) -> bool {
+        if columns.iter().all(|c| c.parse::<f64>().is_ok()) {
+            return false;
+        }
+
+        let name = |c: &str| c.to_uppercase().replace(|ch: char| !ch.is_ascii_alphanumeric(), "");
+        let config = &mut self.assembler.config;
+        for (index, column) in columns.iter().enumerate() {
+            match &name(column)[..] {
+                "TRACECLK" => config.clock = Some(index),
+                "TRACEDATA0" => config.data = index,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl<R: BufRead> Read for PortReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.assembler.output.is_empty() {
+            match self.read_sample()? {
+                Some(sample) => self.assembler.push_sample(sample),
+                None => return Ok(0)
+            }
+        }
+
+        let count = buf.len().min(self.assembler.output.len());
+        for (dest, src) in buf.iter_mut().zip(self.assembler.output.drain(..count)) {
+            *dest = src;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use ::tpiu::parser::Parser;
+    use ::tpiu::types::*;
+
+    /// Sync and one frame with 14 bytes of data from source 1.
+    fn trace() -> Vec<u8> {
+        let mut data = vec![0xFF, 0xFF, 0xFF, 0x7F, 0x03];
+        data.extend(vec![0x10; 14]);
+        data.push(0x00);
+        data
+    }
+
+    fn edges(data: &[u8], width: u8, skip_bits: usize) -> Vec<u32> {
+        let mut bits: Vec<bool> = vec![true; 7];
+        bits.extend(data.iter().flat_map(|&b| (0..8).map(move |i| b & (1 << i) != 0)));
+        bits.drain(..skip_bits);
+        bits.chunks(width as usize)
+            .map(|c| c.iter().enumerate().fold(0, |v, (i, &b)| v | ((b as u32) << i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_binary() {
+        // Clock in bit 0, data in bits 1..4.
+        let samples: Vec<u8> = edges(&trace(), 4, 3).iter().enumerate()
+            .map(|(i, &d)| ((d << 1) | (i as u32 % 2)) as u8).collect();
+        let config = PortConfig { width: 4, clock: Some(0), data: 1 };
+        let reader = PortReader::new(Cursor::new(samples), SampleFormat::Binary(1), config);
+        let packets: Vec<TPIUPacket> = Parser::new(Box::new(reader)).collect();
+        assert_eq!(packets, vec![
+            TPIUPacket::FrameSynchronization,
+            TPIUPacket::Data(TraceSourceID(1), vec![0x10; 14]),
+        ]);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut csv = String::from("Time,TRACEDATA[0],TRACEDATA[1],TRACECLK\n");
+        for (i, d) in edges(&trace(), 2, 2).iter().enumerate() {
+            csv += &format!("{},{},{},{}\n", i, d & 1, d >> 1, i % 2);
+        }
+        let config = PortConfig { width: 2, clock: None, data: 0 };
+        let mut reader = PortReader::new(Cursor::new(csv), SampleFormat::CSV, config);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, trace());
+    }
+}
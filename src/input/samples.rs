@@ -0,0 +1,229 @@
+//! Digital signal captured with a logic analyzer. The samples are
+//! stored as the list of transitions, which is compact for slow
+//! signals sampled at a high rate.
+
+extern crate zip;
+
+use std::io::{Read, Seek, Error, ErrorKind};
+use std::collections::BTreeMap;
+
+/// Single channel of sampled digital data.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Samples {
+    rate: u64,
+    initial: bool,
+    edges: Vec<u64>,
+    length: u64,
+}
+
+impl Samples {
+    /// Creates from individual sample levels, at rate samples per second.
+    pub fn from_levels<I: IntoIterator<Item=bool>>(rate: u64, levels: I) -> Samples {
+        let mut samples = Samples { rate, initial: false, edges: Vec::new(), length: 0 };
+        let mut previous = None;
+        for level in levels {
+            match previous {
+                None => samples.initial = level,
+                Some(p) if p != level => samples.edges.push(samples.length),
+                _ => {}
+            }
+            previous = Some(level);
+            samples.length += 1;
+        }
+        samples
+    }
+
+    /// Creates from a file with one bit per sample, least significant bit first.
+    pub fn from_packed_bits(rate: u64, data: &[u8]) -> Samples {
+        Samples::from_levels(rate, data.iter().flat_map(|&byte| (0..8).map(move |bit| byte & (1 << bit) != 0)))
+    }
+
+    /// Creates from logic analyzer data with `unitsize` bytes per sample,
+    /// taking one channel, numbered from 0.
+    pub fn from_logic_data(rate: u64, data: &[u8], unitsize: usize, channel: usize) -> Samples {
+        let (byte, bit) = (channel / 8, channel % 8);
+        Samples::from_levels(rate, data.chunks(unitsize.max(1))
+                                       .filter(|sample| sample.len() > byte)
+                                       .map(|sample| sample[byte] & (1 << bit) != 0))
+    }
+
+    /// Loads a sigrok session file. The channel is selected by name,
+    /// or by number from 0 if no channel has that name.
+    pub fn from_sigrok<R: Read + Seek>(input: R, channel: &str) -> Result<Samples, Error> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+        let mut archive = zip::ZipArchive::new(input).map_err(|e| invalid(e.to_string()))?;
+
+        let mut metadata = String::new();
+        archive.by_name("metadata").map_err(|e| invalid(e.to_string()))?
+            .read_to_string(&mut metadata)?;
+        let device = parse_metadata(&metadata).remove("device 1")
+            .ok_or_else(|| invalid(String::from("No device in sigrok metadata")))?;
+
+        let rate = device.get("samplerate").and_then(|s| parse_samplerate(s))
+            .ok_or_else(|| invalid(String::from("Missing sample rate")))?;
+        let unitsize = device.get("unitsize").and_then(|s| s.parse().ok()).unwrap_or(1);
+        let capturefile = device.get("capturefile").cloned().unwrap_or_else(|| String::from("logic-1"));
+
+        let index = device.iter()
+            .filter(|&(key, value)| key.starts_with("probe") && value == channel)
+            .filter_map(|(key, _)| key[5..].parse::<usize>().ok())
+            .next().map(|probe| probe - 1)
+            .or_else(|| channel.parse().ok())
+            .ok_or_else(|| invalid(format!("Channel {} not found", channel)))?;
+
+        // Data is either in a single file, or split in numbered chunks.
+        let mut data = Vec::new();
+        if archive.by_name(&capturefile).is_ok() {
+            archive.by_name(&capturefile).map_err(|e| invalid(e.to_string()))?.read_to_end(&mut data)?;
+        } else {
+            let mut chunk = 1;
+            while let Ok(mut file) = archive.by_name(&format!("{}-{}", capturefile, chunk)) {
+                file.read_to_end(&mut data)?;
+                chunk += 1;
+            }
+        }
+
+        Ok(Samples::from_logic_data(rate, &data, unitsize, index))
+    }
+
+    /// Sample rate in samples per second.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Total number of samples.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Sample indexes where the level changes.
+    pub fn edges(&self) -> &[u64] {
+        &self.edges
+    }
+
+    /// Level of the signal at a sample index.
+    pub fn level_at(&self, sample: u64) -> bool {
+        let count = self.edges.partition_point(|&e| e <= sample);
+        self.initial ^ (count % 2 == 1)
+    }
+
+    /// First edge at or after the sample index.
+    pub fn next_edge(&self, sample: u64) -> Option<u64> {
+        let index = self.edges.partition_point(|&e| e < sample);
+        self.edges.get(index).cloned()
+    }
+
+    /// Edge nearest to the sample index.
+    pub fn nearest_edge(&self, sample: u64) -> Option<u64> {
+        let index = self.edges.partition_point(|&e| e < sample);
+        let after = self.edges.get(index).cloned();
+        let before = if index > 0 { Some(self.edges[index - 1]) } else { None };
+        match (before, after) {
+            (Some(b), Some(a)) => Some(if sample - b <= a - sample { b } else { a }),
+            (b, a) => b.or(a),
+        }
+    }
+
+    /// Lengths and levels of the constant level runs between edges.
+    pub fn runs(&self) -> Vec<(u64, bool)> {
+        let mut result = Vec::with_capacity(self.edges.len() + 1);
+        let mut start = 0;
+        let mut level = self.initial;
+        for &edge in self.edges.iter().chain(Some(&self.length)) {
+            if edge > start {
+                result.push((edge - start, level));
+            }
+            start = edge;
+            level = !level;
+        }
+        result
+    }
+}
+
+/// Parses the INI style metadata to sections of key-value pairs.
+fn parse_metadata(text: &str) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut result: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut section = String::new();
+    for line in text.lines().map(|l| l.trim()) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = String::from(&line[1..line.len() - 1]);
+        } else if let Some(pos) = line.find('=') {
+            result.entry(section.clone()).or_default()
+                .insert(String::from(&line[..pos]), String::from(&line[pos + 1..]));
+        }
+    }
+    result
+}
+
+/// Parses sample rate such as "24 MHz".
+fn parse_samplerate(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, multiplier) = if let Some(n) = text.strip_suffix("GHz") {
+        (n, 1_000_000_000.0)
+    } else if let Some(n) = text.strip_suffix("MHz") {
+        (n, 1_000_000.0)
+    } else if let Some(n) = text.strip_suffix("kHz") {
+        (n, 1_000.0)
+    } else {
+        (text.trim_end_matches("Hz"), 1.0)
+    };
+    number.trim().parse::<f64>().ok().map(|n| (n * multiplier).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_levels() {
+        let samples = Samples::from_packed_bits(1000, &[0b11110000, 0b00000011]);
+        assert_eq!(samples.len(), 16);
+        assert_eq!(samples.edges(), &[4, 10]);
+        assert_eq!(samples.runs(), vec![(4, false), (6, true), (6, false)]);
+        assert!(!samples.level_at(3));
+        assert!(samples.level_at(4));
+        assert!(!samples.level_at(12));
+        assert_eq!(samples.nearest_edge(8), Some(10));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = parse_metadata("[global]\nsigrok version=0.5.1\n\n[device 1]\n\
+                                       capturefile=logic-1\nsamplerate=12.5 MHz\nprobe2=SWO\n");
+        let device = &metadata["device 1"];
+        assert_eq!(parse_samplerate(&device["samplerate"]), Some(12_500_000));
+        assert_eq!(device["probe2"], "SWO");
+    }
+
+    #[test]
+    fn test_sigrok() {
+        // Session with the capture split in two chunks.
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        archive.start_file("version", options).unwrap();
+        archive.write_all(b"2").unwrap();
+        archive.start_file("metadata", options).unwrap();
+        archive.write_all(b"[device 1]\ncapturefile=logic-1\ntotal probes=2\n\
+                            samplerate=1 MHz\nprobe1=CLK\nprobe2=SWO\nunitsize=1\n").unwrap();
+        archive.start_file("logic-1-1", options).unwrap();
+        archive.write_all(&[0x03, 0x01, 0x01]).unwrap();
+        archive.start_file("logic-1-2", options).unwrap();
+        archive.write_all(&[0x02, 0x02]).unwrap();
+        let data = archive.finish().unwrap().into_inner();
+
+        let swo = Samples::from_sigrok(Cursor::new(&data[..]), "SWO").unwrap();
+        assert_eq!(swo.rate(), 1_000_000);
+        assert_eq!(swo.len(), 5);
+        assert_eq!(swo.runs(), vec![(1, true), (2, false), (2, true)]);
+
+        let clock = Samples::from_sigrok(Cursor::new(&data[..]), "0").unwrap();
+        assert_eq!(clock.runs(), vec![(3, true), (2, false)]);
+
+        assert!(Samples::from_sigrok(Cursor::new(&data[..]), "TRACE").is_err());
+    }
+}
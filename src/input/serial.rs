@@ -0,0 +1,287 @@
+//! Reads SWO from a serial port, for boards that route the SWO pin
+//! to a USB-UART adapter. The tty is configured to raw mode with
+//! parity and framing error marking, so that line errors can be
+//! reported as lost data instead of being decoded as garbage bytes.
+
+extern crate libc;
+
+use std::io::{Read, Cursor, Error, ErrorKind};
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::collections::VecDeque;
+use std::time::Duration;
+use ::itm::types::ITMPacket;
+use ::itm::parser::parse_one;
+
+/// Receive error detected by the UART.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LineError {
+    /// Line was held low for longer than a character.
+    Break,
+    /// Stop bit or parity was wrong, the character was dropped.
+    Framing,
+}
+
+/// Received bytes or a line error between them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SerialData {
+    Bytes(Vec<u8>),
+    Error(LineError),
+}
+
+/// Decodes the PARMRK escape sequences: 0xFF 0xFF is a 0xFF data
+/// byte, 0xFF 0x00 0x00 is a break and 0xFF 0x00 X is a character X
+/// with framing or parity error. Sequences may be split across reads.
+#[derive(Debug, Default)]
+pub struct Unescaper {
+    state: u8,
+}
+
+impl Unescaper {
+    pub fn new() -> Unescaper {
+        Unescaper::default()
+    }
+
+    pub fn push(&mut self, input: &[u8]) -> Vec<SerialData> {
+        let mut result = Vec::new();
+        let mut bytes = Vec::new();
+        for &byte in input {
+            match (self.state, byte) {
+                (0, 0xFF) => self.state = 1,
+                (0, _) => bytes.push(byte),
+                (1, 0x00) => self.state = 2,
+                (1, _) => {
+                    bytes.push(byte);
+                    self.state = 0;
+                },
+                (_, _) => {
+                    if !bytes.is_empty() {
+                        result.push(SerialData::Bytes(bytes.split_off(0)));
+                    }
+                    let error = if byte == 0 { LineError::Break } else { LineError::Framing };
+                    result.push(SerialData::Error(error));
+                    self.state = 0;
+                }
+            }
+        }
+        if !bytes.is_empty() {
+            result.push(SerialData::Bytes(bytes));
+        }
+        result
+    }
+}
+
+fn speed(baudrate: u32) -> Option<libc::speed_t> {
+    Some(match baudrate {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(target_os = "linux")] 460800 => libc::B460800,
+        #[cfg(target_os = "linux")] 500000 => libc::B500000,
+        #[cfg(target_os = "linux")] 921600 => libc::B921600,
+        #[cfg(target_os = "linux")] 1000000 => libc::B1000000,
+        #[cfg(target_os = "linux")] 1500000 => libc::B1500000,
+        #[cfg(target_os = "linux")] 2000000 => libc::B2000000,
+        #[cfg(target_os = "linux")] 3000000 => libc::B3000000,
+        #[cfg(target_os = "linux")] 4000000 => libc::B4000000,
+        _ => return None
+    })
+}
+
+/// Serial port opened in raw mode.
+pub struct SerialPort {
+    file: File,
+    timeout: Duration,
+    unescaper: Unescaper,
+}
+
+impl SerialPort {
+    /// Opens the tty at a standard baud rate. Reads return after the
+    /// timeout even if no data was received.
+    pub fn open(path: &str, baudrate: u32, timeout: Duration) -> Result<SerialPort, Error> {
+        let speed = speed(baudrate).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("Unsupported baud rate {}", baudrate))
+        })?;
+
+        let file = OpenOptions::new().read(true).write(true)
+            .custom_flags(libc::O_NOCTTY).open(path)?;
+        let fd = file.as_raw_fd();
+
+        unsafe {
+            let mut termios: libc::termios = ::std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            termios.c_iflag &= !(libc::IGNBRK | libc::BRKINT | libc::IGNPAR | libc::ISTRIP);
+            termios.c_iflag |= libc::INPCK | libc::PARMRK;
+            termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            libc::cfsetispeed(&mut termios, speed);
+            libc::cfsetospeed(&mut termios, speed);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        Ok(SerialPort { file, timeout, unescaper: Unescaper::new() })
+    }
+
+    /// Waits for data until the timeout. Returns an empty list on timeout,
+    /// and an UnexpectedEof error if the device was disconnected.
+    pub fn read_data(&mut self) -> Result<Vec<SerialData>, Error> {
+        let mut poll = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout = self.timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            -1 => {
+                let error = Error::last_os_error();
+                return if error.kind() == ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(error) };
+            },
+            0 => return Ok(Vec::new()),
+            _ => {}
+        }
+
+        let mut buffer = [0u8; 1024];
+        match self.file.read(&mut buffer) {
+            Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "Serial port closed")),
+            Ok(count) => Ok(self.unescaper.push(&buffer[..count])),
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => {
+                Err(Error::new(ErrorKind::UnexpectedEof, "Serial port closed"))
+            },
+            Err(e) => Err(e)
+        }
+    }
+}
+
+/// ITM packet or a marker for data lost on the serial line.
+#[derive(Debug, Eq, PartialEq)]
+pub enum SerialPacket {
+    ITM(ITMPacket),
+    LostData(LineError),
+}
+
+/// Decodes ITM packets live from the serial port. A partially received
+/// packet is discarded at a line error, as its remaining bytes cannot
+/// be trusted.
+pub struct SerialItm {
+    port: SerialPort,
+    buffer: Vec<u8>,
+    output: VecDeque<SerialPacket>,
+    error: Option<Error>,
+}
+
+impl SerialItm {
+    pub fn new(port: SerialPort) -> SerialItm {
+        SerialItm { port, buffer: Vec::new(), output: VecDeque::new(), error: None }
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Processes the data received within one timeout period and
+    /// returns the packets decoded from it.
+    pub fn poll(&mut self) -> Result<Vec<SerialPacket>, Error> {
+        for data in self.port.read_data()? {
+            match data {
+                SerialData::Bytes(bytes) => {
+                    self.buffer.extend(bytes);
+                    self.parse();
+                },
+                SerialData::Error(error) => {
+                    self.buffer.clear();
+                    self.output.push_back(SerialPacket::LostData(error));
+                }
+            }
+        }
+        Ok(self.output.drain(..).collect())
+    }
+
+    fn parse(&mut self) {
+        let mut consumed = 0;
+        loop {
+            let mut cursor = Cursor::new(&self.buffer[consumed..]);
+            match parse_one(&mut cursor) {
+                Ok(packet) => {
+                    consumed += cursor.position() as usize;
+                    self.output.push_back(SerialPacket::ITM(packet));
+                },
+                Err(_) => break
+            }
+        }
+        self.buffer.drain(..consumed);
+    }
+}
+
+impl Iterator for SerialItm {
+    type Item = SerialPacket;
+    fn next(&mut self) -> Option<SerialPacket> {
+        while self.output.is_empty() {
+            match self.poll() {
+                Ok(packets) => self.output.extend(packets),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+                Err(e) => {self.error = Some(e); return None}
+            }
+        }
+        self.output.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::itm::types::*;
+
+    #[test]
+    fn test_unescape() {
+        let mut unescaper = Unescaper::new();
+        assert_eq!(unescaper.push(&[0x01, 0xFF, 0xFF, 0x02, 0xFF]),
+                   vec![SerialData::Bytes(vec![0x01, 0xFF, 0x02])]);
+        assert_eq!(unescaper.push(&[0x00, 0x00, 0x03, 0xFF, 0x00, 0x55]), vec![
+            SerialData::Error(LineError::Break),
+            SerialData::Bytes(vec![0x03]),
+            SerialData::Error(LineError::Framing),
+        ]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pty() {
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+        use std::ffi::CStr;
+
+        let (mut master, path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+            let mut name = [0 as libc::c_char; 128];
+            assert_eq!(libc::ptsname_r(fd, name.as_mut_ptr(), name.len()), 0);
+            (File::from_raw_fd(fd), CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+        };
+
+        let port = SerialPort::open(&path, 115200, Duration::from_millis(50)).unwrap();
+        let mut itm = SerialItm::new(port);
+        assert_eq!(itm.poll().unwrap(), vec![]);
+
+        // Packet split across writes, and a data byte 0xFF.
+        master.write_all(&[0x17, 0x16, 0x02]).unwrap();
+        master.flush().unwrap();
+        assert_eq!(itm.poll().unwrap(), vec![]);
+        master.write_all(&[0x00, 0x08, 0x01, 0xFF]).unwrap();
+        let mut packets = Vec::new();
+        while packets.len() < 2 {
+            packets.extend(itm.poll().unwrap());
+        }
+        assert_eq!(packets, vec![
+            SerialPacket::ITM(ITMPacket::ProgramCounter(Address(0x08000216))),
+            SerialPacket::ITM(ITMPacket::Software(InstrumentationPort(0), DataValue::U8(0xFF))),
+        ]);
+    }
+}
@@ -0,0 +1,299 @@
+//! Recovers the Serial Wire Output byte stream from a logic analyzer
+//! capture of the TRACESWO pin. Both encodings supported by the TPIU
+//! are decoded:
+//!
+//! - NRZ: UART format with idle high, one start bit, 8 data bits
+//!   least significant first and one stop bit.
+//! - Manchester: idle low, start bit 1 and data bits where 1 is high
+//!   followed by low. A frame of one or more bytes ends when the line
+//!   stays low for a whole bit period.
+
+use std::io::{Cursor, Error, ErrorKind};
+use ::itm::types::ITMPacket;
+use ::itm::parser::parse_one;
+use ::tpiu::parser::Parser as TPIUParser;
+use super::samples::Samples;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Encoding {
+    NRZ,
+    Manchester,
+}
+
+/// Decoded byte along with the sample index where it started.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimedByte {
+    pub sample: u64,
+    pub value: u8,
+}
+
+/// Detects the encoding from the idle level of the line, i.e. the
+/// level of the longest run.
+pub fn detect_encoding(samples: &Samples) -> Option<Encoding> {
+    samples.runs().iter().max_by_key(|r| r.0).map(|&(_, level)| {
+        if level { Encoding::NRZ } else { Encoding::Manchester }
+    })
+}
+
+/// Detects the length of one bit period in samples. The shortest
+/// runs are one bit long in NRZ and half a bit in Manchester encoding.
+/// Runs much shorter than the typical run are treated as glitches.
+pub fn detect_bit_length(samples: &Samples, encoding: Encoding) -> Option<f64> {
+    let runs = samples.runs();
+    if runs.len() < 3 {
+        return None;
+    }
+
+    // First and last run are cut off by the capture.
+    let mut lengths: Vec<u64> = runs[1..runs.len() - 1].iter().map(|r| r.0).collect();
+    lengths.sort();
+    let median = lengths[lengths.len() / 2];
+    let shortest = *lengths.iter().find(|&&l| l * 3 >= median)?;
+    let similar: Vec<u64> = lengths.iter().cloned()
+        .filter(|&l| l >= shortest && l * 2 < shortest * 3).collect();
+    let unit = similar.iter().sum::<u64>() as f64 / similar.len() as f64;
+
+    Some(match encoding {
+        Encoding::NRZ => unit,
+        Encoding::Manchester => unit * 2.0,
+    })
+}
+
+/// Decodes UART format bytes. Bytes with a framing error, i.e. low
+/// stop bit, are skipped.
+pub fn decode_nrz(samples: &Samples, bit_length: f64) -> Vec<TimedByte> {
+    let mut result = Vec::new();
+    let at = |start: u64, bits: f64| start + (bits * bit_length) as u64;
+    let mut position = 0;
+
+    while let Some(start) = samples.next_edge(position) {
+        if samples.level_at(start) {
+            // Rising edge, not a start bit.
+            position = start + 1;
+            continue;
+        }
+
+        let stop = at(start, 9.5);
+        if stop >= samples.len() {
+            break;
+        }
+
+        let mut value = 0u8;
+        for bit in 0..8 {
+            if samples.level_at(at(start, 1.5 + bit as f64)) {
+                value |= 1 << bit;
+            }
+        }
+
+        if samples.level_at(stop) {
+            result.push(TimedByte { sample: start, value });
+        }
+        position = stop.max(start + 1);
+    }
+
+    result
+}
+
+/// Decodes Manchester encoded frames. The bit timing is adjusted on
+/// every mid-bit transition to tolerate clock drift.
+pub fn decode_manchester(samples: &Samples, bit_length: f64) -> Vec<TimedByte> {
+    let mut result = Vec::new();
+    let half = bit_length / 2.0;
+    let mut position = 0;
+
+    while let Some(start) = samples.next_edge(position) {
+        if !samples.level_at(start) {
+            position = start + 1;
+            continue;
+        }
+
+        // Middle of the start bit should have a falling edge.
+        let mut middle = start as f64 + half;
+        let mut bits = Vec::new();
+        loop {
+            if let Some(edge) = samples.nearest_edge(middle as u64) {
+                if (edge as f64 - middle).abs() < half / 2.0 {
+                    middle = edge as f64;
+                }
+            }
+
+            let first = samples.level_at((middle - half / 2.0) as u64);
+            let second = samples.level_at((middle + half / 2.0) as u64);
+            if first == second || middle + half >= samples.len() as f64 {
+                break;
+            }
+            bits.push(first);
+            middle += bit_length;
+        }
+
+        // First bit is the start bit, followed by whole bytes.
+        for (index, byte) in bits[1.min(bits.len())..].chunks(8).enumerate() {
+            if byte.len() == 8 {
+                let value = byte.iter().enumerate().fold(0u8, |v, (i, &b)| v | ((b as u8) << i));
+                let sample = (start as f64 + bit_length * (1 + index * 8) as f64) as u64;
+                result.push(TimedByte { sample, value });
+            }
+        }
+        position = ((middle - half) as u64 + 1).max(start + 1);
+    }
+
+    result
+}
+
+/// Byte stream recovered from a capture.
+#[derive(Debug, Clone)]
+pub struct SwoCapture {
+    rate: u64,
+    encoding: Encoding,
+    bit_length: f64,
+    bytes: Vec<TimedByte>,
+}
+
+impl SwoCapture {
+    /// Decodes with known encoding and baud rate. The sample rate
+    /// must be at least twice the baud rate.
+    pub fn new(samples: &Samples, encoding: Encoding, baudrate: u64) -> Result<SwoCapture, Error> {
+        if baudrate == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Baud rate must not be zero"));
+        }
+        SwoCapture::with_bit_length(samples, encoding, samples.rate() as f64 / baudrate as f64)
+    }
+
+    fn with_bit_length(samples: &Samples, encoding: Encoding, bit_length: f64) -> Result<SwoCapture, Error> {
+        if bit_length < 2.0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Too few samples per bit to decode"));
+        }
+        let bytes = match encoding {
+            Encoding::NRZ => decode_nrz(samples, bit_length),
+            Encoding::Manchester => decode_manchester(samples, bit_length),
+        };
+        Ok(SwoCapture { rate: samples.rate(), encoding, bit_length, bytes })
+    }
+
+    /// Detects the encoding and baud rate from the signal.
+    pub fn detect(samples: &Samples) -> Result<SwoCapture, Error> {
+        let encoding = detect_encoding(samples);
+        let bit_length = encoding.and_then(|e| detect_bit_length(samples, e));
+        match (encoding, bit_length) {
+            (Some(encoding), Some(bit_length)) => SwoCapture::with_bit_length(samples, encoding, bit_length),
+            _ => Err(Error::new(ErrorKind::InvalidData, "Too few transitions to detect baud rate"))
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Baud rate in bits per second.
+    pub fn baudrate(&self) -> u64 {
+        (self.rate as f64 / self.bit_length).round() as u64
+    }
+
+    pub fn bytes(&self) -> &[TimedByte] {
+        &self.bytes
+    }
+
+    /// Byte values without the timing.
+    pub fn data(&self) -> Vec<u8> {
+        self.bytes.iter().map(|b| b.value).collect()
+    }
+
+    /// Time in seconds of the byte at a position in the data, such
+    /// as returned by `tpiu::parser::Parser::position()`.
+    pub fn time(&self, position: usize) -> Option<f64> {
+        self.bytes.get(position).map(|b| b.sample as f64 / self.rate as f64)
+    }
+
+    /// Parses the data as ITM packets, along with the time of the
+    /// first byte of each packet.
+    pub fn itm_packets(&self) -> Vec<(f64, ITMPacket)> {
+        let data = self.data();
+        let mut input = Cursor::new(&data[..]);
+        let mut result = Vec::new();
+        loop {
+            let position = input.position() as usize;
+            match parse_one(&mut input) {
+                Ok(packet) => result.push((self.time(position).unwrap_or(0.0), packet)),
+                Err(_) => break
+            }
+        }
+        result
+    }
+
+    /// Parser for data that goes through the TPIU formatter.
+    pub fn tpiu_parser(&self) -> TPIUParser {
+        TPIUParser::new(Box::new(Cursor::new(self.data())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::itm::types::{InstrumentationPort, DataValue};
+
+    /// Generates the signal at the given samples per bit.
+    fn nrz(bytes: &[u8], bit: usize) -> Vec<bool> {
+        let mut levels = vec![true; bit * 20];
+        for &byte in bytes {
+            let mut bits = vec![false];
+            bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+            bits.push(true);
+            for b in bits {
+                levels.extend(vec![b; bit]);
+            }
+        }
+        levels.extend(vec![true; bit * 20]);
+        levels
+    }
+
+    fn manchester(bytes: &[u8], bit: usize) -> Vec<bool> {
+        let mut levels = vec![false; bit * 5];
+        let mut bits = vec![true];
+        for &byte in bytes {
+            bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+        }
+        for b in bits {
+            levels.extend(vec![b; bit / 2]);
+            levels.extend(vec![!b; bit / 2]);
+        }
+        levels.extend(vec![false; bit * 5]);
+        levels
+    }
+
+    #[test]
+    fn test_nrz() {
+        let samples = Samples::from_levels(1_000_000, nrz(&[0x01, 0x55, 0xF0, 0x80], 10));
+        let capture = SwoCapture::detect(&samples).unwrap();
+        assert_eq!(capture.encoding(), Encoding::NRZ);
+        assert_eq!(capture.baudrate(), 100_000);
+        assert_eq!(capture.data(), vec![0x01, 0x55, 0xF0, 0x80]);
+        assert_eq!(capture.bytes()[1].sample, 300);
+        assert_eq!(capture.itm_packets()[0], (200e-6, ITMPacket::Software(
+            InstrumentationPort(0), DataValue::U8(0x55))));
+    }
+
+    #[test]
+    fn test_manchester() {
+        let samples = Samples::from_levels(1_000_000, manchester(&[0x01, 0x55, 0xF0, 0x80], 8));
+        let capture = SwoCapture::detect(&samples).unwrap();
+        assert_eq!(capture.encoding(), Encoding::Manchester);
+        assert_eq!(capture.baudrate(), 125_000);
+        assert_eq!(capture.data(), vec![0x01, 0x55, 0xF0, 0x80]);
+        assert_eq!(capture.bytes()[1].sample, 40 + 8 * 9);
+    }
+
+    #[test]
+    fn test_baudrate() {
+        let samples = Samples::from_levels(1_000_000, nrz(&[0x55, 0xAA], 10));
+        let capture = SwoCapture::new(&samples, Encoding::NRZ, 100_000).unwrap();
+        assert_eq!(capture.data(), vec![0x55, 0xAA]);
+        for &baudrate in &[0, 600_000, 2_000_000] {
+            let error = SwoCapture::new(&samples, Encoding::NRZ, baudrate).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+
+        // Bit periods below one sample still make progress.
+        decode_nrz(&samples, 0.1);
+        decode_manchester(&samples, 0.1);
+    }
+}
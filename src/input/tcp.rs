@@ -0,0 +1,187 @@
+//! Reads a live trace stream from a TCP server, such as the SWO
+//! port of OpenOCD (`tpiu config ... :port`), J-Link SWO telnet
+//! port or pyOCD SWV server. These all send the raw trace bytes.
+//! The connection is reopened if the server closes it, so that the
+//! stream continues when the debugger is restarted.
+//!
+//! With the default configuration the stream never ends: failed
+//! connections are retried forever, and reads wait forever on a
+//! server that stays connected but sends nothing. Set `max_retries`
+//! and `idle_timeout` to end the stream instead.
+
+use std::io::{Read, Error, ErrorKind};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TcpConfig {
+    /// Server address as host:port.
+    pub address: String,
+
+    /// Reconnect when the connection is closed or lost.
+    pub reconnect: bool,
+
+    /// Delay between connection attempts.
+    pub retry_delay: Duration,
+
+    /// Number of failed connection attempts before giving up,
+    /// or None to retry forever.
+    pub max_retries: Option<u32>,
+
+    /// Ends the stream if no data arrives within the timeout,
+    /// or None to wait forever.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl TcpConfig {
+    /// Configuration that reconnects and waits for data forever.
+    pub fn new(address: &str) -> TcpConfig {
+        TcpConfig {
+            address: String::from(address),
+            reconnect: true,
+            retry_delay: Duration::from_secs(1),
+            max_retries: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+/// Byte stream from the server. Reads block until data is available,
+/// so packets that span several TCP segments are parsed normally.
+pub struct TcpInput {
+    config: TcpConfig,
+    stream: Option<TcpStream>,
+    connections: usize,
+}
+
+impl TcpInput {
+    /// Connects to the server, retrying as configured.
+    pub fn connect(config: TcpConfig) -> Result<TcpInput, Error> {
+        let mut input = TcpInput { config, stream: None, connections: 0 };
+        input.open()?;
+        Ok(input)
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let mut failures = 0;
+        loop {
+            match TcpStream::connect(&self.config.address[..]) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(self.config.idle_timeout)?;
+                    self.stream = Some(stream);
+                    self.connections += 1;
+                    return Ok(());
+                },
+                Err(e) => {
+                    failures += 1;
+                    if self.config.max_retries.is_some_and(|max| failures > max) {
+                        return Err(e);
+                    }
+                    thread::sleep(self.config.retry_delay);
+                }
+            }
+        }
+    }
+
+    /// Number of times the connection was reopened. Packets may be
+    /// lost or cut in half at each reconnect.
+    pub fn reconnects(&self) -> usize {
+        self.connections.saturating_sub(1)
+    }
+}
+
+/// Errors after which the connection cannot be used anymore.
+fn is_disconnect(error: &Error) -> bool {
+    matches!(error.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted |
+                           ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof)
+}
+
+/// Errors returned when the read timeout expires, which depend on
+/// the platform.
+fn is_timeout(error: &Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+impl Read for TcpInput {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            if self.stream.is_none() {
+                self.open()?;
+            }
+
+            let result = self.stream.as_mut().unwrap().read(buf);
+            match result {
+                Ok(0) if self.config.reconnect && !buf.is_empty() => self.stream = None,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(ref e) if is_timeout(e) => return Ok(0),
+                Err(ref e) if self.config.reconnect && is_disconnect(e) => self.stream = None,
+                result => return result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Write, Cursor};
+    use std::net::TcpListener;
+    use ::itm::parser::{Parser, parse_one};
+    use ::itm::types::ITMPacket;
+
+    const DATA: &[u8] = include_bytes!("../../testdata/itm_only.bin");
+
+    #[test]
+    fn test_replay() {
+        // Split at a packet boundary for the reconnect.
+        let mut cursor = Cursor::new(DATA);
+        for _ in 0..100 {
+            parse_one(&mut cursor).unwrap();
+        }
+        let split = cursor.position() as usize;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // Send in small pieces, so that packets span reads.
+            for part in &[&DATA[..split], &DATA[split..]] {
+                let (mut stream, _) = listener.accept().unwrap();
+                for chunk in part.chunks(7) {
+                    stream.write_all(chunk).unwrap();
+                }
+            }
+        });
+
+        let config = TcpConfig {
+            retry_delay: Duration::from_millis(10), max_retries: Some(2), ..TcpConfig::new(&address)
+        };
+        let input = TcpInput::connect(config).unwrap();
+        let received: Vec<ITMPacket> = Parser::new(input).collect();
+        server.join().unwrap();
+
+        let expected: Vec<ITMPacket> = Parser::new(Cursor::new(DATA)).collect();
+        assert_eq!(received.len(), expected.len());
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[0x01, 0x41]).unwrap();
+            thread::sleep(Duration::from_millis(500));
+        });
+
+        let config = TcpConfig { idle_timeout: Some(Duration::from_millis(50)), ..TcpConfig::new(&address) };
+        let mut input = TcpInput::connect(config).unwrap();
+        let mut data = Vec::new();
+        input.read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![0x01, 0x41]);
+        assert_eq!(input.reconnects(), 0);
+        server.join().unwrap();
+    }
+}
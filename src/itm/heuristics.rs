@@ -44,7 +44,6 @@ pub fn find_starting_point(block: &[u8]) -> usize {
             count += 1;
         }
         score /= count as f32;
-        println!("i {} score {}", i, score);
         (i,score)
     }).max_by(|&(_,a), &(_,b)| a.partial_cmp(&b).unwrap()).unwrap_or((0,0.0)).0
 }
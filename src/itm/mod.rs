@@ -1,3 +1,6 @@
 pub mod types;
 pub mod parser;
 pub mod heuristics;
+pub mod timestamp;
+pub mod stream;
+pub mod schema;
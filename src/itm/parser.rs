@@ -8,6 +8,8 @@ use std::io::{Read, Error, ErrorKind};
 use super::types::*;
 
 use ::utils::bittuple::{to_bits,to_u32};
+use ::utils::readpos::ReadPos;
+use ::decoder::Decoder;
 
 /// Reads variable length value encoded in the "protocol" encoding format,
 /// where the top bit marks continuation. Returns value and length in bits.
@@ -144,19 +146,21 @@ pub fn parse_one(input: &mut Read) -> Result<ITMPacket, Error> {
 }
 
 pub struct Parser<T> {
-    input: T,
+    input: ReadPos<T>,
     error: Option<Error>,
+    packet_offset: usize,
 }
 
 impl<T:Read> Parser<T> {
     pub fn new(input: T) -> Parser<T> {
-        Parser{ input, error: None }
+        Parser{ input: ReadPos::new(input), error: None, packet_offset: 0 }
     }
 }
 
 impl<T:Read> Iterator for Parser<T> {
     type Item = ITMPacket;
     fn next(&mut self) -> Option<ITMPacket> {
+        self.packet_offset = self.input.position();
         match parse_one(&mut self.input) {
             Ok(result) => Some(result),
             Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => None,
@@ -165,6 +169,47 @@ impl<T:Read> Iterator for Parser<T> {
     }
 }
 
+/// ITM parser has no settings, as the packets are self-describing.
+impl<T:Read> Decoder for Parser<T> {
+    type Packet = ITMPacket;
+    type Config = ();
+
+    fn config(&self) -> &() {
+        &()
+    }
+
+    fn position(&self) -> usize {
+        self.input.position()
+    }
+
+    fn packet_offset(&self) -> usize {
+        self.packet_offset
+    }
+
+    fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    fn reset(&mut self) {
+        self.error = None;
+    }
+
+    /// Synchronization packet is at least 47 zero bits followed by a one bit.
+    fn resync(&mut self) -> bool {
+        self.reset();
+        let mut zeros = 0;
+        loop {
+            match self.input.read_u8() {
+                Ok(0x00) => zeros += 1,
+                Ok(0x80) if zeros >= 5 => return true,
+                Ok(_) => zeros = 0,
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return false,
+                Err(e) => {self.error = Some(e); return false}
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,382 @@
+//! Typed values of stimulus port writes. A schema gives a signal name
+//! and type to each port, so that e.g. floats and signed values are
+//! shown as such in the outputs. It can be read from a text file with
+//! one port per line:
+//!
+//! ```text
+//! # port  name         type
+//! 0       temperature  f32
+//! 1       current      q1.14
+//! 2       state        enum 0=Idle 1=Run 2=Fault
+//! 3       accel        struct x:i16 y:i16 z:i16 flags:u16
+//! ```
+//!
+//! Fixed point types are given in Q notation: `qN` and `qM.N` are
+//! signed with M integer and N fraction bits, `uqM.N` is unsigned.
+//! Structs are assembled from the bytes of consecutive writes, and
+//! their fields become signals named `accel.x` and so on.
+//!
+//! The decoded values are available from `SignalDecoder`, and as
+//! `Signal` records in the JSON Lines, CSV, SQLite and VCD outputs,
+//! the command line ITM input and the Python `ItmParser`,
+//! `itm_columns()` and `TpiuDemux.decode()`. The C API and the trace
+//! events of `event` do not include them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use super::types::*;
+use super::stream::value_bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalType {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    F32,
+
+    /// Fixed point value of 8, 16 or 32 bits, e.g. Q15 is
+    /// `Fixed { bits: 16, fraction: 15, signed: true }`.
+    Fixed { bits: u8, fraction: u8, signed: bool },
+
+    /// Names of the values. Values without a name are shown as numbers.
+    Enum(BTreeMap<u32, String>),
+
+    /// Named fields of scalar types, in little endian byte order.
+    Struct(Vec<(String, SignalType)>),
+}
+
+impl SignalType {
+    /// Size in bytes, or None for types that take the size of the write.
+    fn size(&self) -> Option<usize> {
+        match *self {
+            SignalType::U8 | SignalType::I8 => Some(1),
+            SignalType::U16 | SignalType::I16 => Some(2),
+            SignalType::U32 | SignalType::I32 | SignalType::F32 => Some(4),
+            SignalType::Fixed { bits, .. } => Some(bits as usize / 8),
+            SignalType::Enum(_) => None,
+            SignalType::Struct(ref fields) => fields.iter().map(|f| f.1.size()).sum(),
+        }
+    }
+
+    /// Decodes a value from the low bits of a write.
+    fn decode(&self, raw: u32) -> SignalValue {
+        match *self {
+            SignalType::U8 => SignalValue::Int(raw as u8 as i64),
+            SignalType::U16 => SignalValue::Int(raw as u16 as i64),
+            SignalType::U32 => SignalValue::Int(raw as i64),
+            SignalType::I8 => SignalValue::Int(raw as i8 as i64),
+            SignalType::I16 => SignalValue::Int(raw as i16 as i64),
+            SignalType::I32 => SignalValue::Int(raw as i32 as i64),
+            SignalType::F32 => SignalValue::Float(f32::from_bits(raw) as f64),
+            SignalType::Fixed { bits, fraction, signed } => {
+                let shift = 32 - bits as u32;
+                let value = if signed {
+                    ((raw << shift) as i32 >> shift) as f64
+                } else {
+                    ((raw << shift) >> shift) as f64
+                };
+                SignalValue::Float(value / (1u64 << fraction) as f64)
+            },
+            SignalType::Enum(ref names) => SignalValue::Enum(raw, names.get(&raw).cloned()),
+            SignalType::Struct(_) => unreachable!(),
+        }
+    }
+
+    /// Parses a type name of the schema file, except for enums and
+    /// structs.
+    fn parse(name: &str) -> Option<SignalType> {
+        Some(match name {
+            "u8" => SignalType::U8,
+            "u16" => SignalType::U16,
+            "u32" => SignalType::U32,
+            "i8" => SignalType::I8,
+            "i16" => SignalType::I16,
+            "i32" => SignalType::I32,
+            "f32" => SignalType::F32,
+            _ => {
+                let (signed, q) = match name.strip_prefix("uq") {
+                    Some(q) => (false, q),
+                    None => (true, name.strip_prefix('q')?),
+                };
+                let (integer, fraction) = match q.find('.') {
+                    Some(i) => (q[..i].parse::<u8>().ok()?, q[i + 1..].parse::<u8>().ok()?),
+                    None => (0, q.parse::<u8>().ok()?),
+                };
+                let bits = integer.checked_add(fraction)?.checked_add(signed as u8)?;
+                if bits != 8 && bits != 16 && bits != 32 {
+                    return None;
+                }
+                SignalType::Fixed { bits, fraction, signed }
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalValue {
+    Int(i64),
+    Float(f64),
+
+    /// Enum value, with its name if it is in the table.
+    Enum(u32, Option<String>),
+}
+
+impl SignalValue {
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            SignalValue::Int(v) => v as f64,
+            SignalValue::Float(v) => v,
+            SignalValue::Enum(v, _) => v as f64,
+        }
+    }
+}
+
+impl fmt::Display for SignalValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SignalValue::Int(v) => write!(f, "{}", v),
+            SignalValue::Float(v) => write!(f, "{}", v),
+            SignalValue::Enum(_, Some(ref name)) => write!(f, "{}", name),
+            SignalValue::Enum(v, None) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Name and type of the values written to a port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub ty: SignalType,
+}
+
+/// Signals by port number, including the page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    ports: BTreeMap<u32, Signal>,
+}
+
+fn invalid_input(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema { ports: BTreeMap::new() }
+    }
+
+    /// Sets the signal of a port. Struct fields must be integer,
+    /// float or fixed point types.
+    pub fn add(&mut self, port: InstrumentationPort, name: &str, ty: SignalType) -> Result<(), Error> {
+        if let SignalType::Struct(ref fields) = ty {
+            if fields.is_empty() || fields.iter().any(|f| matches!(f.1, SignalType::Enum(_) | SignalType::Struct(_))) {
+                return Err(invalid_input(format!("Invalid struct fields for signal {}", name)));
+            }
+        }
+        self.ports.insert(port.0, Signal { name: String::from(name), ty });
+        Ok(())
+    }
+
+    pub fn get(&self, port: InstrumentationPort) -> Option<&Signal> {
+        self.ports.get(&port.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// Parses the schema file format described in the module docs.
+    pub fn parse(text: &str) -> Result<Schema, Error> {
+        let mut schema = Schema::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let error = || invalid_input(format!("Invalid schema on line {}: {}", number + 1, line.trim()));
+            if words.len() < 3 {
+                return Err(error());
+            }
+
+            let port = words[0].parse::<u32>().map_err(|_| error())?;
+            let ty = match words[2] {
+                "enum" => {
+                    let mut names = BTreeMap::new();
+                    for item in &words[3..] {
+                        let mut parts = item.splitn(2, '=');
+                        let value = parts.next().and_then(|v| v.parse::<u32>().ok()).ok_or_else(error)?;
+                        names.insert(value, String::from(parts.next().ok_or_else(error)?));
+                    }
+                    SignalType::Enum(names)
+                },
+                "struct" => {
+                    let mut fields = Vec::new();
+                    for item in &words[3..] {
+                        let mut parts = item.splitn(2, ':');
+                        let name = parts.next().unwrap_or("");
+                        let ty = parts.next().and_then(SignalType::parse).ok_or_else(error)?;
+                        fields.push((String::from(name), ty));
+                    }
+                    SignalType::Struct(fields)
+                },
+                name if words.len() == 3 => SignalType::parse(name).ok_or_else(error)?,
+                _ => return Err(error()),
+            };
+            schema.add(InstrumentationPort(port), words[1], ty).map_err(|_| error())?;
+        }
+        Ok(schema)
+    }
+}
+
+/// Decoded value of a signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Time of the write, or of the first write of a struct.
+    pub time: u64,
+
+    /// Port number including the page.
+    pub port: InstrumentationPort,
+
+    /// Signal name, with the field name for structs.
+    pub name: String,
+
+    pub value: SignalValue,
+}
+
+/// Decodes the writes to the ports in a schema from timestamped
+/// packets.
+#[derive(Debug, Clone)]
+pub struct SignalDecoder {
+    schema: Schema,
+    page: u32,
+
+    /// Bytes of incomplete structs, with the time of the first write.
+    partial: BTreeMap<u32, (u64, Vec<u8>)>,
+}
+
+impl SignalDecoder {
+    pub fn new(schema: Schema) -> SignalDecoder {
+        SignalDecoder { schema, page: 0, partial: BTreeMap::new() }
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Sets the stimulus port page, as the number of its first port,
+    /// when starting in the middle of a stream.
+    pub fn set_page(&mut self, page: InstrumentationPort) {
+        self.page = page.0;
+    }
+
+    /// Adds a packet, with time given in timestamp clock ticks, and
+    /// returns the values that it completes.
+    pub fn push(&mut self, time: u64, packet: &ITMPacket) -> Vec<Sample> {
+        let (port, value) = match *packet {
+            ITMPacket::SoftwarePageNumber(page) => {
+                self.page = page.0;
+                return Vec::new();
+            },
+            ITMPacket::Overflow => {
+                // Writes may have been lost from the middle of a struct.
+                self.partial.clear();
+                return Vec::new();
+            },
+            ITMPacket::Software(port, ref value) => (InstrumentationPort(self.page + port.0), value),
+            _ => return Vec::new(),
+        };
+        let signal = match self.schema.get(port) {
+            Some(signal) => signal,
+            None => return Vec::new(),
+        };
+
+        let fields = match signal.ty {
+            SignalType::Struct(ref fields) => fields,
+            ref ty => return vec![Sample { time, port, name: signal.name.clone(), value: ty.decode(value.to_u32()) }],
+        };
+
+        let size = signal.ty.size().unwrap_or(0);
+        let partial = self.partial.entry(port.0).or_insert_with(|| (time, Vec::new()));
+        partial.1.extend(value_bytes(value));
+        let mut samples = Vec::new();
+        while partial.1.len() >= size {
+            let bytes: Vec<u8> = partial.1.drain(..size).collect();
+            let mut position = 0;
+            for (name, ty) in fields {
+                let length = ty.size().unwrap_or(0);
+                let raw = bytes[position..position + length].iter().rev().fold(0, |v, &b| (v << 8) | b as u32);
+                position += length;
+                samples.push(Sample {
+                    time: partial.0, port, name: format!("{}.{}", signal.name, name), value: ty.decode(raw),
+                });
+            }
+            partial.0 = time;
+        }
+        if partial.1.is_empty() {
+            self.partial.remove(&port.0);
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let schema = Schema::parse("
+            # Comment
+            0 temperature f32
+            1 current q1.14   # Comment
+            2 state enum 0=Idle 1=Run
+            35 accel struct x:i16 y:i16 flags:uq8
+        ").unwrap();
+        assert_eq!(schema.get(InstrumentationPort(0)).unwrap().ty, SignalType::F32);
+        assert_eq!(schema.get(InstrumentationPort(1)).unwrap().ty,
+                   SignalType::Fixed { bits: 16, fraction: 14, signed: true });
+        assert_eq!(schema.get(InstrumentationPort(35)).unwrap().ty.size(), Some(5));
+        assert!(Schema::parse("0 x q3.3").is_err());
+        assert!(Schema::parse("0 x struct a:u8 b").is_err());
+        assert!(Schema::parse("x y u8").is_err());
+        let nested = SignalType::Struct(vec![(String::from("a"), SignalType::Struct(vec![]))]);
+        assert!(Schema::new().add(InstrumentationPort(0), "x", nested).is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let schema = Schema::parse("
+            0 temperature f32
+            1 current q15
+            2 state enum 0=Idle 1=Run
+            3 offset i16
+            35 accel struct x:i16 y:i16
+        ").unwrap();
+        let mut decoder = SignalDecoder::new(schema);
+        let mut push = |time, packet| decoder.push(time, &packet).into_iter()
+            .map(|s| format!("{} {} {} {}", s.time, s.port.0, s.name, s.value)).collect::<Vec<String>>();
+
+        assert_eq!(push(1, ITMPacket::Software(InstrumentationPort(0), DataValue::U32(1.5f32.to_bits()))),
+                   vec!["1 0 temperature 1.5"]);
+        assert_eq!(push(2, ITMPacket::Software(InstrumentationPort(1), DataValue::U16(0xC000))),
+                   vec!["2 1 current -0.5"]);
+        assert_eq!(push(3, ITMPacket::Software(InstrumentationPort(2), DataValue::U8(1))), vec!["3 2 state Run"]);
+        assert_eq!(push(3, ITMPacket::Software(InstrumentationPort(2), DataValue::U8(7))), vec!["3 2 state 7"]);
+        assert_eq!(push(4, ITMPacket::Software(InstrumentationPort(3), DataValue::U32(0xFFFE))), vec!["4 3 offset -2"]);
+        assert!(push(4, ITMPacket::Software(InstrumentationPort(4), DataValue::U8(0))).is_empty());
+
+        // Struct on page 32, split over writes and broken by overflow.
+        assert!(push(5, ITMPacket::SoftwarePageNumber(InstrumentationPort(32))).is_empty());
+        assert!(push(6, ITMPacket::Software(InstrumentationPort(3), DataValue::U16(5))).is_empty());
+        assert!(push(7, ITMPacket::Overflow).is_empty());
+        assert!(push(8, ITMPacket::Software(InstrumentationPort(3), DataValue::U16(1))).is_empty());
+        assert_eq!(push(9, ITMPacket::Software(InstrumentationPort(3), DataValue::U16(0xFFFF))),
+                   vec!["8 35 accel.x 1", "8 35 accel.y -1"]);
+        assert_eq!(push(10, ITMPacket::Software(InstrumentationPort(3), DataValue::U32(0x0003_0002))),
+                   vec!["10 35 accel.x 2", "10 35 accel.y 3"]);
+    }
+}
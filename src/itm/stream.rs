@@ -0,0 +1,79 @@
+//! Reassembles the bytes written by software to one stimulus port,
+//! for decoding protocols that use the port as a byte stream, such
+//! as log frames.
+
+use super::types::*;
+
+/// Data from the port, with the time of the packet.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PortData {
+    /// Bytes of one write, in little endian order.
+    Bytes(u64, Vec<u8>),
+
+    /// ITM overflow, some writes may have been lost.
+    Overflow(u64),
+}
+
+/// Iterator adapter from `(time, ITMPacket)` pairs, as returned by
+/// `Timestamper`, to the data of one port. The port number includes
+/// the page, like in `SoftwareWrite::channel`.
+pub struct PortStream<I> {
+    packets: I,
+    port: u32,
+    page: u32,
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> PortStream<I> {
+    pub fn new(packets: I, port: InstrumentationPort) -> PortStream<I> {
+        PortStream { packets, port: port.0, page: 0 }
+    }
+}
+
+/// Bytes of a write, lowest byte first like in target memory.
+pub fn value_bytes(value: &DataValue) -> Vec<u8> {
+    match *value {
+        DataValue::U8(v) => vec![v],
+        DataValue::U16(v) => v.to_le_bytes().to_vec(),
+        DataValue::U32(v) => v.to_le_bytes().to_vec(),
+    }
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> Iterator for PortStream<I> {
+    type Item = PortData;
+    fn next(&mut self) -> Option<PortData> {
+        loop {
+            match self.packets.next()? {
+                (_, ITMPacket::SoftwarePageNumber(page)) => self.page = page.0,
+                (time, ITMPacket::Software(port, ref value)) if self.page + port.0 == self.port => {
+                    return Some(PortData::Bytes(time, value_bytes(value)));
+                },
+                (time, ITMPacket::Overflow) => return Some(PortData::Overflow(time)),
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_stream() {
+        let packets = vec![
+            (1, ITMPacket::Software(InstrumentationPort(0), DataValue::U32(0x04030201))),
+            (2, ITMPacket::Software(InstrumentationPort(1), DataValue::U8(9))),
+            (3, ITMPacket::Overflow),
+            (4, ITMPacket::SoftwarePageNumber(InstrumentationPort(32))),
+            (5, ITMPacket::Software(InstrumentationPort(0), DataValue::U8(9))),
+            (6, ITMPacket::SoftwarePageNumber(InstrumentationPort(0))),
+            (7, ITMPacket::Software(InstrumentationPort(0), DataValue::U16(0x0605))),
+        ];
+        let data: Vec<PortData> = PortStream::new(packets.into_iter(), InstrumentationPort(0)).collect();
+        assert_eq!(data, vec![
+            PortData::Bytes(1, vec![1, 2, 3, 4]),
+            PortData::Overflow(3),
+            PortData::Bytes(7, vec![5, 6]),
+        ]);
+    }
+}
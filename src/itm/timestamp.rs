@@ -0,0 +1,108 @@
+//! Reconstructs absolute time for ITM packets from the
+//! local timestamp deltas.
+
+use std::collections::VecDeque;
+use super::types::*;
+
+/// Item that contains an ITM packet. Allows passing other data, such
+/// as the input offset of the packet, through the `Timestamper`.
+pub trait AsPacket {
+    fn packet(&self) -> &ITMPacket;
+}
+
+impl AsPacket for ITMPacket {
+    fn packet(&self) -> &ITMPacket {
+        self
+    }
+}
+
+impl<T> AsPacket for (T, ITMPacket) {
+    fn packet(&self) -> &ITMPacket {
+        &self.1
+    }
+}
+
+/// Iterator adapter that pairs each ITM packet with the absolute
+/// local timestamp, counted in timestamp clock ticks since the
+/// start of the stream.
+///
+/// A local timestamp packet is output after the packets it
+/// applies to, so packets are buffered until the next timestamp
+/// arrives. Packets after the last timestamp in the stream get
+/// the last known time.
+pub struct Timestamper<I: Iterator> {
+    packets: I,
+    time: u64,
+    pending: VecDeque<I::Item>,
+    ready: VecDeque<(u64, I::Item)>,
+}
+
+impl<I: Iterator> Timestamper<I> where I::Item: AsPacket {
+    pub fn new(packets: I) -> Timestamper<I> {
+        Timestamper::with_time(packets, 0)
+    }
+
+    /// Starts from a known time, when decoding from the middle of a stream.
+    pub fn with_time(packets: I, time: u64) -> Timestamper<I> {
+        Timestamper {
+            packets,
+            time,
+            pending: VecDeque::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Current absolute time, including all timestamps read so far.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    fn flush(&mut self) {
+        let time = self.time;
+        self.ready.extend(self.pending.drain(..).map(|p| (time, p)));
+    }
+}
+
+impl<I: Iterator> Iterator for Timestamper<I> where I::Item: AsPacket {
+    type Item = (u64, I::Item);
+    fn next(&mut self) -> Option<(u64, I::Item)> {
+        while self.ready.is_empty() {
+            match self.packets.next() {
+                Some(item) => {
+                    if let ITMPacket::LocalTimestamp(_, delta) = *item.packet() {
+                        self.time += delta.0 as u64;
+                        self.flush();
+                        self.ready.push_back((self.time, item));
+                    } else {
+                        self.pending.push_back(item);
+                    }
+                },
+                None => {
+                    self.flush();
+                    break;
+                }
+            }
+        }
+
+        self.ready.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delayed_assignment() {
+        let packets = vec![
+            ITMPacket::Software(InstrumentationPort(0), DataValue::U8(1)),
+            ITMPacket::LocalTimestamp(TimestampSync::Synchronous, LocalTimestampDelta(10)),
+            ITMPacket::Software(InstrumentationPort(1), DataValue::U8(2)),
+            ITMPacket::Overflow,
+            ITMPacket::LocalTimestamp(TimestampSync::Synchronous, LocalTimestampDelta(5)),
+            ITMPacket::Synchronization,
+        ];
+        let times: Vec<u64> = Timestamper::new(packets.into_iter()).map(|(t, _)| t).collect();
+        assert_eq!(times, vec![10, 10, 15, 15, 15, 15]);
+    }
+}
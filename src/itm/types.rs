@@ -2,9 +2,12 @@
 //! Reference: ARMv7-M Architecture Reference Manual
 
 use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 /// Supported ITM packet types.
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ITMPacket {
     /// This packet is sent periodically for
     /// synchronizing hardware to byte boundaries.
@@ -60,6 +63,7 @@ pub enum ITMPacket {
 
 /// Represents a memory address on the target processor.
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Address(pub u32);
 
 impl fmt::Debug for Address {
@@ -70,23 +74,50 @@ impl fmt::Debug for Address {
 
 /// Represents an exception/interrupt number
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExceptionNumber(pub u32);
 
+impl ExceptionNumber {
+    /// Returns the architectural name of the exception,
+    /// or IRQn for external interrupts.
+    pub fn name(&self) -> String {
+        match self.0 {
+            0 => String::from("Thread"),
+            1 => String::from("Reset"),
+            2 => String::from("NMI"),
+            3 => String::from("HardFault"),
+            4 => String::from("MemManage"),
+            5 => String::from("BusFault"),
+            6 => String::from("UsageFault"),
+            11 => String::from("SVCall"),
+            12 => String::from("DebugMonitor"),
+            14 => String::from("PendSV"),
+            15 => String::from("SysTick"),
+            n if n >= 16 => format!("IRQ{}", n - 16),
+            n => format!("Exception{}", n),
+        }
+    }
+}
+
 /// Represents a watchpoint comparator index
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ComparatorIndex(pub u32);
 
 /// Represents software instrumentation port number
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InstrumentationPort(pub u32);
 
 /// Represents local timestamp delta value
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LocalTimestampDelta(pub u32);
 
 /// Represents global timestamp value.
 /// known_mask identifies which bits of the timestamp are valid.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GlobalTimestampValue {
     pub timestamp: u64,
     pub known_mask: u64,
@@ -96,6 +127,7 @@ pub struct GlobalTimestampValue {
 
 /// A variably sized data value, corresponding to bus access size.
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DataValue {
     U8(u8), U16(u16), U32(u32)
 }
@@ -108,6 +140,15 @@ impl DataValue {
             DataValue::U32(word) => word
         }
     }
+
+    /// Size of the value in bits.
+    pub fn bit_width(&self) -> u8 {
+        match *self {
+            DataValue::U8(_) => 8,
+            DataValue::U16(_) => 16,
+            DataValue::U32(_) => 32,
+        }
+    }
 }
 
 impl fmt::Debug for DataValue {
@@ -122,6 +163,7 @@ impl fmt::Debug for DataValue {
 
 /// Relation of timestamp to other trace events
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TimestampSync {
     /// Timestamp is synchronous to data packet
     Synchronous,
@@ -138,6 +180,7 @@ pub enum TimestampSync {
 
 /// Stores information about which event counters have overflowed/wrapped.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventCounterFlags {
     pub cpicnt: bool,
     pub exccnt: bool,
@@ -149,6 +192,7 @@ pub struct EventCounterFlags {
 
 /// Exception entry/exit event
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ExceptionEvent {
     /// Exception handler started from beginning.
     Enter,
@@ -163,6 +207,7 @@ pub enum ExceptionEvent {
 
 /// Extended information
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExtendedInformation {
     pub data: u32,
     pub bitcount: u8,
@@ -1,3 +1,24 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "python")]
+extern crate pyo3;
+// The pyo3 macros refer to ::core, which the 2015 edition does not
+// have in the extern prelude.
+#[cfg(feature = "python")]
+extern crate core;
+
 pub mod itm;
 pub mod tpiu;
+pub mod stm;
+pub mod mtb;
 pub mod utils;
+pub mod decoder;
+pub mod elf;
+pub mod flow;
+pub mod event;
+pub mod export;
+pub mod input;
+pub mod ffi;
+pub mod log;
+#[cfg(feature = "python")]
+mod python;
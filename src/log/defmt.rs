@@ -0,0 +1,950 @@
+//! Decodes defmt log frames written to a stimulus port. The format
+//! strings are read from the `.defmt` section of the firmware ELF,
+//! where each interned string is a symbol whose address is the index
+//! sent by the device.
+
+extern crate object;
+extern crate gimli;
+use self::object::{Object, ObjectSection, ObjectSymbol};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use ::itm::types::*;
+use ::itm::stream::{PortStream, PortData};
+use super::types::*;
+use super::reader::{Reader, invalid};
+
+/// Framing of the log frames on the port.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Encoding {
+    /// Frames back to back, found by decoding the arguments.
+    Raw,
+
+    /// Each frame is rzCOBS encoded and terminated by a zero byte.
+    Rzcobs,
+}
+
+/// Kind of an interned string.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Tag {
+    /// Log statement with a level.
+    Level(Level),
+
+    /// `defmt::println!()`, which has no level.
+    Println,
+
+    /// Format of the timestamp sent with each log frame.
+    Timestamp,
+
+    /// Format of a derived `Format` implementation. Enums list the
+    /// formats of all variants separated by `|`.
+    Derived,
+
+    /// Format of a manual `Format` implementation, or an interned string.
+    Other,
+}
+
+impl Tag {
+    fn from_name(name: &str) -> Tag {
+        match name {
+            "defmt_trace" => Tag::Level(Level::Trace),
+            "defmt_debug" => Tag::Level(Level::Debug),
+            "defmt_info" => Tag::Level(Level::Info),
+            "defmt_warn" => Tag::Level(Level::Warn),
+            "defmt_error" => Tag::Level(Level::Error),
+            "defmt_println" => Tag::Println,
+            "defmt_timestamp" => Tag::Timestamp,
+            "defmt_derived" => Tag::Derived,
+            _ => Tag::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TableEntry {
+    pub tag: Tag,
+    pub format: String,
+    pub location: Option<Location>,
+}
+
+/// Interned strings of one firmware build.
+#[derive(Debug, Clone)]
+pub struct Table {
+    encoding: Encoding,
+    entries: BTreeMap<u16, TableEntry>,
+    timestamp: Option<u16>,
+}
+
+/// Nested formats deeper than this are treated as corrupted data.
+const MAX_DEPTH: usize = 16;
+
+fn dwarf_error(e: gimli::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+impl Table {
+    pub fn new(encoding: Encoding) -> Table {
+        Table { encoding, entries: BTreeMap::new(), timestamp: None }
+    }
+
+    pub fn insert(&mut self, index: u16, entry: TableEntry) {
+        if entry.tag == Tag::Timestamp {
+            self.timestamp = Some(index);
+        }
+        self.entries.insert(index, entry);
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn get(&self, index: u16) -> Option<&TableEntry> {
+        self.entries.get(&index)
+    }
+
+    /// Loads the table from ELF file contents. Locations are filled
+    /// in if the file has debug info.
+    pub fn from_elf(data: &[u8]) -> Result<Table, Error> {
+        let file = object::File::parse(data)
+            .map_err(|e| invalid(e.to_string()))?;
+        let section = file.section_by_name(".defmt")
+            .ok_or_else(|| invalid(String::from("No .defmt section in ELF file")))?;
+
+        let mut encoding = Encoding::Raw;
+        let mut symbols = Vec::new();
+        for symbol in file.symbols() {
+            let name = match symbol.name() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if let Some(name) = name.strip_prefix("_defmt_encoding_ = ") {
+                encoding = match name {
+                    "rzcobs" => Encoding::Rzcobs,
+                    "raw" => Encoding::Raw,
+                    other => return Err(invalid(format!("Unsupported defmt encoding {:?}", other))),
+                };
+            } else if symbol.section_index() == Some(section.index()) {
+                if let Some(fields) = parse_symbol(name) {
+                    symbols.push((symbol.address(), fields));
+                }
+            }
+        }
+
+        let locations = locations(&file)?;
+        let mut table = Table::new(encoding);
+        for (address, mut fields) in symbols {
+            let tag = Tag::from_name(fields.get("tag").map(|s| &s[..]).unwrap_or(""));
+            table.insert(address as u16, TableEntry {
+                tag,
+                format: fields.remove("data").unwrap_or_default(),
+                location: locations.get(&address).cloned(),
+            });
+        }
+        Ok(table)
+    }
+
+    /// Decodes one frame from the start of `data`, and returns the
+    /// message with the number of bytes used. Returns an error of
+    /// kind UnexpectedEof if the frame is not complete.
+    pub fn decode(&self, time: u64, data: &[u8]) -> Result<(LogMessage, usize), Error> {
+        let mut reader = Reader::new(data, "defmt frame");
+        let index = reader.u16()?;
+        let entry = self.get(index)
+            .ok_or_else(|| invalid(format!("Unknown defmt index {}", index)))?;
+        let level = match entry.tag {
+            Tag::Level(level) => Some(level),
+            Tag::Println => None,
+            _ => return Err(invalid(format!("defmt index {} is not a log statement", index))),
+        };
+
+        let timestamp = match self.timestamp.and_then(|i| self.get(i)) {
+            Some(timestamp) => Some(self.format(&timestamp.format, &mut reader, 0)?),
+            None => None,
+        };
+        let text = self.format(&entry.format, &mut reader, 0)?;
+        let message = LogMessage { time, level, timestamp, text, location: entry.location.clone() };
+        Ok((message, reader.position))
+    }
+
+    /// Reads the arguments of a format string and formats it.
+    fn format(&self, format: &str, reader: &mut Reader, depth: usize) -> Result<String, Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(String::from("Too deeply nested defmt format")));
+        }
+
+        let segments = parse_format(format)?;
+        let mut types: BTreeMap<usize, Type> = BTreeMap::new();
+        for segment in &segments {
+            if let Segment::Parameter(ref p) = *segment {
+                let entry = types.entry(p.index).or_insert(p.ty);
+                if let (Type::BitField(a, b), Type::BitField(c, d)) = (*entry, p.ty) {
+                    *entry = Type::BitField(a.min(c), b.max(d));
+                }
+            }
+        }
+        if types.keys().enumerate().any(|(i, &index)| i != index) {
+            return Err(invalid(format!("Missing argument in defmt format {:?}", format)));
+        }
+
+        let mut values = Vec::new();
+        for ty in types.values() {
+            values.push(self.read_value(*ty, reader, depth)?);
+        }
+
+        let mut text = String::new();
+        for segment in &segments {
+            match *segment {
+                Segment::Literal(ref s) => text.push_str(s),
+                Segment::Parameter(ref p) => text.push_str(&render(&values[p.index], p.ty, &p.hint)),
+            }
+        }
+        Ok(text)
+    }
+
+    fn read_value(&self, ty: Type, reader: &mut Reader, depth: usize) -> Result<Value, Error> {
+        Ok(match ty {
+            Type::U(size) => Value::U(reader.uint(size)?),
+            Type::I(size) => {
+                let shift = 128 - 8 * size as u32;
+                Value::I(((reader.uint(size)? << shift) as i128) >> shift, size)
+            },
+            Type::F32 => Value::F32(f32::from_bits(reader.uint(4)? as u32)),
+            Type::F64 => Value::F64(f64::from_bits(reader.uint(8)? as u64)),
+            Type::Bool => Value::Bool(reader.u8()? != 0),
+            Type::Char => {
+                let c = reader.uint(4)? as u32;
+                Value::Char(::std::char::from_u32(c).ok_or_else(|| invalid(format!("Invalid char {}", c)))?)
+            },
+            Type::Usize => Value::U(reader.varint()? as u128),
+            Type::Isize => {
+                let v = reader.varint()?;
+                Value::I(((v >> 1) as i64 ^ -((v & 1) as i64)) as i128, 4)
+            },
+            Type::Str => {
+                let length = reader.varint()? as usize;
+                Value::Str(String::from_utf8_lossy(reader.take(length)?).into_owned())
+            },
+            Type::IStr => {
+                let index = reader.u16()?;
+                let entry = self.get(index)
+                    .ok_or_else(|| invalid(format!("Unknown defmt string index {}", index)))?;
+                Value::Str(entry.format.clone())
+            },
+            Type::Slice => {
+                let length = reader.varint()? as usize;
+                Value::Bytes(reader.take(length)?.to_vec())
+            },
+            Type::Array(length) => Value::Bytes(reader.take(length)?.to_vec()),
+            Type::Format => Value::Str(self.read_format(reader, depth)?),
+            Type::FormatSlice => {
+                let length = reader.varint()? as usize;
+                let mut items = Vec::new();
+                for _ in 0..length {
+                    items.push(self.read_format(reader, depth)?);
+                }
+                Value::Str(format!("[{}]", items.join(", ")))
+            },
+            Type::BitField(low, high) => {
+                let first = low / 8;
+                let last = (high.max(low + 1) - 1) / 8;
+                Value::U(reader.uint((last - first + 1) as usize)? << (8 * first))
+            },
+        })
+    }
+
+    /// Reads a value of a type that implements `Format`.
+    fn read_format(&self, reader: &mut Reader, depth: usize) -> Result<String, Error> {
+        let index = reader.u16()?;
+        let entry = self.get(index)
+            .ok_or_else(|| invalid(format!("Unknown defmt index {}", index)))?;
+        if entry.tag != Tag::Derived {
+            return self.format(&entry.format, reader, depth + 1);
+        }
+
+        // Derived enums are sent with the discriminant of the variant.
+        let variants = split_variants(&entry.format);
+        let format = if variants.len() > 1 {
+            let discriminant = if variants.len() > 256 { reader.u16()? as usize } else { reader.u8()? as usize };
+            variants.get(discriminant)
+                .ok_or_else(|| invalid(format!("Invalid enum discriminant {}", discriminant)))?
+        } else {
+            variants[0]
+        };
+        self.format(format, reader, depth + 1)
+    }
+}
+
+/// Parses a symbol name in the `.defmt` section, which is a JSON
+/// object with string values.
+fn parse_symbol(name: &str) -> Option<BTreeMap<String, String>> {
+    fn string(chars: &mut ::std::iter::Peekable<::std::str::Chars>) -> Option<String> {
+        if chars.next()? != '"' {
+            return None;
+        }
+        let mut s = String::new();
+        loop {
+            match chars.next()? {
+                '"' => return Some(s),
+                '\\' => match chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+                        s.push(::std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                    },
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    let mut chars = name.trim().chars().peekable();
+    let mut fields = BTreeMap::new();
+    if chars.next()? != '{' {
+        return None;
+    }
+    loop {
+        let key = string(&mut chars)?;
+        if chars.next()? != ':' {
+            return None;
+        }
+        fields.insert(key, string(&mut chars)?);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(fields),
+            _ => return None,
+        }
+    }
+}
+
+/// Source locations of the log statements by table index. Each
+/// statement has a static variable named `DEFMT_LOG_STATEMENT` placed
+/// at the address of its interned string.
+fn locations(file: &object::File) -> Result<BTreeMap<u64, Location>, Error> {
+    let sections = gimli::DwarfSections::load(|id| -> Result<&[u8], Error> {
+        match file.section_by_name(id.name()) {
+            Some(section) => section.data().map_err(|e| invalid(e.to_string())),
+            None => Ok(&[])
+        }
+    })?;
+    let dwarf = sections.borrow(|s| gimli::EndianSlice::new(s, gimli::LittleEndian));
+
+    let mut locations = BTreeMap::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().map_err(dwarf_error)? {
+        let unit = dwarf.unit(header).map_err(dwarf_error)?;
+        let mut namespaces: Vec<(isize, String)> = Vec::new();
+        let mut entries = unit.entries();
+        while let Some(entry) = entries.next_dfs().map_err(dwarf_error)? {
+            let depth = entry.depth();
+            while namespaces.last().is_some_and(|n| n.0 >= depth) {
+                namespaces.pop();
+            }
+
+            let name = match entry.attr_value(gimli::DW_AT_name) {
+                Some(name) => dwarf.attr_string(&unit, name).map_err(dwarf_error)?.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if entry.tag() == gimli::DW_TAG_namespace {
+                namespaces.push((depth, name));
+                continue;
+            }
+            if entry.tag() != gimli::DW_TAG_variable || name != "DEFMT_LOG_STATEMENT" {
+                continue;
+            }
+
+            // The location is a single DW_OP_addr, with a 4 or 8 byte address.
+            let address = match entry.attr_value(gimli::DW_AT_location).and_then(|v| v.exprloc_value()) {
+                Some(expr) if (expr.0.len() == 5 || expr.0.len() == 9) && expr.0[0] == gimli::DW_OP_addr.0 => {
+                    expr.0[1..].iter().rev().fold(0, |v, &b| (v << 8) | b as u64)
+                },
+                _ => continue,
+            };
+            let line = entry.attr_value(gimli::DW_AT_decl_line).and_then(|v| v.udata_value()).unwrap_or(0);
+            let file_index = match entry.attr_value(gimli::DW_AT_decl_file) {
+                Some(gimli::AttributeValue::FileIndex(index)) => Some(index),
+                value => value.and_then(|v| v.udata_value()),
+            };
+            let path = match (file_index, unit.line_program.as_ref()) {
+                (Some(index), Some(program)) => match program.header().file(index) {
+                    Some(entry) => {
+                        let name = dwarf.attr_string(&unit, entry.path_name())
+                            .map_err(dwarf_error)?.to_string_lossy().into_owned();
+                        let dir = match entry.directory(program.header()) {
+                            Some(dir) => dwarf.attr_string(&unit, dir)
+                                .map_err(dwarf_error)?.to_string_lossy().into_owned(),
+                            None => String::new()
+                        };
+                        if dir.is_empty() || name.starts_with('/') {
+                            name
+                        } else {
+                            format!("{}/{}", dir, name)
+                        }
+                    },
+                    None => String::from("???"),
+                },
+                _ => String::from("???"),
+            };
+
+            // Functions and blocks also appear as namespaces named
+            // with braces, such as {impl#0}.
+            let module: Vec<&str> = namespaces.iter().map(|n| &n.1[..]).filter(|n| !n.starts_with('{')).collect();
+            locations.insert(address, Location { file: path, line: line as u32, module: module.join("::") });
+        }
+    }
+    Ok(locations)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Type {
+    U(usize),
+    I(usize),
+    F32,
+    F64,
+    Bool,
+    Char,
+    Usize,
+    Isize,
+    Str,
+    IStr,
+    Slice,
+    Array(usize),
+    Format,
+    FormatSlice,
+    BitField(u32, u32),
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Type> {
+        Some(match name {
+            "u8" => Type::U(1),
+            "u16" => Type::U(2),
+            "u32" => Type::U(4),
+            "u64" => Type::U(8),
+            "u128" => Type::U(16),
+            "i8" => Type::I(1),
+            "i16" => Type::I(2),
+            "i32" => Type::I(4),
+            "i64" => Type::I(8),
+            "i128" => Type::I(16),
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "bool" => Type::Bool,
+            "char" => Type::Char,
+            "usize" => Type::Usize,
+            "isize" => Type::Isize,
+            "str" | "__internal_Display" | "__internal_Debug" => Type::Str,
+            "istr" => Type::IStr,
+            "[u8]" => Type::Slice,
+            "?" | "" => Type::Format,
+            "[?]" => Type::FormatSlice,
+            _ if name.starts_with("[u8;") && name.ends_with(']') => {
+                Type::Array(name[4..name.len() - 1].trim().parse().ok()?)
+            },
+            _ => {
+                let mut range = name.splitn(2, "..");
+                let low = range.next()?.parse().ok()?;
+                let high = range.next()?.parse().ok()?;
+                if low >= high || high > 128 {
+                    return None;
+                }
+                Type::BitField(low, high)
+            },
+        })
+    }
+}
+
+/// Display hint after the colon of a parameter, such as `#010x`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct Hint {
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    kind: String,
+}
+
+impl Hint {
+    fn parse(mut s: &str) -> Hint {
+        let mut hint = Hint::default();
+        if s.starts_with('#') {
+            hint.alternate = true;
+            s = &s[1..];
+        }
+        if s.starts_with('0') {
+            hint.zero = true;
+            s = &s[1..];
+        }
+        let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+        hint.width = s[..digits].parse().unwrap_or(0);
+        hint.kind = String::from(&s[digits..]);
+        hint
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Parameter {
+    index: usize,
+    ty: Type,
+    hint: Hint,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    Parameter(Parameter),
+}
+
+/// Parses a format string with `{[index][=type][:hint]}` parameters.
+fn parse_format(format: &str) -> Result<Vec<Segment>, Error> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut next_index = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '}' {
+            if chars.next() != Some('}') {
+                return Err(invalid(format!("Unmatched }} in defmt format {:?}", format)));
+            }
+            literal.push('}');
+            continue;
+        }
+        if c != '{' {
+            literal.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            literal.push('{');
+            continue;
+        }
+
+        let mut spec = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => spec.push(c),
+                None => return Err(invalid(format!("Unterminated parameter in defmt format {:?}", format))),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal.split_off(0)));
+        }
+
+        let (spec, hint) = match spec.find(':') {
+            Some(i) => (&spec[..i], Hint::parse(&spec[i + 1..])),
+            None => (&spec[..], Hint::default()),
+        };
+        let (index, ty) = match spec.find('=') {
+            Some(i) => (&spec[..i], &spec[i + 1..]),
+            None => (spec, ""),
+        };
+        let index = if index.is_empty() {
+            next_index += 1;
+            next_index - 1
+        } else {
+            index.parse().map_err(|_| invalid(format!("Invalid parameter index in defmt format {:?}", format)))?
+        };
+        let ty = Type::parse(ty)
+            .ok_or_else(|| invalid(format!("Unknown type {:?} in defmt format {:?}", ty, format)))?;
+        segments.push(Segment::Parameter(Parameter { index, ty, hint }));
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+/// Splits the format of a derived enum at the `|` between variants.
+fn split_variants(format: &str) -> Vec<&str> {
+    let mut variants = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in format.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                variants.push(&format[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    variants.push(&format[start..]);
+    variants
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    U(u128),
+    I(i128, usize),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+fn pad(prefix: &str, digits: String, hint: &Hint) -> String {
+    let length = prefix.len() + digits.len();
+    if length >= hint.width {
+        format!("{}{}", prefix, digits)
+    } else if hint.zero {
+        format!("{}{}{}", prefix, "0".repeat(hint.width - length), digits)
+    } else {
+        format!("{}{}{}", " ".repeat(hint.width - length), prefix, digits)
+    }
+}
+
+fn render_uint(value: u128, hint: &Hint) -> String {
+    let prefix = |p| if hint.alternate { p } else { "" };
+    match &hint.kind[..] {
+        "x" => pad(prefix("0x"), format!("{:x}", value), hint),
+        "X" => pad(prefix("0x"), format!("{:X}", value), hint),
+        "b" => pad(prefix("0b"), format!("{:b}", value), hint),
+        "o" => pad(prefix("0o"), format!("{:o}", value), hint),
+        "us" => format!("{}.{:06}", value / 1_000_000, value % 1_000_000),
+        "ms" => format!("{}.{:03}", value / 1000, value % 1000),
+        _ => pad("", value.to_string(), hint),
+    }
+}
+
+fn render(value: &Value, ty: Type, hint: &Hint) -> String {
+    let debug = hint.kind == "?";
+    match *value {
+        Value::U(v) => match ty {
+            Type::BitField(low, high) => render_uint((v >> low) & (u128::MAX >> (128 - (high - low))), hint),
+            _ => render_uint(v, hint),
+        },
+        Value::I(v, size) => match &hint.kind[..] {
+            // Radix formats show the two's complement bits.
+            "x" | "X" | "b" | "o" => render_uint(v as u128 & (u128::MAX >> (128 - 8 * size)), hint),
+            _ if v < 0 => format!("-{}", render_uint(v.unsigned_abs(), hint)),
+            _ => render_uint(v as u128, hint),
+        },
+        Value::F32(v) if debug => format!("{:?}", v),
+        Value::F32(v) => v.to_string(),
+        Value::F64(v) if debug => format!("{:?}", v),
+        Value::F64(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Char(v) if debug => format!("{:?}", v),
+        Value::Char(v) => v.to_string(),
+        Value::Str(ref v) if debug && ty == Type::Str => format!("{:?}", v),
+        Value::Str(ref v) => v.clone(),
+        Value::Bytes(ref v) if hint.kind == "a" => {
+            let text: String = v.iter().flat_map(|&b| ::std::ascii::escape_default(b)).map(char::from).collect();
+            format!("b\"{}\"", text.replace('"', "\\\""))
+        },
+        Value::Bytes(ref v) => {
+            let items: Vec<String> = v.iter().map(|&b| render_uint(b as u128, hint)).collect();
+            format!("[{}]", items.join(", "))
+        },
+    }
+}
+
+/// Decodes an rzCOBS frame without the terminating zero. The result
+/// can have extra zeros at the end, which the frame decoder ignores.
+pub fn rzcobs_decode(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let corrupt = || invalid(String::from("Corrupted rzCOBS frame"));
+    let mut result = Vec::new();
+    let mut bytes = data.iter().rev().cloned();
+    while let Some(x) = bytes.next() {
+        match x {
+            0x00 => return Err(corrupt()),
+            0x01..=0x7F => {
+                for i in 0..7 {
+                    if x & (1 << (6 - i)) != 0 {
+                        result.push(0);
+                    } else {
+                        result.push(bytes.next().ok_or_else(corrupt)?);
+                    }
+                }
+            },
+            0x80..=0xFE => {
+                result.push(0);
+                for _ in 0..(x & 0x7F) + 7 {
+                    result.push(bytes.next().ok_or_else(corrupt)?);
+                }
+            },
+            0xFF => {
+                for _ in 0..134 {
+                    result.push(bytes.next().ok_or_else(corrupt)?);
+                }
+            },
+        }
+    }
+    result.reverse();
+    Ok(result)
+}
+
+/// Iterator adapter from `(time, ITMPacket)` pairs to the log
+/// messages written to one stimulus port.
+pub struct DefmtDecoder<I> {
+    stream: PortStream<I>,
+    table: Table,
+    buffer: Vec<u8>,
+
+    /// Time of the first byte in buffer.
+    time: u64,
+
+    /// Time of an overflow, while skipping to the next rzCOBS frame.
+    broken: Option<u64>,
+    discarded: bool,
+
+    events: VecDeque<LogEvent>,
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> DefmtDecoder<I> {
+    pub fn new(packets: I, port: InstrumentationPort, table: Table) -> DefmtDecoder<I> {
+        DefmtDecoder {
+            stream: PortStream::new(packets, port),
+            table,
+            buffer: Vec::new(),
+            time: 0,
+            broken: None,
+            discarded: false,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn decoded(&mut self, result: Result<LogMessage, Error>) {
+        self.events.push_back(match result {
+            Ok(message) => LogEvent::Message(message),
+            Err(e) => LogEvent::Error { time: self.time, message: e.to_string() },
+        });
+    }
+
+    fn push_rzcobs(&mut self, time: u64, bytes: &[u8]) {
+        for &byte in bytes {
+            if let Some(overflow) = self.broken {
+                if byte == 0 {
+                    if self.discarded {
+                        self.events.push_back(LogEvent::Error {
+                            time: overflow, message: String::from("Frame broken by ITM overflow")
+                        });
+                    }
+                    self.broken = None;
+                    self.discarded = false;
+                } else {
+                    self.discarded = true;
+                }
+            } else if byte == 0 {
+                if !self.buffer.is_empty() {
+                    let result = rzcobs_decode(&self.buffer)
+                        .and_then(|frame| self.table.decode(self.time, &frame).map(|m| m.0));
+                    self.decoded(result);
+                    self.buffer.clear();
+                }
+            } else {
+                if self.buffer.is_empty() {
+                    self.time = time;
+                }
+                self.buffer.push(byte);
+            }
+        }
+    }
+
+    fn push_raw(&mut self, time: u64, bytes: &[u8]) {
+        if self.buffer.is_empty() {
+            self.time = time;
+        }
+        self.buffer.extend_from_slice(bytes);
+        while !self.buffer.is_empty() {
+            match self.table.decode(self.time, &self.buffer) {
+                Ok((message, length)) => {
+                    self.buffer.drain(..length);
+                    self.time = time;
+                    self.decoded(Ok(message));
+                },
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    // Raw frames cannot be resynchronized.
+                    self.buffer.clear();
+                    self.decoded(Err(e));
+                },
+            }
+        }
+    }
+
+    fn overflow(&mut self, time: u64) {
+        match self.table.encoding() {
+            Encoding::Rzcobs => {
+                if self.broken.is_none() {
+                    self.broken = Some(time);
+                    self.discarded = !self.buffer.is_empty();
+                }
+            },
+            Encoding::Raw => self.events.push_back(LogEvent::Error {
+                time, message: String::from("ITM overflow, raw defmt stream may be out of sync")
+            }),
+        }
+        self.buffer.clear();
+    }
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> Iterator for DefmtDecoder<I> {
+    type Item = LogEvent;
+    fn next(&mut self) -> Option<LogEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            match self.stream.next()? {
+                PortData::Bytes(time, bytes) => match self.table.encoding() {
+                    Encoding::Rzcobs => self.push_rzcobs(time, &bytes),
+                    Encoding::Raw => self.push_raw(time, &bytes),
+                },
+                PortData::Overflow(time) => self.overflow(time),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rzcobs_encode(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut run, mut zeros) = (0u8, 0u8);
+        for &byte in data {
+            if run < 7 {
+                if byte == 0 {
+                    zeros |= 1 << run;
+                } else {
+                    out.push(byte);
+                }
+                run += 1;
+                if run == 7 && zeros != 0 {
+                    out.push(zeros);
+                    run = 0;
+                    zeros = 0;
+                }
+            } else if byte == 0 {
+                out.push((run - 7) | 0x80);
+                run = 0;
+                zeros = 0;
+            } else {
+                out.push(byte);
+                run += 1;
+                if run == 134 {
+                    out.push(0xFF);
+                    run = 0;
+                    zeros = 0;
+                }
+            }
+        }
+        if run > 0 && run < 7 {
+            out.push(zeros | ((0xFF << run) & 0x7F));
+        } else if run >= 7 {
+            out.push((run - 7) | 0x80);
+        }
+        out
+    }
+
+    fn table(encoding: Encoding) -> Table {
+        let mut table = Table::new(encoding);
+        let location = Location { file: String::from("src/main.rs"), line: 12, module: String::from("app") };
+        table.insert(1, TableEntry { tag: Tag::Level(Level::Info), format: String::from("x={=u8} y={=i16:#06x} {=str:?}"),
+                                     location: Some(location) });
+        table.insert(2, TableEntry { tag: Tag::Println, format: String::from("{0=0..4:b} {0=4..8} {{{1=?}}}"), location: None });
+        table.insert(3, TableEntry { tag: Tag::Derived, format: String::from("None|Some({=[u8]:x})"), location: None });
+        table.insert(5, TableEntry { tag: Tag::Level(Level::Warn), format: String::from("idle|busy {=u8}"), location: None });
+        table.insert(4, TableEntry { tag: Tag::Timestamp, format: String::from("{=u32:us}"), location: None });
+        table
+    }
+
+    fn write(time: u64, bytes: &[u8]) -> Vec<(u64, ITMPacket)> {
+        bytes.iter().map(|&b| (time, ITMPacket::Software(InstrumentationPort(0), DataValue::U8(b)))).collect()
+    }
+
+    #[test]
+    fn test_rzcobs() {
+        let frames: Vec<Vec<u8>> = vec![
+            vec![1, 0, 2, 0, 0, 0, 0, 3], (1..=200).collect(), vec![0; 20], (0..=255).cycle().take(1000).collect(),
+        ];
+        for frame in frames {
+            let encoded = rzcobs_encode(&frame);
+            assert!(!encoded.contains(&0));
+            let decoded = rzcobs_decode(&encoded).unwrap();
+            assert_eq!(&decoded[..frame.len()], &frame[..]);
+            assert!(decoded[frame.len()..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let table = table(Encoding::Raw);
+        let frame = [1, 0, 0x40, 0x42, 0x0F, 0x00, 7, 0xFE, 0xFF, 2, b'h', b'i'];
+        let (message, length) = table.decode(5, &frame).unwrap();
+        assert_eq!(length, frame.len());
+        assert_eq!(message.to_string(), "1.000000 INFO  x=7 y=0xfffe \"hi\"\n└─ app @ src/main.rs:12");
+
+        let frame = [2, 0, 1, 0, 0, 0, 0x5A, 3, 0, 1, 2, 0xAB, 0xCD];
+        let (message, _) = table.decode(5, &frame).unwrap();
+        assert_eq!(message.level, None);
+        assert_eq!(message.text, "1010 5 {Some([ab, cd])}");
+
+        assert_eq!(table.decode(0, &frame[..10]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        // A | in a log statement is not an enum.
+        let frame = [5, 0, 1, 0, 0, 0, 42];
+        let (message, length) = table.decode(5, &frame).unwrap();
+        assert_eq!((message.text.as_str(), length), ("idle|busy 42", frame.len()));
+        assert_eq!(table.decode(0, &[9, 0]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_from_elf() {
+        let table = Table::from_elf(include_bytes!("../../testdata/defmt.elf")).unwrap();
+        assert_eq!(table.encoding(), Encoding::Rzcobs);
+        let entry = table.get(2).unwrap();
+        assert_eq!((entry.tag, &entry.format[..]), (Tag::Level(Level::Info), "temperature {=i16}"));
+        let location = entry.location.as_ref().unwrap();
+        assert!(location.file.ends_with("fixture.rs"));
+        assert_eq!((location.line, &location.module[..]), (13, "fixture::sensor::read"));
+        let derived = table.get(1).unwrap();
+        assert_eq!((derived.tag, &derived.format[..]), (Tag::Derived, "Idle|Busy({=u8})"));
+        assert_eq!(derived.location.as_ref().map(|l| (l.line, &l.module[..])), Some((21, "fixture::start")));
+        assert_eq!(table.decode(0, &[2, 0, 0xFE, 0xFF]).unwrap().0.text, "temperature -2");
+    }
+
+    #[test]
+    fn test_parse_symbol() {
+        let fields = parse_symbol(r#"{"package":"app","tag":"defmt_info","data":"a \"{=u8}\"","disambiguator":"1"}"#).unwrap();
+        assert_eq!(fields["tag"], "defmt_info");
+        assert_eq!(fields["data"], "a \"{=u8}\"");
+        assert_eq!(parse_symbol("main"), None);
+    }
+
+    #[test]
+    fn test_decoder() {
+        let first = [1, 0, 1, 0, 0, 0, 7, 1, 0, 0, 0];
+        let second = [1, 0, 2, 0, 0, 0, 8, 2, 0, 0, 0];
+        let mut packets = Vec::new();
+        for &(time, frame) in &[(10, &first), (20, &second)] {
+            let mut encoded = rzcobs_encode(frame);
+            encoded.push(0);
+            packets.extend(write(time, &encoded));
+        }
+        // Overflow in the middle of a frame loses it.
+        let mut encoded = rzcobs_encode(&second);
+        encoded.push(0);
+        packets.extend(write(30, &encoded[..3]));
+        packets.push((35, ITMPacket::Overflow));
+        packets.extend(write(40, &encoded[5..]));
+        packets.extend(write(50, &encoded));
+
+        let events: Vec<LogEvent> = DefmtDecoder::new(packets.into_iter(), InstrumentationPort(0), table(Encoding::Rzcobs)).collect();
+        let texts: Vec<String> = events.iter().map(|e| match *e {
+            LogEvent::Message(ref m) => format!("{} {}", m.time, m.text),
+            LogEvent::Error { time, ref message } => format!("{} {}", time, message),
+        }).collect();
+        assert_eq!(texts, vec![
+            "10 x=7 y=0x0001 \"\"", "20 x=8 y=0x0002 \"\"", "35 Frame broken by ITM overflow", "50 x=8 y=0x0002 \"\"",
+        ]);
+    }
+}
@@ -0,0 +1,8 @@
+//! Decoders for logging frameworks that write binary log frames to
+//! a stimulus port, with the format strings stored in the firmware
+//! ELF instead of the device.
+
+pub mod types;
+pub mod defmt;
+pub mod tokenized;
+mod reader;
@@ -0,0 +1,70 @@
+//! Reading of the binary log frames, shared by the log decoders.
+
+use std::io::{Error, ErrorKind};
+
+pub fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads little endian values from the start of a frame. Running out
+/// of data is an error of kind UnexpectedEof, so that the caller can
+/// wait for the rest of the frame.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+
+    /// Number of bytes read so far.
+    pub position: usize,
+
+    /// Name of the frame in error messages, e.g. "defmt frame".
+    name: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], name: &'static str) -> Reader<'a> {
+        Reader { data, position: 0, name }
+    }
+
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.position < length {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("Incomplete {}", self.name)));
+        }
+        self.position += length;
+        Ok(&self.data[self.position - length..self.position])
+    }
+
+    pub fn uint(&mut self, size: usize) -> Result<u128, Error> {
+        Ok(self.take(size)?.iter().rev().fold(0, |v, &b| (v << 8) | b as u128))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    /// Unsigned LEB128 value.
+    pub fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid(format!("Too long varint in {}", self.name)))
+    }
+
+    /// Signed varint in zigzag encoding.
+    pub fn zigzag(&mut self) -> Result<i64, Error> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+}
@@ -0,0 +1,628 @@
+//! Decodes Pigweed style tokenized log messages written to a stimulus
+//! port. The device sends a 32-bit token that identifies the printf
+//! format string, followed by the arguments: integers as zigzag
+//! varints, floats as 4 bytes and strings as a length byte and the
+//! characters. The strings come from the `.pw_tokenizer.entries`
+//! section of the firmware ELF or from a token database CSV file.
+//!
+//! A message starts with a 32-bit write of the token. The rest of the
+//! write that completes a message is taken as padding, and writes of
+//! other sizes between messages are skipped. This also resynchronizes
+//! the stream after an overflow or an unknown token.
+
+extern crate object;
+use self::object::{Object, ObjectSection};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use ::itm::types::*;
+use ::itm::stream::{PortStream, PortData};
+use super::types::*;
+use super::reader::{Reader, invalid};
+
+/// Magic number at the start of each entry in the ELF section.
+const ENTRY_MAGIC: u32 = 0xBAA9_8DEE;
+
+/// Field widths and precisions above this are treated as corrupted data.
+const MAX_WIDTH: usize = 1024;
+
+/// Format strings by token. Several strings can have the same token,
+/// in which case the first one that matches the arguments is used.
+#[derive(Debug, Clone, Default)]
+pub struct TokenDatabase {
+    entries: BTreeMap<u32, Vec<String>>,
+}
+
+impl TokenDatabase {
+    pub fn new() -> TokenDatabase {
+        TokenDatabase { entries: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, token: u32, format: String) {
+        let formats = self.entries.entry(token).or_default();
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+
+    pub fn get(&self, token: u32) -> &[String] {
+        self.entries.get(&token).map(|f| &f[..]).unwrap_or(&[])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Loads the strings of the `.pw_tokenizer.entries` section from
+    /// ELF file contents.
+    pub fn from_elf(data: &[u8]) -> Result<TokenDatabase, Error> {
+        let file = object::File::parse(data)
+            .map_err(|e| invalid(e.to_string()))?;
+        let mut database = TokenDatabase::new();
+        let mut found = false;
+        for section in file.sections() {
+            if section.name().map(|n| n.starts_with(".pw_tokenizer.entries")).unwrap_or(false) {
+                found = true;
+                database.add_entries(section.data().map_err(|e| invalid(e.to_string()))?)?;
+            }
+        }
+        if !found {
+            return Err(invalid(String::from("No .pw_tokenizer.entries section in ELF file")));
+        }
+        Ok(database)
+    }
+
+    /// Adds the entries of a `.pw_tokenizer.entries` section. Each
+    /// entry has a header of magic, token, domain length and string
+    /// length, followed by the null terminated domain and string.
+    pub fn add_entries(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        let word = |d: &[u8], i: usize| u32::from_le_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]]);
+        while data.len() >= 16 {
+            // Entries can be separated by alignment padding.
+            if word(data, 0) != ENTRY_MAGIC {
+                data = &data[1..];
+                continue;
+            }
+            let token = word(data, 4);
+            let domain_length = word(data, 8) as usize;
+            let string_length = word(data, 12) as usize;
+            let end = 16 + domain_length + string_length;
+            if string_length == 0 || data.len() < end {
+                return Err(invalid(format!("Truncated token entry 0x{:08x}", token)));
+            }
+            let string = &data[16 + domain_length..end - 1];
+            self.insert(token, String::from_utf8_lossy(string).into_owned());
+            data = &data[end..];
+        }
+        Ok(())
+    }
+
+    /// Loads a token database CSV file. The first column is the token
+    /// in hex and the last one the string, with the removal date and
+    /// optional domain in between.
+    pub fn from_csv(text: &str) -> Result<TokenDatabase, Error> {
+        let mut database = TokenDatabase::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = parse_csv_line(line)
+                .ok_or_else(|| invalid(format!("Invalid CSV on line {}", number + 1)))?;
+            let token = match (fields.first(), fields.len()) {
+                (Some(token), 3..=4) => u32::from_str_radix(token.trim(), 16).ok(),
+                _ => None,
+            };
+            match token {
+                Some(token) => database.insert(token, fields[fields.len() - 1].clone()),
+                None => return Err(invalid(format!("Invalid token entry on line {}", number + 1))),
+            }
+        }
+        Ok(database)
+    }
+
+    /// Decodes one message from the start of `data`, and returns the
+    /// text with the number of bytes used. Returns an error of kind
+    /// UnexpectedEof if the message is not complete.
+    pub fn decode(&self, data: &[u8]) -> Result<(String, usize), Error> {
+        let mut reader = Reader::new(data, "tokenized message");
+        let token = reader.u32()?;
+        let formats = self.get(token);
+        if formats.is_empty() {
+            return Err(invalid(format!("Unknown token 0x{:08x}", token)));
+        }
+
+        // With colliding tokens, the arguments decide which one it is.
+        let mut error: Option<Error> = None;
+        for format in formats {
+            let mut arguments = reader.clone();
+            match printf(format, &mut arguments) {
+                Ok(text) => return Ok((text, arguments.position)),
+                Err(e) => match error {
+                    Some(ref previous) if previous.kind() == ErrorKind::UnexpectedEof => {},
+                    _ => error = Some(e),
+                },
+            }
+        }
+        Err(error.unwrap())
+    }
+}
+
+/// Splits a CSV line with quoted fields.
+fn parse_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    '"' => break,
+                    c => field.push(c),
+                }
+            }
+        }
+        while let Some(&c) = chars.peek() {
+            if c == ',' {
+                break;
+            }
+            field.push(c);
+            chars.next();
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Some(fields);
+        }
+    }
+}
+
+/// Conversion specification of a printf format, such as `%-08lx`.
+#[derive(Debug, Clone, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    bits: u32,
+}
+
+impl Spec {
+    /// Pads a formatted number, zeros go after the sign and prefix.
+    fn pad(&self, prefix: &str, digits: String) -> String {
+        let length = prefix.len() + digits.len();
+        if length >= self.width {
+            format!("{}{}", prefix, digits)
+        } else if self.left {
+            format!("{}{}{}", prefix, digits, " ".repeat(self.width - length))
+        } else if self.zero && self.precision.is_none() {
+            format!("{}{}{}", prefix, "0".repeat(self.width - length), digits)
+        } else {
+            format!("{}{}{}", " ".repeat(self.width - length), prefix, digits)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative { "-" } else if self.plus { "+" } else if self.space { " " } else { "" }
+    }
+
+    /// Applies the precision as the minimum number of digits.
+    fn digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(p) if p > digits.len() => format!("{}{}", "0".repeat(p - digits.len()), digits),
+            _ => digits,
+        }
+    }
+}
+
+/// Formats a float in C style scientific notation, e.g. 1.5e+03.
+fn exponential(value: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// Formats a float in C style hexadecimal notation without the 0x
+/// prefix, e.g. 1.8p+1. Without a precision, all significant digits
+/// are given.
+fn hexadecimal(value: f64, precision: Option<usize>, alternate: bool) -> String {
+    let bits = value.to_bits();
+    let (mut significand, exponent) = if value == 0.0 {
+        (0u64, 0)
+    } else {
+        ((1 << 52) | (bits & ((1 << 52) - 1)), ((bits >> 52) & 0x7FF) as i32 - 1023)
+    };
+
+    // The 52 fraction bits are 13 hexadecimal digits.
+    let zeros = if significand & ((1 << 52) - 1) == 0 { 13 } else { significand.trailing_zeros() as usize / 4 };
+    let digits = precision.unwrap_or(13 - zeros);
+    let mut fraction_digits = 13;
+    if digits < 13 {
+        // Round half to even.
+        let shift = 4 * (13 - digits);
+        let rest = significand & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        significand >>= shift;
+        if rest > half || (rest == half && significand & 1 == 1) {
+            significand += 1;
+        }
+        fraction_digits = digits;
+    }
+
+    let fraction_bits = 4 * fraction_digits;
+    let mut s = format!("{:x}", significand >> fraction_bits);
+    if digits > 0 || alternate {
+        s.push('.');
+    }
+    if fraction_digits > 0 {
+        s.push_str(&format!("{:0width$x}", significand & ((1 << fraction_bits) - 1), width = fraction_digits));
+    }
+    s.push_str(&"0".repeat(digits - fraction_digits));
+    format!("{}p{:+}", s, exponent)
+}
+
+fn format_float(value: f64, conversion: char, spec: &Spec) -> String {
+    let precision = spec.precision.unwrap_or(6);
+    let digits = if !value.is_finite() {
+        String::from(if value.is_nan() { "nan" } else { "inf" })
+    } else {
+        match conversion.to_ascii_lowercase() {
+            'e' => exponential(value.abs(), precision),
+            'g' => {
+                let precision = precision.max(1);
+                let exponent = exponential(value.abs(), precision - 1);
+                let x: i32 = exponent[exponent.find('e').unwrap() + 1..].parse().unwrap();
+                let mut s = if x < -4 || x >= precision as i32 {
+                    exponent
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - x) as usize, value.abs())
+                };
+                if !spec.alternate && s.contains('.') {
+                    let e = s.find('e').unwrap_or(s.len());
+                    let mantissa = s[..e].trim_end_matches('0').trim_end_matches('.').to_string();
+                    s = mantissa + &s[e..];
+                }
+                s
+            },
+            'a' => hexadecimal(value.abs(), spec.precision, spec.alternate),
+            _ => format!("{:.*}", precision, value.abs()),
+        }
+    };
+    let digits = if conversion.is_ascii_uppercase() { digits.to_uppercase() } else { digits };
+    let sign = spec.sign(value.is_sign_negative() && !value.is_nan());
+    let prefix = match conversion {
+        'a' if value.is_finite() => format!("{}0x", sign),
+        'A' if value.is_finite() => format!("{}0X", sign),
+        _ => String::from(sign),
+    };
+    let spec = Spec { precision: None, zero: spec.zero && value.is_finite(), ..spec.clone() };
+    spec.pad(&prefix, digits)
+}
+
+/// Formats a printf style format string with the encoded arguments.
+fn printf(format: &str, reader: &mut Reader) -> Result<String, Error> {
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+
+        let mut spec = Spec { bits: 32, ..Spec::default() };
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = reader.zigzag()?;
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        }
+        while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+            spec.width = spec.width.saturating_mul(10).saturating_add(d as usize);
+            chars.next();
+        }
+        if spec.width > MAX_WIDTH {
+            return Err(invalid(format!("Too large width {} in format {:?}", spec.width, format)));
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut precision = 0;
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                precision = reader.zigzag()?.max(0) as usize;
+            }
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                precision = precision.saturating_mul(10).saturating_add(d as usize);
+                chars.next();
+            }
+            if precision > MAX_WIDTH {
+                return Err(invalid(format!("Too large precision {} in format {:?}", precision, format)));
+            }
+            spec.precision = Some(precision);
+        }
+        let mut modifier = String::new();
+        while let Some(&c) = chars.peek().filter(|c| "hljztL".contains(**c)) {
+            modifier.push(c);
+            chars.next();
+        }
+        // Arguments are encoded as 64-bit values, but only long long
+        // is wider than 32 bits on the target.
+        spec.bits = match &modifier[..] {
+            "hh" => 8,
+            "h" => 16,
+            "ll" | "j" => 64,
+            _ => 32,
+        };
+        let mask = u64::MAX >> (64 - spec.bits);
+
+        let conversion = chars.next()
+            .ok_or_else(|| invalid(format!("Incomplete conversion in format {:?}", format)))?;
+        let s = match conversion {
+            '%' => String::from("%"),
+            'd' | 'i' => {
+                let shift = 64 - spec.bits;
+                let value = (reader.zigzag()? << shift) >> shift;
+                spec.pad(spec.sign(value < 0), spec.digits(value.unsigned_abs().to_string()))
+            },
+            'u' => spec.pad("", spec.digits((reader.zigzag()? as u64 & mask).to_string())),
+            'x' | 'X' | 'o' => {
+                let value = reader.zigzag()? as u64 & mask;
+                let digits = match conversion {
+                    'x' => format!("{:x}", value),
+                    'X' => format!("{:X}", value),
+                    _ => format!("{:o}", value),
+                };
+                let prefix = match conversion {
+                    _ if !spec.alternate || value == 0 => "",
+                    'x' => "0x",
+                    'X' => "0X",
+                    _ => "0",
+                };
+                spec.pad(prefix, spec.digits(digits))
+            },
+            'p' => format!("0x{:08X}", reader.zigzag()? as u64 & 0xFFFF_FFFF),
+            'c' => {
+                let c = reader.zigzag()? as u32;
+                spec.pad("", ::std::char::from_u32(c).unwrap_or('\u{FFFD}').to_string())
+            },
+            's' => {
+                let header = reader.take(1)?[0];
+                let bytes = reader.take((header & 0x7F) as usize)?;
+                let mut s = String::from_utf8_lossy(bytes).into_owned();
+                if header & 0x80 != 0 {
+                    s.push_str("[...]");
+                }
+                if let Some(precision) = spec.precision {
+                    s = s.chars().take(precision).collect();
+                }
+                Spec { zero: false, precision: None, ..spec }.pad("", s)
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+                let b = reader.take(4)?;
+                let value = f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64;
+                format_float(value, conversion, &spec)
+            },
+            c => return Err(invalid(format!("Unsupported conversion %{} in format {:?}", c, format))),
+        };
+        text.push_str(&s);
+    }
+    Ok(text)
+}
+
+/// Iterator adapter from `(time, ITMPacket)` pairs to the tokenized
+/// log messages written to one stimulus port.
+pub struct TokenizedDecoder<I> {
+    stream: PortStream<I>,
+    database: TokenDatabase,
+    buffer: Vec<u8>,
+
+    /// Time of the token write of the message in buffer.
+    time: u64,
+
+    events: VecDeque<LogEvent>,
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> TokenizedDecoder<I> {
+    pub fn new(packets: I, port: InstrumentationPort, database: TokenDatabase) -> TokenizedDecoder<I> {
+        TokenizedDecoder {
+            stream: PortStream::new(packets, port),
+            database,
+            buffer: Vec::new(),
+            time: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, time: u64, bytes: &[u8]) {
+        if self.buffer.is_empty() {
+            if bytes.len() != 4 {
+                return;
+            }
+            self.time = time;
+        }
+        self.buffer.extend_from_slice(bytes);
+
+        let event = match self.database.decode(&self.buffer) {
+            Ok((text, _)) => LogEvent::Message(LogMessage {
+                time: self.time, level: None, timestamp: None, text, location: None,
+            }),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return,
+            Err(e) => LogEvent::Error { time: self.time, message: e.to_string() },
+        };
+        self.events.push_back(event);
+        self.buffer.clear();
+    }
+}
+
+impl<I: Iterator<Item=(u64, ITMPacket)>> Iterator for TokenizedDecoder<I> {
+    type Item = LogEvent;
+    fn next(&mut self) -> Option<LogEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            match self.stream.next()? {
+                PortData::Bytes(time, bytes) => self.push(time, &bytes),
+                PortData::Overflow(time) => {
+                    if !self.buffer.is_empty() {
+                        self.buffer.clear();
+                        self.events.push_back(LogEvent::Error {
+                            time, message: String::from("Message broken by ITM overflow")
+                        });
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(value: i64) -> Vec<u8> {
+        let mut v = ((value << 1) ^ (value >> 63)) as u64;
+        let mut bytes = Vec::new();
+        loop {
+            if v < 0x80 {
+                bytes.push(v as u8);
+                return bytes;
+            }
+            bytes.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+    }
+
+    fn database() -> TokenDatabase {
+        TokenDatabase::from_csv(concat!(
+            "00000001,          ,\"Value %d, %u, 0x%08x\"\n",
+            "00000002,2024-01-01,\"\",\"Name \"\"%s\"\" %-4s|\"\n",
+            "00000003,          ,\"%.2f %e %g %5.1f%%\"\n",
+            "00000004,          ,\"%c%c %lld %hhx\"\n",
+            "00000005,          ,\"%a %A %.1a %#.0a %012a %.15a\"\n",
+            "00000006,          ,\"%*d|%.*f\"\n",
+        )).unwrap()
+    }
+
+    fn decode(token: u32, args: &[u8]) -> String {
+        let mut data = token.to_le_bytes().to_vec();
+        data.extend_from_slice(args);
+        let (text, length) = database().decode(&data).unwrap();
+        assert_eq!(length, data.len());
+        text
+    }
+
+    #[test]
+    fn test_printf() {
+        let args: Vec<u8> = [varint(-5), varint(-1), varint(0x1234)].concat();
+        assert_eq!(decode(1, &args), "Value -5, 4294967295, 0x00001234");
+        assert_eq!(decode(2, &[3, b'a', b'b', b'c', 0x82, b'x', b'y']), "Name \"abc\" xy[...]|");
+        let args: Vec<u8> = [1.5f32, 1500.0, 0.0001, -2.25].iter().flat_map(|f| f.to_le_bytes().to_vec()).collect();
+        assert_eq!(decode(3, &args), "1.50 1.500000e+03 0.0001  -2.2%");
+        let args: Vec<u8> = [varint(b'o' as i64), varint(b'k' as i64), varint(-1 << 40), varint(0x1FF)].concat();
+        assert_eq!(decode(4, &args), "ok -1099511627776 ff");
+    }
+
+    #[test]
+    fn test_hexadecimal_float() {
+        let hex = |value: f32| decode(5, &value.to_le_bytes().repeat(6));
+        assert_eq!(hex(1.5), "0x1.8p+0 0X1.8P+0 0x1.8p+0 0x2.p+0 0x00001.8p+0 0x1.800000000000000p+0");
+        assert_eq!(hex(-0.1), "-0x1.99999ap-4 -0X1.99999AP-4 -0x1.ap-4 -0x2.p-4 -0x1.99999ap-4 -0x1.99999a000000000p-4");
+        assert_eq!(hex(0.0), "0x0p+0 0X0P+0 0x0.0p+0 0x0.p+0 0x0000000p+0 0x0.000000000000000p+0");
+        assert_eq!(hex(1e-40), "0x1.16c2p-133 0X1.16C2P-133 0x1.1p-133 0x1.p-133 0x1.16c2p-133 0x1.16c200000000000p-133");
+        assert_eq!(hex(65504.0), "0x1.ffcp+15 0X1.FFCP+15 0x2.0p+15 0x2.p+15 0x01.ffcp+15 0x1.ffc000000000000p+15");
+    }
+
+    #[test]
+    fn test_star_arguments() {
+        let mut args: Vec<u8> = [varint(-4), varint(7), varint(2)].concat();
+        args.extend_from_slice(&1.5f32.to_le_bytes());
+        assert_eq!(decode(6, &args), "7   |1.50");
+
+        // A corrupted width must not be used to allocate the padding.
+        let database = database();
+        let mut data = vec![6, 0, 0, 0];
+        data.extend([varint(1 << 62), varint(7), varint(2)].concat());
+        data.extend_from_slice(&1.5f32.to_le_bytes());
+        assert_eq!(database.decode(&data).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut data = vec![6, 0, 0, 0];
+        data.extend([varint(4), varint(7), varint(i64::MAX)].concat());
+        data.extend_from_slice(&1.5f32.to_le_bytes());
+        assert_eq!(database.decode(&data).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_errors() {
+        let database = database();
+        assert_eq!(database.decode(&[1, 0, 0, 0, 2]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(database.decode(&[9, 0, 0, 0]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(TokenDatabase::from_csv("xyz,,\"a\"").is_err());
+    }
+
+    #[test]
+    fn test_elf_entries() {
+        let mut section = Vec::new();
+        for &(token, string) in &[(0x1234u32, "Hello %d"), (0x5678, "World")] {
+            for &word in &[ENTRY_MAGIC, token, 1, string.len() as u32 + 1] {
+                section.extend_from_slice(&word.to_le_bytes());
+            }
+            section.push(0);
+            section.extend_from_slice(string.as_bytes());
+            section.extend_from_slice(&[0, 0, 0]);
+        }
+        let mut database = TokenDatabase::new();
+        database.add_entries(&section).unwrap();
+        assert_eq!(database.get(0x1234), &[String::from("Hello %d")]);
+        assert_eq!(database.get(0x5678), &[String::from("World")]);
+    }
+
+    #[test]
+    fn test_decoder() {
+        let write = |time, value| (time, ITMPacket::Software(InstrumentationPort(3), value));
+        let packets = vec![
+            write(10, DataValue::U32(1)),
+            write(11, DataValue::U8(2)),
+            write(12, DataValue::U16(0x4001)),
+            write(20, DataValue::U8(0)),
+            write(30, DataValue::U32(1)),
+            (35, ITMPacket::Overflow),
+            write(40, DataValue::U8(7)),
+            write(50, DataValue::U32(9)),
+            write(60, DataValue::U32(2)),
+            write(61, DataValue::U32(0x6301_6201)),
+        ];
+        let events: Vec<LogEvent> = TokenizedDecoder::new(packets.into_iter(), InstrumentationPort(3), database()).collect();
+        let texts: Vec<String> = events.iter().map(|e| match *e {
+            LogEvent::Message(ref m) => format!("{} {}", m.time, m.text),
+            LogEvent::Error { time, ref message } => format!("{} {}", time, message),
+        }).collect();
+        assert_eq!(texts, vec![
+            "10 Value 1, 4294967295, 0x00000020",
+            "35 Message broken by ITM overflow",
+            "50 Unknown token 0x00000009",
+            "60 Name \"b\" c   |",
+        ]);
+    }
+}
@@ -0,0 +1,73 @@
+//! Log message types shared by the log decoders.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        f.pad(name)
+    }
+}
+
+/// Source code location of a log statement.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+    pub module: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogMessage {
+    /// ITM time of the first byte of the message.
+    pub time: u64,
+
+    /// None for messages printed without a level.
+    pub level: Option<Level>,
+
+    /// Timestamp formatted by the device, if it provides one.
+    pub timestamp: Option<String>,
+
+    pub text: String,
+    pub location: Option<Location>,
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref timestamp) = self.timestamp {
+            write!(f, "{} ", timestamp)?;
+        }
+        if let Some(level) = self.level {
+            write!(f, "{:<5} ", level)?;
+        }
+        write!(f, "{}", self.text)?;
+        if let Some(ref location) = self.location {
+            write!(f, "\n└─ {} @ {}:{}", location.module, location.file, location.line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LogEvent {
+    Message(LogMessage),
+
+    /// Frame that could not be decoded, e.g. because part of it was
+    /// lost in an ITM overflow.
+    Error { time: u64, message: String },
+}
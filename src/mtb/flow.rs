@@ -0,0 +1,196 @@
+//! Reconstructs program flow from MTB branch packets. The code
+//! between the destination of one branch and the source of the
+//! next one was executed sequentially.
+
+use ::itm::types::{Address, ExceptionNumber};
+use ::elf::image::Image;
+use ::flow::types::*;
+use super::types::MTBPacket;
+
+/// Longest sequential range that is considered plausible. Longer
+/// ranges indicate that the packets are not consecutive.
+const MAX_RANGE: u32 = 0x10000;
+
+/// Converts MTB packets to program flow events.
+pub struct Reconstructor<'a> {
+    image: &'a Image,
+    vector_table: Address,
+    previous: Option<Address>,
+}
+
+impl<'a> Reconstructor<'a> {
+    /// The image is used for classifying the branch instructions,
+    /// and for finding exception numbers from the vector table.
+    pub fn new(image: &'a Image) -> Reconstructor<'a> {
+        Reconstructor { image, vector_table: Address(0), previous: None }
+    }
+
+    /// Sets the vector table address, if VTOR is not zero.
+    pub fn set_vector_table(&mut self, address: Address) {
+        self.vector_table = address;
+    }
+
+    /// Finds the first branch instruction starting from address that
+    /// is not conditional. Conditional branches on the way were not
+    /// taken, as a taken branch would have produced a packet.
+    fn find_branch(&self, start: Address) -> Option<(Address, BranchKind)> {
+        let mut address = start;
+        while address.0 - start.0 < MAX_RANGE {
+            if self.image.is_conditional_branch(address) != Some(true) {
+                if let Some(kind) = self.image.classify_branch(address) {
+                    return Some((address, kind));
+                }
+            }
+            address = Address(address.0 + self.image.instruction_size(address)?);
+        }
+        None
+    }
+
+    /// Emits the range from start to end, along with the not taken
+    /// conditional branches inside it.
+    fn range(&self, start: Address, end: Address, events: &mut Vec<FlowEvent>) {
+        if end == start {
+            return;
+        } else if end < start || end.0 - start.0 > MAX_RANGE {
+            events.push(FlowEvent::Discontinuity);
+            return;
+        }
+
+        let instructions = self.image.instructions(start, end);
+        let count = if instructions.is_empty() { (end.0 - start.0) / 2 } else { instructions.len() as u32 };
+        events.push(FlowEvent::Range(InstructionRange { start, end, instructions: count }));
+
+        for &address in instructions.iter().rev().skip(1).rev() {
+            if self.image.is_conditional_branch(address) == Some(true) {
+                let next = Address(address.0 + self.image.instruction_size(address).unwrap_or(2));
+                events.push(FlowEvent::Branch(BranchRecord {
+                    source: address, destination: next, kind: BranchKind::Jump, taken: false
+                }));
+            }
+        }
+    }
+
+    /// Processes one packet and returns the resulting events.
+    pub fn add(&mut self, packet: &MTBPacket) -> Vec<FlowEvent> {
+        let mut events = Vec::new();
+
+        if packet.start {
+            events.push(FlowEvent::Discontinuity);
+            self.previous = Some(packet.destination);
+            return events;
+        }
+
+        // End of the sequentially executed range, if it can be determined.
+        let (source, kind, end) = if packet.is_exception_return() {
+            // Source is EXC_RETURN, the return instruction has to be found from code.
+            match self.previous.and_then(|p| self.find_branch(p)) {
+                Some((address, _)) => {
+                    let size = self.image.instruction_size(address).unwrap_or(2);
+                    (address, BranchKind::ExceptionReturn, Some(Address(address.0 + size)))
+                },
+                None => (packet.source, BranchKind::ExceptionReturn, None)
+            }
+        } else if packet.exception {
+            // The preempted instruction was not executed.
+            let number = self.image.exception_number(self.vector_table, packet.destination)
+                .unwrap_or(ExceptionNumber(0));
+            (packet.source, BranchKind::Exception(number), Some(packet.source))
+        } else {
+            let kind = self.image.classify_branch(packet.source).unwrap_or(BranchKind::Jump);
+            let size = self.image.instruction_size(packet.source).unwrap_or(2);
+            (packet.source, kind, Some(Address(packet.source.0 + size)))
+        };
+
+        match (self.previous, end) {
+            (Some(start), Some(end)) => self.range(start, end, &mut events),
+            (Some(_), None) => events.push(FlowEvent::Discontinuity),
+            (None, _) => {}
+        }
+
+        events.push(FlowEvent::Branch(BranchRecord {
+            source, destination: packet.destination, kind, taken: true
+        }));
+        self.previous = Some(packet.destination);
+        events
+    }
+
+    /// Converts a complete buffer of packets.
+    pub fn reconstruct(&mut self, packets: &[MTBPacket]) -> Vec<FlowEvent> {
+        packets.iter().flat_map(|p| self.add(p)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconstruct() {
+        // Vector table with IRQ0 handler at 0x200, and code:
+        // 0x100: NOP, BEQ, BL 0x180, NOP
+        // 0x180: NOP, BX LR
+        // 0x200: NOP, BX LR
+        let mut image = Image::new();
+        let mut vectors = vec![0; 0x44];
+        vectors[0x40] = 0x01;
+        vectors[0x41] = 0x02;
+        image.add_region(Address(0), vectors);
+        image.add_region(Address(0x100), vec![0x00, 0xbf, 0x01, 0xd0, 0x00, 0xf0, 0x3c, 0xf8, 0x00, 0xbf]);
+        image.add_region(Address(0x180), vec![0x00, 0xbf, 0x70, 0x47]);
+        image.add_region(Address(0x200), vec![0x00, 0xbf, 0x70, 0x47]);
+
+        let packet = |source, destination, exception, start| MTBPacket {
+            source: Address(source), destination: Address(destination), exception, start
+        };
+        let packets = vec![
+            packet(0, 0x100, false, true),
+            packet(0x104, 0x180, false, false),
+            packet(0x182, 0x108, false, false),
+            packet(0x108, 0x200, true, false),
+            packet(0xFFFFFFF8, 0x108, true, false),
+        ];
+
+        let mut reconstructor = Reconstructor::new(&image);
+        let events = reconstructor.reconstruct(&packets);
+        let range = |start, end, instructions| FlowEvent::Range(InstructionRange {
+            start: Address(start), end: Address(end), instructions
+        });
+        let branch = |source, destination, kind, taken| FlowEvent::Branch(BranchRecord {
+            source: Address(source), destination: Address(destination), kind, taken
+        });
+
+        assert_eq!(events, vec![
+            FlowEvent::Discontinuity,
+            range(0x100, 0x108, 3),
+            branch(0x102, 0x104, BranchKind::Jump, false),
+            branch(0x104, 0x180, BranchKind::Call, true),
+            range(0x180, 0x184, 2),
+            branch(0x182, 0x108, BranchKind::Return, true),
+            branch(0x108, 0x200, BranchKind::Exception(ExceptionNumber(16)), true),
+            range(0x200, 0x204, 2),
+            branch(0x202, 0x108, BranchKind::ExceptionReturn, true),
+        ]);
+    }
+
+    #[test]
+    fn test_conditional_before_return() {
+        // Handler at 0x200: NOP, BNE, BX LR
+        let mut image = Image::new();
+        image.add_region(Address(0x200), vec![0x00, 0xbf, 0x00, 0xd1, 0x70, 0x47]);
+
+        let packets = vec![
+            MTBPacket { source: Address(0), destination: Address(0x200), exception: false, start: true },
+            MTBPacket { source: Address(0xFFFFFFF8), destination: Address(0x108), exception: true, start: false },
+        ];
+        let events = Reconstructor::new(&image).reconstruct(&packets);
+        assert_eq!(events[1..], [
+            FlowEvent::Range(InstructionRange { start: Address(0x200), end: Address(0x206), instructions: 3 }),
+            FlowEvent::Branch(BranchRecord {
+                source: Address(0x202), destination: Address(0x204), kind: BranchKind::Jump, taken: false
+            }),
+            FlowEvent::Branch(BranchRecord {
+                source: Address(0x204), destination: Address(0x108), kind: BranchKind::ExceptionReturn, taken: true
+            }),
+        ]);
+    }
+}
@@ -0,0 +1,72 @@
+//! Parses MTB packets from a memory dump of the trace buffer.
+
+extern crate byteorder;
+use self::byteorder::{ByteOrder, LittleEndian as LE};
+
+use std::io::{Error, ErrorKind};
+use super::types::*;
+use ::itm::types::Address;
+
+/// Decodes a single 8-byte packet.
+pub fn parse_packet(data: &[u8]) -> MTBPacket {
+    let source = LE::read_u32(&data[0..4]);
+    let destination = LE::read_u32(&data[4..8]);
+    MTBPacket {
+        source: Address(source & !1),
+        destination: Address(destination & !1),
+        exception: source & 1 != 0,
+        start: destination & 1 != 0,
+    }
+}
+
+/// Parses all valid packets from the trace buffer contents, in
+/// chronological order. The buffer must start at the MTB SRAM base
+/// address. If the write pointer has wrapped, the oldest packets
+/// are the ones after the pointer.
+pub fn parse(buffer: &[u8], registers: &MTBRegisters) -> Result<Vec<MTBPacket>, Error> {
+    let size = registers.buffer_size().min(buffer.len() & !7);
+    let pointer = registers.pointer() % registers.buffer_size();
+    if pointer > size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "MTB buffer dump is shorter than MTB_POSITION"));
+    }
+
+    let mut packets = Vec::with_capacity(size / 8);
+    if registers.wrapped() {
+        packets.extend(buffer[pointer..size].chunks(8).map(parse_packet));
+    }
+    packets.extend(buffer[..pointer].chunks(8).map(parse_packet));
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(source: u32, destination: u32) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        LE::write_u32(&mut data[0..4], source);
+        LE::write_u32(&mut data[4..8], destination);
+        data
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut buffer = Vec::new();
+        buffer.extend(packet(0x300, 0x400));
+        buffer.extend(packet(0x100, 0x201));
+        buffer.extend(packet(0x208, 0x300));
+        buffer.extend(packet(0x1000, 0x2000));
+
+        // 32 byte buffer, pointer at second packet.
+        let registers = MTBRegisters { position: 8, master: 0x80000001 };
+        let sources: Vec<u32> = parse(&buffer, &registers).unwrap().iter().map(|p| p.source.0).collect();
+        assert_eq!(sources, vec![0x300]);
+
+        let registers = MTBRegisters { position: 8 | 4, master: 0x80000001 };
+        let packets = parse(&buffer, &registers).unwrap();
+        let sources: Vec<u32> = packets.iter().map(|p| p.source.0).collect();
+        assert_eq!(sources, vec![0x100, 0x208, 0x1000, 0x300]);
+        assert!(packets[0].start);
+        assert_eq!(packets[0].destination, Address(0x200));
+    }
+}
@@ -0,0 +1,59 @@
+//! Packet types for the ARM CoreSight Micro Trace Buffer.
+//! Reference: CoreSight MTB-M0+ Technical Reference Manual
+
+use ::itm::types::Address;
+
+/// Execution trace packet, recording one taken branch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MTBPacket {
+    /// Address of the branch instruction. For exception entry, the
+    /// address of the instruction that was preempted. For exception
+    /// return, the EXC_RETURN value.
+    pub source: Address,
+
+    /// Address the branch went to.
+    pub destination: Address,
+
+    /// A-bit: the branch was caused by an exception entry or return
+    /// instead of a branch instruction.
+    pub exception: bool,
+
+    /// S-bit: first packet after tracing was started, the source
+    /// address is not related to the previous packet.
+    pub start: bool,
+}
+
+impl MTBPacket {
+    /// Returns true if the packet records an exception return.
+    pub fn is_exception_return(&self) -> bool {
+        self.exception && self.source.0 >= 0xFFFFFF00
+    }
+}
+
+/// Values of the MTB registers at the time the buffer was read.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MTBRegisters {
+    /// MTB_POSITION: write pointer and wrap flag.
+    pub position: u32,
+
+    /// MTB_MASTER: buffer size mask and enable bits.
+    pub master: u32,
+}
+
+impl MTBRegisters {
+    /// Offset of the next packet to be written in the buffer.
+    pub fn pointer(&self) -> usize {
+        (self.position & !7) as usize
+    }
+
+    /// True if the write pointer has wrapped around, so that the
+    /// whole buffer contains valid packets.
+    pub fn wrapped(&self) -> bool {
+        self.position & 4 != 0
+    }
+
+    /// Size of the circular buffer in bytes, from MTB_MASTER.MASK.
+    pub fn buffer_size(&self) -> usize {
+        1 << ((self.master & 0x1F) + 4)
+    }
+}
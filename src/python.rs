@@ -0,0 +1,279 @@
+//! Python extension module, built with maturin from `pyproject.toml`.
+//! Packets are returned as dicts with the fields of `export::record`,
+//! so that they match the JSON Lines output. For large traces,
+//! `itm_columns()` returns the fields as `array.array` columns, which
+//! `numpy.asarray()` and pandas accept without copying. Both take
+//! an optional port schema in the format of `itm::schema`, and give
+//! the decoded values as `Signal` records or in a `signals` dict.
+
+use std::fs;
+use std::io::Cursor;
+use std::collections::BTreeMap;
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use ::decoder::Decoder;
+use ::export::record::{Record, itm_records, itm_signal_records, tpiu_records};
+use ::itm::parser::Parser as ITMParser;
+use ::itm::schema::{Schema, SignalDecoder};
+use ::itm::timestamp::Timestamper;
+use ::itm::types::ITMPacket;
+use ::tpiu::demux::{Demux, SourcePacket, SourceType};
+use ::tpiu::parser::Parser as TPIUParser;
+use ::tpiu::types::{TPIUPacket, TraceSourceID};
+
+/// Packet types in the order of the `type` column codes.
+const ITM_TYPES: [&str; 17] = [
+    "Synchronization", "Overflow", "LocalTimestamp", "GlobalTimestamp", "SoftwarePageNumber",
+    "Software", "EventCounter", "ProgramCounter", "SleepMode", "Exception", "DataTracePC",
+    "DataTraceOffset", "DataTraceReadData", "DataTraceWriteData", "Extension", "Reserved", "Invalid",
+];
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn record_dict<'py>(py: Python<'py>, record: &Record) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("offset", record.offset)?;
+    dict.set_item("time", record.time)?;
+    dict.set_item("source", record.source)?;
+    dict.set_item("type", &record.kind)?;
+    dict.set_item("port", record.port)?;
+    dict.set_item("comparator", record.comparator)?;
+    dict.set_item("exception", record.exception)?;
+    dict.set_item("event", &record.event)?;
+    dict.set_item("address", record.address)?;
+    dict.set_item("value", record.value)?;
+    dict.set_item("bits", record.bits)?;
+    dict.set_item("data", &record.data)?;
+    dict.set_item("text", &record.text)?;
+    dict.set_item("signal", &record.signal)?;
+    dict.set_item("signal_value", record.signal_value)?;
+    dict.set_item("page", record.page)?;
+    Ok(dict)
+}
+
+fn parse_schema(schema: Option<&str>) -> PyResult<Option<Schema>> {
+    schema.map(|text| Schema::parse(text).map_err(|e| PyValueError::new_err(e.to_string()))).transpose()
+}
+
+type Records = Box<dyn Iterator<Item=Record> + Send + Sync>;
+
+fn records(data: Vec<u8>, schema: Option<&str>) -> PyResult<Records> {
+    let parser = ITMParser::new(Cursor::new(data));
+    Ok(match parse_schema(schema)? {
+        Some(schema) => Box::new(itm_signal_records(parser, schema)),
+        None => Box::new(itm_records(parser)),
+    })
+}
+
+fn parse_source_type(name: &str) -> PyResult<SourceType> {
+    match name {
+        "itm" => Ok(SourceType::ITM),
+        "stm" => Ok(SourceType::STM),
+        "raw" => Ok(SourceType::Raw),
+        _ => Err(PyValueError::new_err(format!("Unknown source type {:?}, expected itm, stm or raw", name))),
+    }
+}
+
+/// Iterator over the ITM packets of a capture, as dicts with the
+/// absolute time reconstructed from the local timestamps.
+#[pyclass(module = "arm_coresight_decoder")]
+struct ItmParser {
+    records: Records,
+}
+
+#[pymethods]
+impl ItmParser {
+    #[new]
+    #[pyo3(signature = (data, schema=None))]
+    fn new(data: &[u8], schema: Option<&str>) -> PyResult<ItmParser> {
+        Ok(ItmParser { records: records(data.to_vec(), schema)? })
+    }
+
+    /// Reads a capture file.
+    #[staticmethod]
+    #[pyo3(signature = (path, schema=None))]
+    fn open(path: &str, schema: Option<&str>) -> PyResult<ItmParser> {
+        Ok(ItmParser { records: records(fs::read(path)?, schema)? })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        self.records.next().map(|record| record_dict(py, &record)).transpose()
+    }
+}
+
+/// Columns of `itm_columns()`.
+#[derive(Default)]
+struct Columns {
+    offset: Vec<u64>,
+    time: Vec<u64>,
+    kind: Vec<u8>,
+    port: Vec<u32>,
+    comparator: Vec<u32>,
+    exception: Vec<u32>,
+    address: Vec<u32>,
+    value: Vec<u64>,
+    bits: Vec<u8>,
+}
+
+fn array<'py, T: Copy, const N: usize>(py: Python<'py>, typecode: &str, values: &[T],
+                                       to_bytes: fn(T) -> [u8; N]) -> PyResult<Bound<'py, PyAny>> {
+    let bytes: Vec<u8> = values.iter().flat_map(|&v| to_bytes(v)).collect();
+    let array = py.import("array")?.getattr("array")?.call1((typecode,))?;
+    array.call_method1("frombytes", (PyBytes::new(py, &bytes),))?;
+    Ok(array)
+}
+
+/// Decodes all ITM packets of a capture into `array.array` columns.
+/// Fields that do not apply to a packet are zero, and the `type`
+/// column holds indexes to the `ITM_TYPES` tuple. With a schema, the
+/// `signals` item has the `time` and `value` columns of each signal.
+#[pyfunction]
+#[pyo3(signature = (data, schema=None))]
+fn itm_columns<'py>(py: Python<'py>, data: &[u8], schema: Option<&str>) -> PyResult<Bound<'py, PyDict>> {
+    let mut columns = Columns::default();
+    let mut signals: BTreeMap<String, (Vec<u64>, Vec<f64>)> = BTreeMap::new();
+    for record in records(data.to_vec(), schema)? {
+        if let (Some(ref name), Some(value)) = (&record.signal, record.signal_value) {
+            let signal = signals.entry(name.clone()).or_default();
+            signal.0.push(record.time.unwrap_or(0));
+            signal.1.push(value);
+            continue;
+        }
+        columns.offset.push(record.offset.unwrap_or(0));
+        columns.time.push(record.time.unwrap_or(0));
+        columns.kind.push(ITM_TYPES.iter().position(|&t| t == record.kind).unwrap_or(0) as u8);
+        columns.port.push(record.port.unwrap_or(0));
+        columns.comparator.push(record.comparator.unwrap_or(0));
+        columns.exception.push(record.exception.unwrap_or(0));
+        columns.address.push(record.address.unwrap_or(0));
+        columns.value.push(record.value.unwrap_or(0));
+        columns.bits.push(record.bits.unwrap_or(0));
+    }
+
+    let dict = PyDict::new(py);
+    dict.set_item("offset", array(py, "Q", &columns.offset, u64::to_ne_bytes)?)?;
+    dict.set_item("time", array(py, "Q", &columns.time, u64::to_ne_bytes)?)?;
+    dict.set_item("type", array(py, "B", &columns.kind, u8::to_ne_bytes)?)?;
+    dict.set_item("port", array(py, "I", &columns.port, u32::to_ne_bytes)?)?;
+    dict.set_item("comparator", array(py, "I", &columns.comparator, u32::to_ne_bytes)?)?;
+    dict.set_item("exception", array(py, "I", &columns.exception, u32::to_ne_bytes)?)?;
+    dict.set_item("address", array(py, "I", &columns.address, u32::to_ne_bytes)?)?;
+    dict.set_item("value", array(py, "Q", &columns.value, u64::to_ne_bytes)?)?;
+    dict.set_item("bits", array(py, "B", &columns.bits, u8::to_ne_bytes)?)?;
+    if schema.is_some() {
+        let signal_dict = PyDict::new(py);
+        for (name, (time, value)) in signals {
+            let columns = PyDict::new(py);
+            columns.set_item("time", array(py, "Q", &time, u64::to_ne_bytes)?)?;
+            columns.set_item("value", array(py, "d", &value, f64::to_ne_bytes)?)?;
+            signal_dict.set_item(name, columns)?;
+        }
+        dict.set_item("signals", signal_dict)?;
+    }
+    Ok(dict)
+}
+
+/// Decodes a TPIU capture into dicts of the frame packets.
+#[pyfunction]
+fn tpiu_packets<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+    let records = tpiu_records(TPIUParser::new(Box::new(Cursor::new(data.to_vec()))));
+    let dicts = records.map(|record| record_dict(py, &record)).collect::<PyResult<Vec<_>>>()?;
+    PyList::new(py, dicts)
+}
+
+/// Splits a TPIU capture by trace source and decodes the sources
+/// configured with `add_source()`. Other sources are returned as
+/// raw data.
+#[pyclass(module = "arm_coresight_decoder")]
+struct TpiuDemux {
+    demux: Demux,
+}
+
+#[pymethods]
+impl TpiuDemux {
+    /// Sources can be given as a dict from ID to "itm", "stm" or "raw".
+    #[new]
+    #[pyo3(signature = (sources=None))]
+    fn new(sources: Option<BTreeMap<u8, String>>) -> PyResult<TpiuDemux> {
+        let mut demux = TpiuDemux { demux: Demux::new() };
+        for (id, name) in sources.unwrap_or_default() {
+            demux.add_source(id, &name)?;
+        }
+        Ok(demux)
+    }
+
+    fn add_source(&mut self, id: u8, source_type: &str) -> PyResult<()> {
+        self.demux.add_source(TraceSourceID(id), parse_source_type(source_type)?);
+        Ok(())
+    }
+
+    /// Decodes a complete capture. ITM packets get absolute times from
+    /// the timestamps of their own source, and the other packets have
+    /// None as the time. With a schema, the `Signal` records of each
+    /// ITM source follow the packets that completed them.
+    #[pyo3(signature = (data, schema=None))]
+    fn decode<'py>(&self, py: Python<'py>, data: &[u8], schema: Option<&str>) -> PyResult<Bound<'py, PyList>> {
+        let schema = parse_schema(schema)?;
+        let mut demux = self.demux.clone();
+        let mut records = Vec::new();
+        let mut itm: BTreeMap<TraceSourceID, Vec<(usize, ITMPacket)>> = BTreeMap::new();
+        for (offset, packet) in TPIUParser::new(Box::new(Cursor::new(data.to_vec()))).with_offsets() {
+            for (id, packet) in demux.push(packet) {
+                let offset = Some(offset as u64);
+                let record = match packet {
+                    SourcePacket::ITM(packet) => {
+                        // Filled in below, once the timestamps are known.
+                        itm.entry(id).or_default().push((records.len(), packet));
+                        Record::default()
+                    },
+                    SourcePacket::STM(packet) => Record {
+                        offset, source: Some(id.0), kind: String::from("STM"),
+                        text: Some(format!("{:?}", packet)), ..Record::default()
+                    },
+                    SourcePacket::Raw(data) => Record {
+                        data: Some(hex(&data)), ..Record::from_tpiu(offset, &TPIUPacket::Data(id, Vec::new()))
+                    },
+                    SourcePacket::Trigger(data) => Record::from_tpiu(offset, &TPIUPacket::Trigger(data)),
+                };
+                records.push((offset, record));
+            }
+        }
+        let mut signals: BTreeMap<usize, Vec<Record>> = BTreeMap::new();
+        for (id, packets) in itm {
+            let mut decoder = schema.clone().map(SignalDecoder::new);
+            for (time, (index, packet)) in Timestamper::new(packets.into_iter()) {
+                let offset = records[index].0;
+                records[index].1 = Record::from_itm(offset, Some(time), Some(id), &packet);
+                for sample in decoder.as_mut().map(|d| d.push(time, &packet)).unwrap_or_default() {
+                    signals.entry(index).or_default().push(Record::from_signal(offset, Some(id), &sample));
+                }
+            }
+        }
+
+        let mut dicts = Vec::new();
+        for (index, record) in records.iter().enumerate() {
+            dicts.push(record_dict(py, &record.1)?);
+            for signal in signals.get(&index).into_iter().flatten() {
+                dicts.push(record_dict(py, signal)?);
+            }
+        }
+        PyList::new(py, dicts)
+    }
+}
+
+#[pymodule]
+fn arm_coresight_decoder(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<ItmParser>()?;
+    module.add_class::<TpiuDemux>()?;
+    module.add_function(wrap_pyfunction!(self::itm_columns, module)?)?;
+    module.add_function(wrap_pyfunction!(self::tpiu_packets, module)?)?;
+    module.add("ITM_TYPES", ITM_TYPES.to_vec())?;
+    Ok(())
+}
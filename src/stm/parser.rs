@@ -0,0 +1,452 @@
+//! Parses STPv2 packets from the nibble stream. Each byte carries
+//! two nibbles, the lower nibble first. Multi-nibble values are sent
+//! most significant nibble first.
+
+use std::io::{Read, Error, ErrorKind};
+use std::collections::VecDeque;
+use super::types::*;
+
+/// ASYNC packet is 21 nibbles of 0xF followed by 0x0.
+const ASYNC_LENGTH: usize = 21;
+
+/// Packet contents before master, channel and timestamp state is applied.
+#[derive(Debug, Clone, Copy)]
+enum RawPacket {
+    Null,
+    Async,
+    Version(u8),
+    Master(u16),
+    Channel8(u8),
+    Channel16(u16),
+    MasterError(u8),
+    GlobalError(u8),
+    Data(STMDataValue, bool, Option<RawTimestamp>),
+    Flag(Option<RawTimestamp>),
+    Trigger(u8, Option<RawTimestamp>),
+    NullTimestamp(RawTimestamp),
+    Frequency(u32),
+    Reserved(u16),
+}
+
+/// Timestamp update, carrying only the lowest nibbles.
+#[derive(Debug, Clone, Copy)]
+struct RawTimestamp {
+    value: u64,
+    nibbles: u32,
+}
+
+/// Reads values from a slice of nibbles.
+struct Nibbles<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Nibbles<'a> {
+    /// Reads count nibbles as a value, or None if there is not
+    /// enough data yet.
+    fn take(&mut self, count: usize) -> Option<u64> {
+        let nibbles = self.data.get(self.pos..self.pos + count)?;
+        self.pos += count;
+        Some(nibbles.iter().fold(0, |v, &n| (v << 4) | n as u64))
+    }
+
+    /// Reads data payload of 1, 2, 4, 8 or 16 nibbles.
+    fn data(&mut self, nibbles: usize) -> Option<STMDataValue> {
+        let value = self.take(nibbles)?;
+        Some(match nibbles {
+            1 => STMDataValue::D4(value as u8),
+            2 => STMDataValue::D8(value as u8),
+            4 => STMDataValue::D16(value as u16),
+            8 => STMDataValue::D32(value as u32),
+            _ => STMDataValue::D64(value),
+        })
+    }
+
+    /// Reads timestamp, which starts with a length nibble.
+    fn timestamp(&mut self) -> Option<Result<RawTimestamp, String>> {
+        let nibbles = match self.take(1)? {
+            0x0 | 0xF => return Some(Err(String::from("Invalid timestamp length"))),
+            0xD => 14,
+            0xE => 16,
+            n => n as u32,
+        };
+        let value = self.take(nibbles as usize)?;
+        Some(Ok(RawTimestamp { value, nibbles }))
+    }
+}
+
+/// Payload size in nibbles for the data opcodes, indexed by the
+/// lowest two bits of the opcode.
+const DATA_NIBBLES: [usize; 4] = [2, 4, 8, 16];
+
+/// Decodes one packet from the start of the nibbles. Returns None
+/// if more nibbles are needed, otherwise the packet and its length.
+fn decode(data: &[u8]) -> Option<(Result<RawPacket, String>, usize)> {
+    let mut n = Nibbles { data, pos: 0 };
+
+    // Reads an optional timestamp, returning errors from the function.
+    macro_rules! timestamp {
+        ($present:expr) => {
+            if $present {
+                match n.timestamp()? {
+                    Ok(ts) => Some(ts),
+                    Err(e) => return Some((Err(e), n.pos)),
+                }
+            } else {
+                None
+            }
+        }
+    }
+
+    let packet = match n.take(1)? as u8 {
+        0x0 => RawPacket::Null,
+        0x1 => RawPacket::Master(n.take(2)? as u16),
+        0x2 => RawPacket::MasterError(n.take(2)? as u8),
+        0x3 => RawPacket::Channel8(n.take(2)? as u8),
+        op @ 0x4 ..= 0xB => {
+            let value = n.data(DATA_NIBBLES[(op & 3) as usize])?;
+            let timed = op >= 0x8;
+            RawPacket::Data(value, timed, timestamp!(timed))
+        },
+        0xC => RawPacket::Data(n.data(1)?, false, None),
+        0xD => {
+            let value = n.data(1)?;
+            RawPacket::Data(value, true, timestamp!(true))
+        },
+        0xE => RawPacket::Flag(timestamp!(true)),
+        _ => match n.take(1)? as u8 {
+            0x0 => match n.take(1)? {
+                0x0 => RawPacket::Version(n.take(1)? as u8),
+                0x1 => RawPacket::NullTimestamp(timestamp!(true).unwrap()),
+                0x6 => RawPacket::Trigger(n.take(2)? as u8, None),
+                0x7 => {
+                    let value = n.take(2)? as u8;
+                    RawPacket::Trigger(value, timestamp!(true))
+                },
+                0x8 => RawPacket::Frequency(n.take(8)? as u32),
+                op => RawPacket::Reserved(0xF00 | op as u16),
+            },
+            0x1 => RawPacket::Master(n.take(4)? as u16),
+            0x2 => RawPacket::GlobalError(n.take(2)? as u8),
+            0x3 => RawPacket::Channel16(n.take(4)? as u16),
+            op @ 0x4 ..= 0x7 => {
+                let value = n.data(DATA_NIBBLES[(op & 3) as usize])?;
+                RawPacket::Data(value, false, timestamp!(true))
+            },
+            op @ 0x8 ..= 0xB => RawPacket::Data(n.data(DATA_NIBBLES[(op & 3) as usize])?, true, None),
+            0xC => {
+                let value = n.data(1)?;
+                RawPacket::Data(value, false, timestamp!(true))
+            },
+            0xD => RawPacket::Data(n.data(1)?, true, None),
+            0xE => RawPacket::Flag(None),
+            _ => {
+                // ASYNC: continues with 0xF nibbles until 0x0.
+                loop {
+                    match n.take(1)? {
+                        0xF => continue,
+                        0x0 if n.pos > ASYNC_LENGTH => break RawPacket::Async,
+                        _ => return Some((Err(String::from("Invalid ASYNC packet")), n.pos)),
+                    }
+                }
+            }
+        }
+    };
+
+    Some((Ok(packet), n.pos))
+}
+
+fn gray_to_binary(gray: u64) -> u64 {
+    let mut value = gray;
+    let mut shift = 1;
+    while shift < 64 {
+        value ^= value >> shift;
+        shift <<= 1;
+    }
+    value
+}
+
+fn binary_to_gray(value: u64) -> u64 {
+    value ^ (value >> 1)
+}
+
+/// Incremental STPv2 decoder, which keeps track of the selected
+/// master and channel and the full timestamp value. Packets are
+/// only decoded after the first ASYNC has given the nibble alignment.
+#[derive(Clone)]
+pub struct Decoder {
+    nibbles: Vec<u8>,
+    synced: bool,
+    async_count: usize,
+    master: MasterID,
+    channel: ChannelID,
+    timestamp: u64,
+    encoding: TimestampEncoding,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            nibbles: Vec::with_capacity(32),
+            synced: false,
+            async_count: 0,
+            master: MasterID(0),
+            channel: ChannelID(0),
+            timestamp: 0,
+            encoding: TimestampEncoding::NaturalBinary,
+        }
+    }
+
+    pub fn master(&self) -> MasterID {
+        self.master
+    }
+
+    pub fn channel(&self) -> ChannelID {
+        self.channel
+    }
+
+    /// Latest full timestamp value.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Updates the lowest nibbles of the timestamp value.
+    fn update_timestamp(&mut self, ts: RawTimestamp) -> u64 {
+        let mask = if ts.nibbles >= 16 { !0 } else { (1u64 << (ts.nibbles * 4)) - 1 };
+        self.timestamp = match self.encoding {
+            TimestampEncoding::NaturalBinary => (self.timestamp & !mask) | (ts.value & mask),
+            TimestampEncoding::GrayCode => {
+                let gray = (binary_to_gray(self.timestamp) & !mask) | (ts.value & mask);
+                gray_to_binary(gray)
+            }
+        };
+        self.timestamp
+    }
+
+    fn apply(&mut self, packet: RawPacket) -> STMPacket {
+        match packet {
+            RawPacket::Null => STMPacket::Null,
+            RawPacket::Async => {
+                self.master = MasterID(0);
+                self.channel = ChannelID(0);
+                STMPacket::Async
+            },
+            RawPacket::Version(version) => {
+                self.master = MasterID(0);
+                self.channel = ChannelID(0);
+                self.encoding = if version == 4 {
+                    TimestampEncoding::GrayCode
+                } else {
+                    TimestampEncoding::NaturalBinary
+                };
+                STMPacket::Version(version)
+            },
+            RawPacket::Master(master) => {
+                self.master = MasterID(master);
+                self.channel = ChannelID(0);
+                STMPacket::Master(self.master)
+            },
+            RawPacket::Channel8(channel) => {
+                self.channel = ChannelID((self.channel.0 & 0xFF00) | channel as u16);
+                STMPacket::Channel(self.channel)
+            },
+            RawPacket::Channel16(channel) => {
+                self.channel = ChannelID(channel);
+                STMPacket::Channel(self.channel)
+            },
+            RawPacket::MasterError(value) => STMPacket::MasterError(value),
+            RawPacket::GlobalError(value) => STMPacket::GlobalError(value),
+            RawPacket::Data(value, marker, ts) => STMPacket::Data {
+                master: self.master,
+                channel: self.channel,
+                value,
+                marker,
+                timestamp: ts.map(|ts| self.update_timestamp(ts)),
+            },
+            RawPacket::Flag(ts) => STMPacket::Flag {
+                master: self.master,
+                channel: self.channel,
+                timestamp: ts.map(|ts| self.update_timestamp(ts)),
+            },
+            RawPacket::Trigger(value, ts) => STMPacket::Trigger {
+                master: self.master,
+                channel: self.channel,
+                value,
+                timestamp: ts.map(|ts| self.update_timestamp(ts)),
+            },
+            RawPacket::NullTimestamp(ts) => STMPacket::NullTimestamp(self.update_timestamp(ts)),
+            RawPacket::Frequency(frequency) => STMPacket::Frequency(frequency),
+            RawPacket::Reserved(opcode) => STMPacket::Reserved(opcode),
+        }
+    }
+
+    /// Adds one nibble of input, and appends any decoded packet to output.
+    pub fn push_nibble(&mut self, nibble: u8, output: &mut Vec<STMPacket>) {
+        if !self.synced {
+            if nibble == 0xF {
+                self.async_count += 1;
+            } else if nibble == 0x0 && self.async_count >= ASYNC_LENGTH {
+                self.synced = true;
+                self.async_count = 0;
+                output.push(self.apply(RawPacket::Async));
+            } else {
+                self.async_count = 0;
+            }
+            return;
+        }
+
+        self.nibbles.push(nibble);
+        if let Some((result, length)) = decode(&self.nibbles) {
+            self.nibbles.drain(..length);
+            match result {
+                Ok(packet) => output.push(self.apply(packet)),
+                Err(e) => {
+                    // Alignment has probably been lost.
+                    self.synced = false;
+                    self.nibbles.clear();
+                    output.push(STMPacket::Invalid(e));
+                }
+            }
+        }
+    }
+
+    /// Adds one byte of input and returns the decoded packets.
+    pub fn push(&mut self, byte: u8) -> Vec<STMPacket> {
+        let mut output = Vec::new();
+        self.push_nibble(byte & 0xF, &mut output);
+        self.push_nibble(byte >> 4, &mut output);
+        output
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+pub struct Parser<T> {
+    input: T,
+    decoder: Decoder,
+    buffer: VecDeque<STMPacket>,
+    error: Option<Error>,
+}
+
+impl<T:Read> Parser<T> {
+    pub fn new(input: T) -> Parser<T> {
+        Parser { input, decoder: Decoder::new(), buffer: VecDeque::new(), error: None }
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<T:Read> Iterator for Parser<T> {
+    type Item = STMPacket;
+    fn next(&mut self) -> Option<STMPacket> {
+        while self.buffer.is_empty() {
+            let mut byte = [0u8; 1];
+            match self.input.read_exact(&mut byte) {
+                Ok(()) => self.buffer.extend(self.decoder.push(byte[0])),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+                Err(e) => {self.error = Some(e); return None}
+            }
+        }
+
+        self.buffer.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Packs nibbles into bytes, lower nibble first.
+    fn pack(nibbles: &[u8]) -> Vec<u8> {
+        nibbles.chunks(2).map(|c| c[0] | (c.get(1).cloned().unwrap_or(0) << 4)).collect()
+    }
+
+    fn parse(nibbles: &[u8]) -> Vec<STMPacket> {
+        let mut all = vec![0xF; ASYNC_LENGTH];
+        all.push(0x0);
+        all.extend_from_slice(nibbles);
+        Parser::new(Cursor::new(pack(&all))).collect()
+    }
+
+    #[test]
+    fn test_master_channel() {
+        let packets = parse(&[
+            0x1, 0x0, 0x5,                  // M8 5
+            0x3, 0x1, 0x2,                  // C8 0x12
+            0x5, 0x1, 0x2, 0x3, 0x4,        // D16 0x1234
+            0xF, 0x3, 0x0, 0x1, 0x0, 0x0,   // C16 0x0100
+            0x8, 0xA, 0xB, 0x2, 0x3, 0x4,   // D8MTS 0xAB, 2 nibble timestamp 0x34
+            0xF, 0xE,                       // FLAG
+            0x0,                            // NULL
+        ]);
+
+        assert_eq!(packets, vec![
+            STMPacket::Async,
+            STMPacket::Master(MasterID(5)),
+            STMPacket::Channel(ChannelID(0x12)),
+            STMPacket::Data { master: MasterID(5), channel: ChannelID(0x12),
+                              value: STMDataValue::D16(0x1234), marker: false, timestamp: None },
+            STMPacket::Channel(ChannelID(0x100)),
+            STMPacket::Data { master: MasterID(5), channel: ChannelID(0x100),
+                              value: STMDataValue::D8(0xAB), marker: true, timestamp: Some(0x34) },
+            STMPacket::Flag { master: MasterID(5), channel: ChannelID(0x100), timestamp: None },
+            STMPacket::Null,
+        ]);
+    }
+
+    #[test]
+    fn test_master16() {
+        let packets = parse(&[
+            0x3, 0x1, 0x2,                  // C8 0x12
+            0xF, 0x1, 0x1, 0x2, 0x3, 0x4,   // M16 0x1234
+            0x4, 0x5, 0x6,                  // D8 0x56
+        ]);
+
+        assert_eq!(packets, vec![
+            STMPacket::Async,
+            STMPacket::Channel(ChannelID(0x12)),
+            STMPacket::Master(MasterID(0x1234)),
+            STMPacket::Data { master: MasterID(0x1234), channel: ChannelID(0),
+                              value: STMDataValue::D8(0x56), marker: false, timestamp: None },
+        ]);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let packets = parse(&[
+            0xF, 0x0, 0x0, 0x3,                       // VERSION 3
+            0xF, 0x0, 0x1, 0x4, 0x1, 0x2, 0x3, 0x4,   // NULL_TS 0x1234
+            0xF, 0x0, 0x1, 0x1, 0x9,                  // NULL_TS low nibble 9
+            0xF, 0x0, 0x8, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0xA,  // FREQ 10
+            0xF, 0x0, 0x7, 0x0, 0x1, 0x1, 0x0,        // TRIG_TS 1, low nibble 0
+            0x0,                                      // NULL
+        ]);
+
+        assert_eq!(&packets[1..], &[
+            STMPacket::Version(3),
+            STMPacket::NullTimestamp(0x1234),
+            STMPacket::NullTimestamp(0x1239),
+            STMPacket::Frequency(10),
+            STMPacket::Trigger { master: MasterID(0), channel: ChannelID(0), value: 1, timestamp: Some(0x1230) },
+            STMPacket::Null,
+        ][..]);
+    }
+
+    #[test]
+    fn test_gray_code() {
+        for value in &[0u64, 1, 2, 0x1234, 0xFFFF_FFFF_FFFF] {
+            assert_eq!(gray_to_binary(binary_to_gray(*value)), *value);
+        }
+    }
+}
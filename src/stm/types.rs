@@ -0,0 +1,120 @@
+//! Packet types for ARM CoreSight System Trace Macrocell,
+//! which uses the MIPI STPv2 protocol.
+//! Reference: CoreSight STM-500 Technical Reference Manual
+
+use std::fmt;
+
+/// Decoded STPv2 packets. Data packets are annotated with the
+/// master and channel that were selected when they were received.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum STMPacket {
+    /// Padding packet.
+    Null,
+
+    /// Alignment synchronization, resets master and channel.
+    Async,
+
+    /// Protocol version, resets master and channel.
+    Version(u8),
+
+    /// Selects the master for following packets, and resets channel.
+    Master(MasterID),
+
+    /// Selects the channel for following packets.
+    Channel(ChannelID),
+
+    /// Data written by software to a stimulus channel.
+    Data {
+        master: MasterID,
+        channel: ChannelID,
+        value: STMDataValue,
+        marker: bool,
+        timestamp: Option<u64>,
+    },
+
+    /// Flag written to a stimulus channel, without data.
+    Flag {
+        master: MasterID,
+        channel: ChannelID,
+        timestamp: Option<u64>,
+    },
+
+    /// Hardware or software trigger event.
+    Trigger {
+        master: MasterID,
+        channel: ChannelID,
+        value: u8,
+        timestamp: Option<u64>,
+    },
+
+    /// Timestamp without data.
+    NullTimestamp(u64),
+
+    /// Frequency of the timestamp clock in Hz.
+    Frequency(u32),
+
+    /// Data lost for the current master.
+    MasterError(u8),
+
+    /// Data lost for all masters.
+    GlobalError(u8),
+
+    /// Undefined packet types, with the opcode nibbles.
+    Reserved(u16),
+    Invalid(String),
+}
+
+/// Represents STM master number, e.g. a processor or bus master.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct MasterID(pub u16);
+
+/// Represents STM stimulus channel number.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ChannelID(pub u16);
+
+/// A variably sized data value, corresponding to write size.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum STMDataValue {
+    D4(u8), D8(u8), D16(u16), D32(u32), D64(u64)
+}
+
+impl STMDataValue {
+    pub fn to_u64(&self) -> u64 {
+        match *self {
+            STMDataValue::D4(v) | STMDataValue::D8(v) => v as u64,
+            STMDataValue::D16(v) => v as u64,
+            STMDataValue::D32(v) => v as u64,
+            STMDataValue::D64(v) => v,
+        }
+    }
+
+    /// Size of the value in bits.
+    pub fn bit_width(&self) -> u8 {
+        match *self {
+            STMDataValue::D4(_) => 4,
+            STMDataValue::D8(_) => 8,
+            STMDataValue::D16(_) => 16,
+            STMDataValue::D32(_) => 32,
+            STMDataValue::D64(_) => 64,
+        }
+    }
+}
+
+impl fmt::Debug for STMDataValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            STMDataValue::D4(v) => write!(f, "0x{:01x}", v),
+            STMDataValue::D8(v) => write!(f, "0x{:02x}", v),
+            STMDataValue::D16(v) => write!(f, "0x{:04x}", v),
+            STMDataValue::D32(v) => write!(f, "0x{:08x}", v),
+            STMDataValue::D64(v) => write!(f, "0x{:016x}", v),
+        }
+    }
+}
+
+/// Encoding of the timestamp values, selected by the version packet.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TimestampEncoding {
+    NaturalBinary,
+    GrayCode,
+}
@@ -0,0 +1,186 @@
+//! Splits the TPIU stream by trace source ID, and decodes the
+//! data of each source with the protocol decoder configured for it.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, ErrorKind};
+use super::types::*;
+use ::itm::types::ITMPacket;
+use ::itm::parser as itm_parser;
+use ::stm::types::STMPacket;
+use ::stm::parser as stm_parser;
+
+/// Protocol used by a trace source.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SourceType {
+    ITM,
+    STM,
+    /// Data is passed through undecoded.
+    Raw,
+}
+
+/// Decoded packet from one of the trace sources.
+#[derive(Debug, Eq, PartialEq)]
+pub enum SourcePacket {
+    ITM(ITMPacket),
+    STM(STMPacket),
+    Raw(Vec<u8>),
+    /// Trigger packet from the TPIU itself.
+    Trigger(Vec<u8>),
+}
+
+/// Variants are named like those of `SourceType`.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
+enum SourceDecoder {
+    /// ITM packets are parsed once they have been received completely.
+    ITM(Vec<u8>),
+    STM(stm_parser::Decoder),
+    Raw,
+}
+
+impl SourceDecoder {
+    fn push(&mut self, data: Vec<u8>, output: &mut Vec<SourcePacket>) {
+        match *self {
+            SourceDecoder::ITM(ref mut buffer) => {
+                buffer.extend(data);
+                let mut consumed = 0;
+                loop {
+                    let mut cursor = Cursor::new(&buffer[consumed..]);
+                    match itm_parser::parse_one(&mut cursor) {
+                        Ok(packet) => {
+                            consumed += cursor.position() as usize;
+                            output.push(SourcePacket::ITM(packet));
+                        },
+                        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(e) => {
+                            consumed += cursor.position() as usize;
+                            output.push(SourcePacket::ITM(ITMPacket::Invalid(e.to_string())));
+                        }
+                    }
+                }
+                buffer.drain(..consumed);
+            },
+            SourceDecoder::STM(ref mut decoder) => {
+                for byte in data {
+                    output.extend(decoder.push(byte).into_iter().map(SourcePacket::STM));
+                }
+            },
+            SourceDecoder::Raw => output.push(SourcePacket::Raw(data)),
+        }
+    }
+}
+
+/// Routes the TPIU data packets to per-source decoders.
+/// Sources that have not been configured are passed as raw data.
+#[derive(Clone)]
+pub struct Demux {
+    sources: BTreeMap<TraceSourceID, SourceDecoder>,
+}
+
+impl Demux {
+    pub fn new() -> Demux {
+        Demux { sources: BTreeMap::new() }
+    }
+
+    /// Selects the decoder for a trace source ID.
+    pub fn add_source(&mut self, id: TraceSourceID, source_type: SourceType) {
+        let decoder = match source_type {
+            SourceType::ITM => SourceDecoder::ITM(Vec::new()),
+            SourceType::STM => SourceDecoder::STM(stm_parser::Decoder::new()),
+            SourceType::Raw => SourceDecoder::Raw,
+        };
+        self.sources.insert(id, decoder);
+    }
+
+    /// True if no source has partially received data, so that the
+    /// following packets decode the same as with a new demux.
+    /// STM decoders keep state, so they are never idle.
+    pub fn is_idle(&self) -> bool {
+        self.sources.values().all(|source| match *source {
+            SourceDecoder::ITM(ref buffer) => buffer.is_empty(),
+            SourceDecoder::STM(_) => false,
+            SourceDecoder::Raw => true,
+        })
+    }
+
+    /// Processes one TPIU packet, returning the packets decoded from it.
+    pub fn push(&mut self, packet: TPIUPacket) -> Vec<(TraceSourceID, SourcePacket)> {
+        match packet {
+            TPIUPacket::Data(id, data) => {
+                let mut output = Vec::new();
+                self.sources.entry(id).or_insert(SourceDecoder::Raw).push(data, &mut output);
+                output.into_iter().map(|p| (id, p)).collect()
+            },
+            TPIUPacket::Trigger(data) => vec![(TraceSourceID(0x7D), SourcePacket::Trigger(data))],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Default for Demux {
+    fn default() -> Demux {
+        Demux::new()
+    }
+}
+
+/// Iterator adapter that demultiplexes a stream of TPIU packets.
+pub struct DemuxIter<I> {
+    packets: I,
+    demux: Demux,
+    buffer: ::std::vec::IntoIter<(TraceSourceID, SourcePacket)>,
+}
+
+impl<I: Iterator<Item=TPIUPacket>> DemuxIter<I> {
+    pub fn new(packets: I, demux: Demux) -> DemuxIter<I> {
+        DemuxIter { packets, demux, buffer: Vec::new().into_iter() }
+    }
+}
+
+impl<I: Iterator<Item=TPIUPacket>> Iterator for DemuxIter<I> {
+    type Item = (TraceSourceID, SourcePacket);
+    fn next(&mut self) -> Option<(TraceSourceID, SourcePacket)> {
+        loop {
+            if let Some(packet) = self.buffer.next() {
+                return Some(packet);
+            }
+            let packet = self.packets.next()?;
+            self.buffer = self.demux.push(packet).into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::itm::types::*;
+    use ::stm::types::*;
+
+    #[test]
+    fn test_split_packets() {
+        let mut demux = Demux::new();
+        demux.add_source(TraceSourceID(1), SourceType::ITM);
+        demux.add_source(TraceSourceID(2), SourceType::STM);
+
+        let mut stm_data = vec![0xFF; 10];
+        stm_data.push(0x0F);
+        stm_data.extend(vec![0x11, 0x40, 0x0A]); // M8 0x10, D8 0xA0
+
+        let packets = vec![
+            TPIUPacket::Data(TraceSourceID(1), vec![0x17, 0x16, 0x02]),
+            TPIUPacket::Data(TraceSourceID(2), stm_data),
+            TPIUPacket::Data(TraceSourceID(1), vec![0x00, 0x08]),
+            TPIUPacket::Data(TraceSourceID(3), vec![0x42]),
+        ];
+
+        let result: Vec<(TraceSourceID, SourcePacket)> = DemuxIter::new(packets.into_iter(), demux).collect();
+        assert_eq!(result, vec![
+            (TraceSourceID(2), SourcePacket::STM(STMPacket::Async)),
+            (TraceSourceID(2), SourcePacket::STM(STMPacket::Master(MasterID(0x10)))),
+            (TraceSourceID(2), SourcePacket::STM(STMPacket::Data {
+                master: MasterID(0x10), channel: ChannelID(0), value: STMDataValue::D8(0xA0),
+                marker: false, timestamp: None })),
+            (TraceSourceID(1), SourcePacket::ITM(ITMPacket::ProgramCounter(Address(0x08000216)))),
+            (TraceSourceID(3), SourcePacket::Raw(vec![0x42])),
+        ]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Decodes formatted trace captured in an on-chip circular buffer,
+//! i.e. Embedded Trace Buffer or Trace Memory Controller in ETB/ETF
+//! mode. The dump is unwrapped into chronological order and the
+//! partial frame at the start is skipped.
+
+use std::io::Cursor;
+use super::parser::{Parser, find_frame_sync};
+
+/// Frame size of the trace formatter.
+const FRAME_SIZE: usize = 16;
+
+/// Unit of the read and write pointer registers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PointerUnit {
+    /// TMC: pointers are byte addresses.
+    Bytes,
+
+    /// ETB: pointers count 32-bit words.
+    Words,
+}
+
+/// Register values read along with the buffer contents.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BufferRegisters {
+    /// RRP: RAM read pointer.
+    pub read_pointer: u32,
+
+    /// RWP: RAM write pointer, the next location to be written.
+    pub write_pointer: u32,
+
+    /// STS: status register.
+    pub status: u32,
+
+    pub unit: PointerUnit,
+}
+
+impl BufferRegisters {
+    /// True if the write pointer has wrapped around, so that the
+    /// whole buffer contains valid trace.
+    pub fn full(&self) -> bool {
+        self.status & 1 != 0
+    }
+
+    /// Converts a pointer register value to offset in the buffer.
+    /// TMC pointers may include the base address of the memory.
+    fn offset(&self, pointer: u32, size: usize) -> usize {
+        let bytes = match self.unit {
+            PointerUnit::Bytes => pointer as usize,
+            PointerUnit::Words => pointer as usize * 4,
+        };
+        if size == 0 { 0 } else { bytes % size }
+    }
+}
+
+/// Buffer contents in chronological order, starting at a frame boundary.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceBuffer {
+    data: Vec<u8>,
+    wrapped: bool,
+    discarded: usize,
+    overwritten: usize,
+}
+
+impl TraceBuffer {
+    /// Unwraps the raw buffer dump using the register values.
+    /// If the buffer is full, the oldest data is at the write pointer,
+    /// otherwise the valid data is between the read and write pointers.
+    pub fn new(dump: &[u8], registers: &BufferRegisters) -> TraceBuffer {
+        let size = dump.len();
+        let write = registers.offset(registers.write_pointer, size);
+        let wrapped = registers.full();
+
+        let (start, mut data) = if wrapped {
+            let mut data = dump[write..].to_vec();
+            data.extend_from_slice(&dump[..write]);
+            (write, data)
+        } else {
+            let read = registers.offset(registers.read_pointer, size);
+            (read, dump[read.min(write)..write].to_vec())
+        };
+
+        let discarded = frame_alignment(&data, start).min(data.len());
+        data.drain(..discarded);
+
+        // Incomplete frame at the end cannot be decoded either.
+        let length = data.len() - data.len() % FRAME_SIZE;
+        data.truncate(length);
+
+        // After wrapping, the data from the buffer start up to the
+        // write pointer has replaced the oldest trace.
+        let overwritten = if wrapped { write } else { 0 };
+
+        TraceBuffer { data, wrapped, discarded, overwritten }
+    }
+
+    /// Unwrapped trace data, starting at a frame boundary.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// True if the buffer wrapped around. An unknown amount of older
+    /// trace was then overwritten, and the data does not start at
+    /// a synchronization point of the trace sources.
+    pub fn wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    /// Number of bytes at the start that were skipped to reach the
+    /// first complete frame.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Minimum number of bytes of older trace that were overwritten,
+    /// i.e. the bytes written after the last wrap. The hardware does
+    /// not record how many times the buffer has wrapped, so more may
+    /// have been lost.
+    pub fn overwritten(&self) -> usize {
+        self.overwritten
+    }
+
+    /// Returns a parser for the formatter frames in the buffer.
+    pub fn parser(&self) -> Parser {
+        Parser::new(Box::new(Cursor::new(self.data.clone())))
+    }
+}
+
+/// Finds the offset of the first complete frame in the unwrapped data.
+/// Frames are normally aligned to the buffer start, but if a frame
+/// synchronization packet is found at a different alignment, the
+/// synchronization packet is trusted instead.
+fn frame_alignment(data: &[u8], start: usize) -> usize {
+    let aligned = (FRAME_SIZE - start % FRAME_SIZE) % FRAME_SIZE;
+    match find_frame_sync(data) {
+        Some(sync) if sync % FRAME_SIZE != aligned => sync,
+        _ => aligned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tpiu::types::*;
+
+    fn frame(value: u8) -> Vec<u8> {
+        // ID change to source 1, followed by data bytes.
+        let mut frame = vec![value; 16];
+        frame[0] = 0x03;
+        frame[15] = 0x00;
+        frame
+    }
+
+    #[test]
+    fn test_unwrap() {
+        // Four frames written, the fifth one partially overwriting the first.
+        let mut dump = Vec::new();
+        for value in 1..5 {
+            dump.extend(frame(value * 2));
+        }
+        dump[..8].copy_from_slice(&frame(10)[..8]);
+
+        let registers = BufferRegisters {
+            read_pointer: 0, write_pointer: 2, status: 1, unit: PointerUnit::Words
+        };
+        let buffer = TraceBuffer::new(&dump, &registers);
+        assert!(buffer.wrapped());
+        assert_eq!(buffer.discarded(), 8);
+        assert_eq!(buffer.data().len(), 48);
+        assert_eq!(buffer.overwritten(), 8);
+
+        let packets: Vec<TPIUPacket> = buffer.parser().collect();
+        assert_eq!(packets, vec![
+            TPIUPacket::Data(TraceSourceID(1), vec![4; 14]),
+            TPIUPacket::Data(TraceSourceID(1), vec![6; 14]),
+            TPIUPacket::Data(TraceSourceID(1), vec![8; 14]),
+        ]);
+    }
+
+    #[test]
+    fn test_overwritten() {
+        // Writes continued 36 bytes past the start after wrapping.
+        let mut dump = Vec::new();
+        for value in 1..5 {
+            dump.extend(frame(value * 2));
+        }
+
+        let registers = BufferRegisters {
+            read_pointer: 0, write_pointer: 0x2000_0024, status: 1, unit: PointerUnit::Bytes
+        };
+        let buffer = TraceBuffer::new(&dump, &registers);
+        assert!(buffer.wrapped());
+        assert_eq!(buffer.discarded(), 12);
+        assert_eq!(buffer.overwritten(), 36);
+        assert_eq!(buffer.data().len(), 48);
+    }
+
+    #[test]
+    fn test_not_full() {
+        let mut dump = frame(2);
+        dump.extend(frame(4));
+        dump.extend(vec![0; 32]);
+
+        let registers = BufferRegisters {
+            read_pointer: 0x10, write_pointer: 0x20, status: 0, unit: PointerUnit::Bytes
+        };
+        let buffer = TraceBuffer::new(&dump, &registers);
+        assert!(!buffer.wrapped());
+        assert_eq!(buffer.overwritten(), 0);
+        assert_eq!(buffer.data(), &frame(4)[..]);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod types;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod demux;
+pub mod etb;
+pub mod parallel;
@@ -0,0 +1,268 @@
+//! Decodes a large TPIU capture on several threads. The input is
+//! split into chunks at full frame synchronization packets, and each
+//! chunk is decoded speculatively, without knowing the state at its
+//! start. The results are then merged in order, and the parts that
+//! depended on the unknown state are decoded again:
+//!
+//! - TPIU data before the first source ID change in a chunk gets the
+//!   source ID active at the end of the previous chunk.
+//! - If a frame crosses the chunk boundary, e.g. because the sync
+//!   pattern was inside frame data, the chunk is decoded again from
+//!   where the previous one ended.
+//! - If a source decoder had partial data at the chunk boundary, the
+//!   chunk is demultiplexed again until the decoder state matches
+//!   the speculative run, which for ITM happens within a few packets
+//!   or at the next ITM synchronization packet.
+//!
+//! The result is identical to decoding with `Parser` and `DemuxIter`.
+
+use std::mem;
+use std::thread;
+use super::types::*;
+use super::parser::{decode_frame_data, FRAME_SYNC, HALFWORD_SYNC};
+use super::demux::{Demux, SourcePacket};
+
+/// Result of decoding the frames of one chunk.
+struct ChunkFrames {
+    start: usize,
+    end: usize,
+    packets: Vec<TPIUPacket>,
+    /// Indexes of data sent before the first source ID change, stored
+    /// as Null packets until the source is known.
+    unresolved: Vec<usize>,
+    /// Source ID at the start of the chunk, once resolved.
+    initial: TraceSourceID,
+    /// Source ID after the last ID change in the chunk.
+    source: Option<TraceSourceID>,
+    /// Offset where the next frame starts.
+    stop: usize,
+}
+
+/// Decodes frames from start until reaching end or the packet limit,
+/// the same way as `Parser` does. The last frame may extend past end.
+fn decode_frames(data: &[u8], start: usize, end: usize, mut source: Option<TraceSourceID>,
+                 limit: usize) -> ChunkFrames {
+    let initial = source.unwrap_or(TraceSourceID(0));
+    let mut packets = Vec::new();
+    let mut unresolved = Vec::new();
+    let mut offset = start;
+    while offset < end && packets.len() < limit {
+        let rest = &data[offset..];
+        if rest.len() < 16 {
+            break;
+        } else if rest.starts_with(&FRAME_SYNC) {
+            packets.push(TPIUPacket::FrameSynchronization);
+            offset += 4;
+        } else if rest.starts_with(&HALFWORD_SYNC) {
+            packets.push(TPIUPacket::HalfwordSynchronization);
+            offset += 2;
+        } else {
+            let mut frame = [0u8; 16];
+            frame.copy_from_slice(&rest[..16]);
+            for (id, bytes) in decode_frame_data(&frame, &mut source) {
+                if id.is_none() {
+                    unresolved.push(packets.len());
+                }
+                packets.push(id.unwrap_or(TraceSourceID(0)).to_packet(bytes));
+            }
+            offset += 16;
+        }
+    }
+    ChunkFrames { start, end, packets, unresolved, initial, source, stop: offset }
+}
+
+/// Result of demultiplexing one chunk with a new demux.
+struct ChunkOutput {
+    output: Vec<(TraceSourceID, SourcePacket)>,
+    /// Packet counts after which the demux was idle, with the output
+    /// length at that point. Only the start of the chunk is recorded,
+    /// if the state does not converge there the rest is decoded again.
+    idle: Vec<(usize, usize)>,
+    demux: Demux,
+}
+
+/// Number of packets at the start of a chunk where the state can converge.
+const CHECKPOINTS: usize = 4096;
+
+fn demux_chunk(packets: Vec<TPIUPacket>, mut demux: Demux) -> ChunkOutput {
+    let mut output = Vec::new();
+    let mut idle = Vec::new();
+    for (index, packet) in packets.into_iter().enumerate() {
+        output.extend(demux.push(packet));
+        if index < CHECKPOINTS && demux.is_idle() {
+            idle.push((index + 1, output.len()));
+        }
+    }
+    ChunkOutput { output, idle, demux }
+}
+
+/// Finds the chunk boundaries: the first frame sync after each
+/// multiple of the chunk size, or the chunk size rounded to whole
+/// frames if there are no syncs.
+fn split(data: &[u8], chunks: usize) -> Vec<usize> {
+    let size = (data.len() / chunks.max(1)).max(16);
+    let mut bounds = vec![0];
+    let mut target = size;
+    while target < data.len() {
+        let sync = data[target..].windows(4).take(size).position(|w| w == FRAME_SYNC);
+        let bound = match sync {
+            Some(pos) => target + pos,
+            None => target - target % 16,
+        };
+        if bound > *bounds.last().unwrap() {
+            bounds.push(bound);
+        }
+        target += size;
+    }
+    bounds.push(data.len());
+    bounds
+}
+
+/// Runs the function on each item on its own thread, keeping the order.
+fn run_parallel<T: Send, R: Send, F: Fn(T) -> R + Sync>(items: Vec<T>, f: F) -> Vec<R> {
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items.into_iter().map(|item| scope.spawn(move || f(item))).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Decodes a complete capture in memory using several threads.
+pub struct ParallelDecoder {
+    demux: Demux,
+    threads: usize,
+}
+
+impl ParallelDecoder {
+    /// The demux selects the decoders of the trace sources.
+    pub fn new(demux: Demux) -> ParallelDecoder {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        ParallelDecoder { demux, threads }
+    }
+
+    /// Sets the number of chunks decoded in parallel.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Decodes the TPIU packets, in the same order as `Parser`.
+    pub fn decode_frames(&self, data: &[u8]) -> Vec<TPIUPacket> {
+        self.frames(data).into_iter().flat_map(|chunk| chunk.packets).collect()
+    }
+
+    /// Frames decoded in chunks, with the source IDs resolved.
+    fn frames(&self, data: &[u8]) -> Vec<ChunkFrames> {
+        let bounds = split(data, self.threads);
+        let ranges: Vec<(usize, usize)> = bounds.windows(2).map(|w| (w[0], w[1])).collect();
+        let chunks = run_parallel(ranges, |(start, end)| {
+            // First chunk starts at the start of the stream.
+            let source = if start == 0 { Some(TraceSourceID(0)) } else { None };
+            decode_frames(data, start, end, source, usize::MAX)
+        });
+
+        let mut result = Vec::new();
+        let mut source = TraceSourceID(0);
+        let mut offset = 0;
+        for mut chunk in chunks {
+            if chunk.start != offset {
+                // Previous chunk ended elsewhere, so the frame alignment was wrong.
+                chunk = decode_frames(data, offset, chunk.end.max(offset), Some(source), usize::MAX);
+            }
+
+            for &index in &chunk.unresolved {
+                if let TPIUPacket::Null(ref mut data) = chunk.packets[index] {
+                    let data = mem::take(data);
+                    chunk.packets[index] = source.to_packet(data);
+                }
+            }
+            chunk.unresolved.clear();
+            chunk.initial = source;
+            source = chunk.source.unwrap_or(source);
+            offset = chunk.stop;
+            result.push(chunk);
+        }
+        result
+    }
+
+    /// Decodes and demultiplexes the data, giving the same result as
+    /// `DemuxIter` over `Parser`.
+    pub fn decode(&self, data: &[u8]) -> Vec<(TraceSourceID, SourcePacket)> {
+        let frames = self.frames(data);
+        let bounds: Vec<_> = frames.iter().map(|chunk| (chunk.start, chunk.end, chunk.initial)).collect();
+        let outputs = run_parallel(frames, |chunk| demux_chunk(chunk.packets, self.demux.clone()));
+
+        let mut result = Vec::new();
+        let mut demux = self.demux.clone();
+        for ((start, end, initial), chunk) in bounds.into_iter().zip(outputs) {
+            if demux.is_idle() {
+                result.extend(chunk.output);
+                demux = chunk.demux;
+                continue;
+            }
+
+            // Decode again until the state matches the speculative run.
+            let head = decode_frames(data, start, end, Some(initial), CHECKPOINTS);
+            let mut converged = None;
+            for (index, packet) in head.packets.into_iter().enumerate() {
+                result.extend(demux.push(packet));
+                if demux.is_idle() {
+                    if let Some(&(_, length)) = chunk.idle.iter().find(|i| i.0 == index + 1) {
+                        converged = Some(length);
+                        break;
+                    }
+                }
+            }
+
+            if let Some(length) = converged {
+                result.extend(chunk.output.into_iter().skip(length));
+                demux = chunk.demux;
+            } else {
+                let source = head.source.unwrap_or(initial);
+                let rest = decode_frames(data, head.stop, end.max(head.stop), Some(source), usize::MAX);
+                for packet in rest.packets {
+                    result.extend(demux.push(packet));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use super::super::parser::Parser;
+    use super::super::demux::{DemuxIter, SourceType};
+
+    const DATA: &[u8] = include_bytes!("../../testdata/etm_itm_tpiu.bin");
+
+    fn check(demux: Demux) {
+        let expected: Vec<(TraceSourceID, SourcePacket)> =
+            DemuxIter::new(Parser::new(Box::new(Cursor::new(DATA))), demux.clone()).collect();
+        let frames: Vec<TPIUPacket> = Parser::new(Box::new(Cursor::new(DATA))).collect();
+
+        // Odd chunk counts put the boundaries at various alignments.
+        for &threads in &[1, 3, 7, 16] {
+            let mut decoder = ParallelDecoder::new(demux.clone());
+            decoder.set_threads(threads);
+            assert_eq!(decoder.decode_frames(DATA), frames);
+            assert_eq!(decoder.decode(DATA), expected);
+        }
+    }
+
+    #[test]
+    fn test_identical_output() {
+        let mut demux = Demux::new();
+        demux.add_source(TraceSourceID(1), SourceType::ITM);
+        check(demux.clone());
+
+        // STM decoder state never converges, so the chunks are decoded again.
+        demux.add_source(TraceSourceID(2), SourceType::STM);
+        check(demux);
+    }
+}
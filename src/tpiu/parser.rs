@@ -1,24 +1,124 @@
 //! Parses TPIU frames
 
-use std::io::{Read, Error};
+use std::io::{Read, Error, ErrorKind};
 use std::collections::VecDeque;
 use super::types::*;
 use ::utils::readpos::ReadPos;
+use ::decoder::Decoder;
+
+/// Full frame synchronization packet, as bytes in the stream.
+pub const FRAME_SYNC: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x7F];
+
+/// Halfword synchronization packet, as bytes in the stream.
+pub const HALFWORD_SYNC: [u8; 2] = [0xFF, 0x7F];
+
+/// Finds the offset of the first frame after a full frame
+/// synchronization packet in the data.
+pub fn find_frame_sync(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|w| w == FRAME_SYNC).map(|pos| {
+        // Skip over consecutive synchronization packets.
+        let mut end = pos + 4;
+        while data[end..].starts_with(&FRAME_SYNC) {
+            end += 4;
+        }
+        end
+    })
+}
+
+/// Decodes the data of one 16-byte frame. The source ID is updated
+/// by ID change bytes in the frame, and applies to the next frame.
+pub fn decode_frame(frame: &[u8; 16], source: &mut TraceSourceID) -> Vec<TPIUPacket> {
+    let mut current = Some(*source);
+    let result = decode_frame_data(frame, &mut current);
+    *source = current.unwrap_or(*source);
+    result.into_iter().map(|(id, data)| id.unwrap_or(*source).to_packet(data)).collect()
+}
+
+/// Decodes the data of one frame when the current source ID may be
+/// unknown, e.g. at the start of a chunk of the stream. Returns the
+/// data bytes grouped by source ID, with None for data before the
+/// first ID change.
+pub fn decode_frame_data(frame: &[u8; 16], source: &mut Option<TraceSourceID>)
+                         -> Vec<(Option<TraceSourceID>, Vec<u8>)> {
+    let mut result = Vec::new();
+    let mut data = Vec::<u8>::with_capacity(16);
+    let mut i = 0;
+    while i < 15 {
+        let aux_bit = (frame[15] >> (i / 2)) & 1;
+
+        if (frame[i] & 0x01) == 0 {
+            // Two data bytes, lowest bit of first byte is in byte 15
+            data.push((frame[i] & 0xFE) | aux_bit);
+
+            if i != 14 {
+                data.push(frame[i+1])
+            }
+        } else {
+            // Source change + one data byte
+            if i != 14 && aux_bit == 1 {
+                data.push(frame[i+1]);
+            }
+            
+            if data.len() > 0 {
+                result.push((*source, data));
+                data = Vec::<u8>::with_capacity(16);
+            }
+
+            *source = Some(TraceSourceID(frame[i] >> 1));
+
+            if i != 14 && aux_bit == 0 {
+                data.push(frame[i+1]);
+            }
+        }
+
+        i += 2;
+    }
+
+    if data.len() > 0 {
+        result.push((*source, data));
+    }
+
+    result
+}
+
+/// Settings for the TPIU parser.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Config {
+    /// Source ID of data before the first ID change in the stream.
+    pub source: TraceSourceID,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { source: TraceSourceID(0) }
+    }
+}
 
 pub struct Parser {
     input: ReadPos,
+    config: Config,
     source: TraceSourceID,
     error: Option<Error>,
-    buffer: VecDeque<TPIUPacket>,
+    /// Decoded packets with the offset of the frame they came from.
+    buffer: VecDeque<(usize, TPIUPacket)>,
+    pending: Vec<u8>,
+    packet_offset: usize,
 }
 
 impl Parser {
     pub fn new(input: Box<Read>) -> Parser {
+        Parser::with_config(input, Config::default())
+    }
+
+    pub fn with_config(input: Box<Read>, config: Config) -> Parser {
         Parser {
             input: ReadPos::new(input),
-            source:TraceSourceID(0),
+            config,
+            source: config.source,
             error: None,
-            buffer: VecDeque::<TPIUPacket>::with_capacity(16),
+            buffer: VecDeque::with_capacity(16),
+            pending: Vec::with_capacity(16),
+            packet_offset: 0,
         }
     }
 
@@ -26,6 +126,16 @@ impl Parser {
         self.input.position()
     }
 
+    /// Trace source ID that applies to data at the start of the next frame.
+    pub fn source(&self) -> TraceSourceID {
+        self.source
+    }
+
+    /// Sets the current source ID, when starting in the middle of a stream.
+    pub fn set_source(&mut self, source: TraceSourceID) {
+        self.source = source;
+    }
+
     pub fn error(&self) -> Option<&Error> {
         match self.error {
             Some(ref e) => Some(&e),
@@ -35,48 +145,38 @@ impl Parser {
 
     fn parse_frame(&mut self)
     {
-        let mut frame: [u8; 16] = [0; 16];
-        if let Err(e) = self.input.read(&mut frame) {
-            self.error = Some(e);
-            return;
-        }
-        
-        let mut data = Vec::<u8>::with_capacity(16);
-        let mut i = 0;
-        while i < 15 {
-            let aux_bit = (frame[15] >> (i / 2)) & 1;
-
-            if (frame[i] & 0x01) == 0 {
-                // Two data bytes, lowest bit of first byte is in byte 15
-                data.push((frame[i] & 0xFE) | aux_bit);
-
-                if i != 14 {
-                    data.push(frame[i+1])
-                }
-            } else {
-                // Source change + one data byte
-                if i != 14 && aux_bit == 1 {
-                    data.push(frame[i+1]);
-                }
-                
-                if data.len() > 0 {
-                    self.buffer.push_back(self.source.to_packet(data));
-                    data = Vec::<u8>::with_capacity(16);
-                }
-
-                self.source = TraceSourceID(frame[i] >> 1);
-
-                if i != 14 && aux_bit == 0 {
-                    data.push(frame[i+1]);
-                }
+        // Bytes of a partial frame are kept, so that a growing input
+        // continues the frame on the next call.
+        let offset = self.input.position() - self.pending.len();
+        let mut data = [0u8; 16];
+        while self.pending.len() < 16 {
+            let wanted = 16 - self.pending.len();
+            match self.input.read(&mut data[..wanted]) {
+                Ok(0) => return,
+                Ok(count) => self.pending.extend_from_slice(&data[..count]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => {self.error = Some(e); return}
             }
-
-            i += 2;
         }
 
-        if data.len() > 0 {
-            self.buffer.push_back(self.source.to_packet(data));
+        // Synchronization packets can occur between frames, and
+        // the rest of the bytes belong to the next frame.
+        if self.pending.starts_with(&FRAME_SYNC) {
+            self.pending.drain(..4);
+            self.buffer.push_back((offset, TPIUPacket::FrameSynchronization));
+            return;
+        } else if self.pending.starts_with(&HALFWORD_SYNC) {
+            self.pending.drain(..2);
+            self.buffer.push_back((offset, TPIUPacket::HalfwordSynchronization));
+            return;
         }
+
+        let mut frame: [u8; 16] = [0; 16];
+        frame.copy_from_slice(&self.pending);
+        self.pending.clear();
+
+        let packets = decode_frame(&frame, &mut self.source);
+        self.buffer.extend(packets.into_iter().map(|packet| (offset, packet)));
     }
 }
 
@@ -87,7 +187,54 @@ impl Iterator for Parser {
             self.parse_frame();
         }
 
-        self.buffer.pop_front()
+        let (offset, packet) = self.buffer.pop_front()?;
+        self.packet_offset = offset;
+        Some(packet)
+    }
+}
+
+impl Decoder for Parser {
+    type Packet = TPIUPacket;
+    type Config = Config;
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn position(&self) -> usize {
+        self.input.position()
+    }
+
+    fn packet_offset(&self) -> usize {
+        self.packet_offset
+    }
+
+    fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    fn reset(&mut self) {
+        self.source = self.config.source;
+        self.error = None;
+        self.buffer.clear();
+        self.pending.clear();
+    }
+
+    /// Frames start after a full frame synchronization packet.
+    fn resync(&mut self) -> bool {
+        self.reset();
+        let mut window = [0u8; 4];
+        let mut byte = [0u8; 1];
+        while window != FRAME_SYNC {
+            match self.input.read_exact(&mut byte) {
+                Ok(()) => {},
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return false,
+                Err(e) => {self.error = Some(e); return false}
+            }
+            window.rotate_left(1);
+            window[3] = byte[0];
+        }
+        true
     }
 }
 
@@ -110,6 +257,50 @@ mod tests {
                          TPIUPacket::Null(vec![0x00;8])]);
     }
 
+    #[test]
+    fn test_frame_sync() {
+        let mut data = vec![0x00, 0xFF, 0xFF, 0xFF, 0x7F];
+        data.extend(vec![0x03, 0x17, 0x14, 0x02, 0x00, 0x08, 0x01, 0x00,
+                         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(find_frame_sync(&data), Some(5));
+
+        test_single(data[1..].to_vec(),
+                    vec![TPIUPacket::FrameSynchronization,
+                         TPIUPacket::Data(TraceSourceID(1), vec![0x17, 0x14, 0x02, 0x00, 0x08]),
+                         TPIUPacket::Null(vec![0x00;8])]);
+    }
+
+    /// Returns the chunks one read at a time, an empty chunk being
+    /// a read at the current end of a growing input.
+    struct ShortReads(Vec<Vec<u8>>);
+
+    impl Read for ShortReads {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let count = self.0[0].len().min(buf.len());
+            buf[..count].copy_from_slice(&self.0[0][..count]);
+            self.0[0].drain(..count);
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        // The partial frame at the end of input is not decoded.
+        let mut data = vec![0xFF, 0xFF, 0xFF, 0x7F];
+        data.extend(vec![0x03, 0x17, 0x14, 0x02, 0x00, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let mut parser = Parser::new(Box::new(Cursor::new(data)));
+        assert_eq!(parser.next(), Some(TPIUPacket::FrameSynchronization));
+        assert_eq!(parser.next(), None);
+        assert_eq!(parser.next(), None);
+        assert!(parser.error().is_none());
+    }
+
     #[test]
     fn test_multiple_streams() {
         test_single(vec![0x03, 0x0E, 0x2C, 0x10, 0x05, 0x00, 0xFB, 0x00,
@@ -119,4 +310,20 @@ mod tests {
                          TPIUPacket::Trigger(vec![0x00]),
                          TPIUPacket::Data(TraceSourceID(2), vec![0x00, 0x00, 0x00, 0x00, 0x80, 0x08])]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_short_reads() {
+        let mut frame = vec![0x42; 16];
+        frame[0] = 0x03;
+        frame[15] = 0x00;
+        let chunks = vec![vec![0xFF, 0xFF], vec![0xFF, 0x7F], frame[..5].to_vec(), vec![],
+                          frame[5..9].to_vec(), frame[9..].to_vec()];
+        let mut parser = Parser::new(Box::new(ShortReads(chunks)));
+        assert_eq!(parser.next(), None);
+        assert_eq!(parser.next(), Some(TPIUPacket::FrameSynchronization));
+        assert_eq!(parser.next(), Some(TPIUPacket::Data(TraceSourceID(1), vec![0x42; 14])));
+        assert_eq!(parser.packet_offset(), 4);
+        assert_eq!(parser.next(), None);
+        assert!(parser.error().is_none());
+    }
+}
@@ -1,7 +1,11 @@
 //! Packet types for ARM Trace Port Interface Unit,
 //! also called Trace Formatter.
 
-#[derive(Debug, Eq, PartialEq)]
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TPIUPacket {
     /// Full frame synchronization packet, emitted between frames.
     FrameSynchronization,
@@ -25,6 +29,7 @@ pub enum TPIUPacket {
 
 /// Represents trace source ID
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TraceSourceID(pub u8);
 
 impl TraceSourceID {
@@ -4,13 +4,13 @@
 
 use std::io::{Read, Result};
 
-pub struct ReadPos {
-    inner: Box<Read>,
+pub struct ReadPos<T = Box<Read>> {
+    inner: T,
     position: usize
 }
 
-impl ReadPos {
-    pub fn new(inner: Box<Read>) -> ReadPos {
+impl<T: Read> ReadPos<T> {
+    pub fn new(inner: T) -> ReadPos<T> {
         ReadPos{inner, position:0}
     }
 
@@ -19,7 +19,7 @@ impl ReadPos {
     }
 }
 
-impl Read for ReadPos {
+impl<T: Read> Read for ReadPos<T> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize>
     {
         let result = self.inner.read(buf);
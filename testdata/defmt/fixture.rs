@@ -0,0 +1,26 @@
+// Source of testdata/defmt.elf, with the interned strings written the
+// way the defmt macros do it. Built on x86_64 with:
+//
+//   rustc --crate-type=lib --emit=obj -g -C opt-level=0 -C panic=abort \
+//         -C relocation-model=static fixture.rs -o fixture.o
+//   ld -T link.ld fixture.o -o ../defmt.elf
+#![no_std]
+
+pub mod sensor {
+    pub fn read() -> u16 {
+        #[link_section = ".defmt.1"]
+        #[export_name = "{\"package\":\"fixture\",\"tag\":\"defmt_info\",\"data\":\"temperature {=i16}\",\"disambiguator\":\"1\",\"crate_name\":\"fixture\"}"]
+        static DEFMT_LOG_STATEMENT: u8 = 0;
+        &DEFMT_LOG_STATEMENT as *const u8 as u16
+    }
+}
+
+pub fn start() -> u16 {
+    #[link_section = ".defmt.2"]
+    #[export_name = "{\"package\":\"fixture\",\"tag\":\"defmt_derived\",\"data\":\"Idle|Busy({=u8})\",\"disambiguator\":\"2\",\"crate_name\":\"fixture\"}"]
+    static DEFMT_LOG_STATEMENT: u8 = 0;
+    &DEFMT_LOG_STATEMENT as *const u8 as u16
+}
+
+#[export_name = "_defmt_encoding_ = rzcobs"]
+pub static ENCODING: u8 = 0;
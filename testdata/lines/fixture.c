@@ -0,0 +1,19 @@
+/* Source of testdata/lines.elf. Built on x86_64 with:
+ *
+ *   gcc -g -O0 -fno-asynchronous-unwind-tables -fdebug-prefix-map=$PWD=/src \
+ *       -c fixture.c -o fixture.o
+ *   ld -Ttext=0x1000 -e loop fixture.o -o ../lines.elf
+ */
+int counter;
+
+static int step(int value)
+{
+    if (value > 10)
+        return value - 10;
+    return value + 1;
+}
+
+void loop(void)
+{
+    counter = step(counter);
+}
@@ -0,0 +1,123 @@
+//! Checks that the C header is up to date, and runs a C program
+//! that uses the library through it.
+
+extern crate cbindgen;
+extern crate arm_coresight_decoder;
+
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use arm_coresight_decoder::decoder::Decoder;
+use arm_coresight_decoder::export::record::Record;
+use arm_coresight_decoder::itm::parser::Parser as ITMParser;
+use arm_coresight_decoder::itm::types::*;
+use arm_coresight_decoder::tpiu::demux::{Demux, SourcePacket, SourceType};
+use arm_coresight_decoder::tpiu::parser::Parser as TPIUParser;
+use arm_coresight_decoder::tpiu::types::*;
+
+const HEADER: &str = "include/arm_coresight_decoder.h";
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_header() {
+    let config = cbindgen::Config::from_file(root().join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::generate_with_config(root(), config).unwrap().write(&mut generated);
+    let path = root().join(HEADER);
+    if env::var_os("ACD_UPDATE_HEADER").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    let current = fs::read(&path).unwrap_or_default();
+    assert!(current == generated, "{} is out of date, run the tests with ACD_UPDATE_HEADER=1", HEADER);
+}
+
+fn itm_line(packet: &ITMPacket) -> String {
+    let (a, b) = match *packet {
+        ITMPacket::LocalTimestamp(sync, delta) => (delta.0, sync as u32),
+        ITMPacket::Software(port, value) => (port.0, value.to_u32()),
+        ITMPacket::ProgramCounter(address) => (address.0, 0),
+        ITMPacket::Exception(event, number) => (number.0, event as u32 + 1),
+        ITMPacket::DataTracePC(comp, address) => (comp.0, address.0),
+        ITMPacket::DataTraceWriteData(comp, value) => (comp.0, value.to_u32()),
+        _ => (0, 0),
+    };
+    format!("{} {} {}", Record::from_itm(None, None, None, packet).kind, a, b)
+}
+
+fn tpiu_line(offset: usize, packet: &TPIUPacket) -> String {
+    let (tag, source, length) = match *packet {
+        TPIUPacket::FrameSynchronization => (0, 0, 0),
+        TPIUPacket::HalfwordSynchronization => (1, 0, 0),
+        TPIUPacket::Data(id, ref data) => (2, id.0, data.len()),
+        TPIUPacket::Trigger(_) => (3, 0, 0),
+        TPIUPacket::Null(ref data) => (4, 0, data.len()),
+        TPIUPacket::Reserved(_) => (5, 0, 0),
+        TPIUPacket::Invalid(_) => (6, 0, 0),
+    };
+    format!("tpiu {} {} {} {}", offset, tag, source, length)
+}
+
+/// Output of tests/ffi/test.c, decoded with the Rust API.
+fn expected_output(itm: &[u8], tpiu: &[u8]) -> String {
+    let mut lines = Vec::new();
+    for (offset, packet) in ITMParser::new(Cursor::new(itm)).with_offsets() {
+        lines.push(format!("itm {} {}", offset, itm_line(&packet)));
+    }
+    for (offset, packet) in TPIUParser::new(Box::new(Cursor::new(tpiu.to_vec()))).with_offsets() {
+        lines.push(tpiu_line(offset, &packet));
+    }
+    let mut demux = Demux::new();
+    demux.add_source(TraceSourceID(1), SourceType::ITM);
+    for (offset, packet) in TPIUParser::new(Box::new(Cursor::new(tpiu.to_vec()))).with_offsets() {
+        for (id, packet) in demux.push(packet) {
+            let text = match packet {
+                SourcePacket::ITM(ref packet) => itm_line(packet),
+                SourcePacket::Raw(ref data) => format!("raw {}", data.len()),
+                _ => String::from("other"),
+            };
+            lines.push(format!("demux {} {} {}", offset, id.0, text));
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Builds the static library, which `cargo test` does not do, and
+/// links the C program with it.
+#[cfg(unix)]
+#[test]
+fn test_c_program() {
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+    let target_dir = profile_dir.parent().unwrap();
+
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args(["build", "--lib"]).arg("--target-dir").arg(target_dir).current_dir(root());
+    if profile_dir.ends_with("release") {
+        cargo.arg("--release");
+    }
+    let output = cargo.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let program = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+    let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let status = Command::new(compiler)
+        .args(["-std=c11", "-Wall", "-Wextra", "-Werror", "-o"]).arg(&program)
+        .arg("-I").arg(root().join("include"))
+        .arg(root().join("tests/ffi/test.c"))
+        .arg(profile_dir.join("libarm_coresight_decoder.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status().unwrap();
+    assert!(status.success());
+
+    let itm = root().join("testdata/itm_only.bin");
+    let tpiu = root().join("testdata/etm_itm_tpiu.bin");
+    let output = Command::new(&program).arg(&itm).arg(&tpiu).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = expected_output(&fs::read(&itm).unwrap(), &fs::read(&tpiu).unwrap());
+    assert!(String::from_utf8(output.stdout).unwrap() == expected);
+}
@@ -0,0 +1,162 @@
+/* Decodes the ITM and TPIU test files through the C API, and prints
+ * one line per packet for tests/ffi.rs to compare against the Rust
+ * decoders. Data is pushed in small chunks to split packets.
+ */
+
+#include <stdio.h>
+#include <string.h>
+#include "arm_coresight_decoder.h"
+
+#define CHUNK 7
+
+static const char *itm_names[] = {
+    "Synchronization", "Overflow", "LocalTimestamp", "GlobalTimestamp",
+    "SoftwarePageNumber", "Software", "EventCounter", "ProgramCounter",
+    "SleepMode", "Exception", "DataTracePC", "DataTraceOffset",
+    "DataTraceReadData", "DataTraceWriteData", "Extension", "Reserved", "Invalid",
+};
+
+static int failures = 0;
+
+#define CHECK(cond) do { \
+        if (!(cond)) { fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond); failures++; } \
+    } while (0)
+
+static void print_itm(const AcdItmPacket *p)
+{
+    unsigned long a = 0, b = 0;
+    switch (p->tag)
+    {
+        case AcdItmPacket_LocalTimestamp:
+            a = p->local_timestamp.delta; b = p->local_timestamp.sync; break;
+        case AcdItmPacket_Software:
+            a = p->software.port; b = p->software.value.value; break;
+        case AcdItmPacket_ProgramCounter:
+            a = p->program_counter.address; break;
+        case AcdItmPacket_Exception:
+            a = p->exception.number; b = p->exception.event; break;
+        case AcdItmPacket_DataTracePC:
+            a = p->data_trace_pc.comparator; b = p->data_trace_pc.address; break;
+        case AcdItmPacket_DataTraceWriteData:
+            a = p->data_trace_write_data.comparator; b = p->data_trace_write_data.value.value; break;
+        default:
+            break;
+    }
+    printf("%s %lu %lu\n", itm_names[p->tag], a, b);
+}
+
+static int on_itm(void *user, uint64_t offset, const AcdItmPacket *packet)
+{
+    (void)user;
+    printf("itm %llu ", (unsigned long long)offset);
+    print_itm(packet);
+    return 0;
+}
+
+static int on_tpiu(void *user, uint64_t offset, const AcdTpiuPacket *packet)
+{
+    size_t length = 0;
+    int source = 0;
+    (void)user;
+    switch (packet->tag)
+    {
+        case AcdTpiuPacket_Data:
+            source = packet->data.source; length = packet->data.data.length; break;
+        case AcdTpiuPacket_Null:
+            length = packet->null.data.length; break;
+        default:
+            break;
+    }
+    printf("tpiu %llu %d %d %zu\n", (unsigned long long)offset, (int)packet->tag, source, length);
+    return 0;
+}
+
+static int on_source(void *user, uint64_t offset, uint8_t source, const AcdSourcePacket *packet)
+{
+    (void)user;
+    printf("demux %llu %d ", (unsigned long long)offset, source);
+    switch (packet->tag)
+    {
+        case AcdSourcePacket_Itm:
+            print_itm(&packet->itm.packet); break;
+        case AcdSourcePacket_Raw:
+            printf("raw %zu\n", packet->raw.data.length); break;
+        default:
+            printf("other\n"); break;
+    }
+    return 0;
+}
+
+static int count_and_stop(void *user, uint64_t offset, const AcdItmPacket *packet)
+{
+    (void)offset; (void)packet;
+    return ++*(int*)user >= 2;
+}
+
+static size_t read_file(const char *path, uint8_t *buffer, size_t size)
+{
+    FILE *f = fopen(path, "rb");
+    size_t length;
+    if (!f) return 0;
+    length = fread(buffer, 1, size, f);
+    fclose(f);
+    return length;
+}
+
+static uint8_t itm_data[1 << 20];
+static uint8_t tpiu_data[1 << 20];
+
+int main(int argc, char **argv)
+{
+    size_t itm_length, tpiu_length, i;
+    int count = 0;
+    AcdItmDecoder *itm;
+    AcdTpiuDecoder *tpiu;
+    AcdDemux *demux;
+
+    if (argc != 3)
+    {
+        fprintf(stderr, "Usage: %s itm.bin tpiu.bin\n", argv[0]);
+        return 2;
+    }
+    itm_length = read_file(argv[1], itm_data, sizeof(itm_data));
+    tpiu_length = read_file(argv[2], tpiu_data, sizeof(tpiu_data));
+    CHECK(itm_length > 0 && tpiu_length > 0);
+    CHECK(acd_abi_version() == 1);
+
+    itm = acd_itm_decoder_new();
+    for (i = 0; i < itm_length; i += CHUNK)
+    {
+        size_t n = itm_length - i < CHUNK ? itm_length - i : CHUNK;
+        CHECK(acd_itm_decoder_push(itm, itm_data + i, n, on_itm, NULL) == AcdStatus_Ok);
+    }
+
+    /* Stopping from the callback, and bad arguments. */
+    CHECK(acd_itm_decoder_reset(itm) == AcdStatus_Ok);
+    CHECK(acd_itm_decoder_push(itm, itm_data, 64, count_and_stop, &count) == AcdStatus_Aborted);
+    CHECK(count == 2);
+    CHECK(acd_itm_decoder_push(itm, NULL, 1, on_itm, NULL) == AcdStatus_NullPointer);
+    CHECK(acd_itm_decoder_push(itm, itm_data, 1, NULL, NULL) == AcdStatus_NullPointer);
+    CHECK(acd_itm_decoder_push(NULL, itm_data, 1, on_itm, NULL) == AcdStatus_NullPointer);
+    acd_itm_decoder_free(itm);
+
+    tpiu = acd_tpiu_decoder_new(0);
+    for (i = 0; i < tpiu_length; i += CHUNK)
+    {
+        size_t n = tpiu_length - i < CHUNK ? tpiu_length - i : CHUNK;
+        CHECK(acd_tpiu_decoder_push(tpiu, tpiu_data + i, n, on_tpiu, NULL) == AcdStatus_Ok);
+    }
+    acd_tpiu_decoder_free(tpiu);
+
+    demux = acd_demux_new(0);
+    CHECK(acd_demux_add_source(demux, 1, AcdSourceType_Itm) == AcdStatus_Ok);
+    CHECK(acd_demux_add_source(demux, 0x70, AcdSourceType_Itm) == AcdStatus_InvalidArgument);
+    for (i = 0; i < tpiu_length; i += CHUNK)
+    {
+        size_t n = tpiu_length - i < CHUNK ? tpiu_length - i : CHUNK;
+        CHECK(acd_demux_push(demux, tpiu_data + i, n, on_source, NULL) == AcdStatus_Ok);
+    }
+    acd_demux_free(demux);
+
+    return failures ? 1 : 0;
+}
@@ -0,0 +1,41 @@
+//! Builds the Python extension module and runs the tests in
+//! tests/python with the interpreter given by PYO3_PYTHON, or
+//! python3 by default.
+
+#![cfg(feature = "python")]
+
+use std::env;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[test]
+fn test_python_module() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+    let target_dir = profile_dir.parent().unwrap();
+
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args(["build", "--lib", "--features", "python"]).arg("--target-dir").arg(target_dir).current_dir(root);
+    if profile_dir.ends_with("release") {
+        cargo.arg("--release");
+    }
+    let output = cargo.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Python imports extension modules by their name, without the "lib" prefix.
+    let module_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("python");
+    fs::create_dir_all(&module_dir).unwrap();
+    let library = format!("{}arm_coresight_decoder{}", DLL_PREFIX, DLL_SUFFIX);
+    let module = if cfg!(windows) { "arm_coresight_decoder.pyd" } else { "arm_coresight_decoder.so" };
+    fs::copy(profile_dir.join(library), module_dir.join(module)).unwrap();
+
+    let python = env::var("PYO3_PYTHON").unwrap_or_else(|_| String::from("python3"));
+    let output = Command::new(python)
+        .args(["-m", "unittest", "discover", "-v", "-s"]).arg(root.join("tests/python"))
+        .env("PYTHONPATH", &module_dir)
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,99 @@
+"""Tests of the Python extension module, run by tests/python.rs."""
+
+import array
+import os
+import unittest
+
+import arm_coresight_decoder as acd
+
+TESTDATA = os.path.join(os.path.dirname(__file__), "..", "..", "testdata")
+
+# Software write to port 1, exception entry, local timestamp, PC sample.
+ITM = bytes([0x0A, 0x34, 0x12, 0x0E, 0x25, 0x10, 0x20, 0x17, 0x16, 0x02, 0x00, 0x08])
+
+
+def read(name):
+    with open(os.path.join(TESTDATA, name), "rb") as f:
+        return f.read()
+
+
+class TestItm(unittest.TestCase):
+    def test_packets(self):
+        packets = list(acd.ItmParser(ITM))
+        self.assertEqual([p["type"] for p in packets],
+                         ["Software", "Exception", "LocalTimestamp", "ProgramCounter"])
+        self.assertEqual(packets[0]["port"], 1)
+        self.assertEqual(packets[0]["value"], 0x1234)
+        self.assertEqual(packets[1]["text"], "IRQ21")
+        self.assertEqual([p["time"] for p in packets], [2, 2, 2, 2])
+        self.assertEqual([p["offset"] for p in packets], [0, 3, 6, 7])
+
+    def test_open(self):
+        path = os.path.join(TESTDATA, "itm_only.bin")
+        self.assertEqual(list(acd.ItmParser.open(path)), list(acd.ItmParser(read("itm_only.bin"))))
+        with self.assertRaises(OSError):
+            acd.ItmParser.open(path + ".missing")
+
+    def test_columns(self):
+        data = read("itm_only.bin")
+        packets = list(acd.ItmParser(data))
+        columns = acd.itm_columns(data)
+        self.assertIsInstance(columns["time"], array.array)
+        self.assertEqual(len(columns["offset"]), len(packets))
+        self.assertEqual(list(columns["time"]), [p["time"] for p in packets])
+        self.assertEqual([acd.ITM_TYPES[t] for t in columns["type"]], [p["type"] for p in packets])
+        self.assertEqual(list(columns["value"]), [p["value"] or 0 for p in packets])
+
+    def test_schema(self):
+        packets = list(acd.ItmParser(ITM, schema="1 level q3.12"))
+        self.assertEqual(packets[1]["type"], "Signal")
+        self.assertEqual(packets[1]["signal"], "level")
+        self.assertEqual(packets[1]["signal_value"], 0x1234 / 4096)
+        self.assertIsNone(packets[0]["signal"])
+
+        columns = acd.itm_columns(ITM, schema="1 level q3.12")
+        self.assertEqual(list(columns["offset"]), [0, 3, 6, 7])
+        self.assertEqual(list(columns["signals"]["level"]["value"]), [0x1234 / 4096])
+        self.assertEqual(list(columns["signals"]["level"]["time"]), [2])
+        with self.assertRaises(ValueError):
+            acd.ItmParser(ITM, schema="1 level q3")
+
+    @unittest.skipUnless(__import__("importlib").util.find_spec("numpy"), "numpy is not installed")
+    def test_numpy(self):
+        import numpy
+        columns = acd.itm_columns(ITM)
+        self.assertEqual(numpy.asarray(columns["offset"]).tolist(), [0, 3, 6, 7])
+
+
+class TestTpiu(unittest.TestCase):
+    def test_demux(self):
+        data = read("etm_itm_tpiu.bin")
+        packets = acd.TpiuDemux({1: "itm"}).decode(data)
+        itm = [p for p in packets if p["source"] == 1]
+        self.assertTrue(itm)
+        self.assertTrue(all(p["type"] not in ("Data", "Raw") for p in itm))
+        self.assertTrue(all(p["time"] is not None for p in itm))
+        self.assertTrue(any(p["type"] == "Data" and p["source"] != 1 for p in packets))
+
+        demux = acd.TpiuDemux()
+        demux.add_source(1, "itm")
+        self.assertEqual(demux.decode(data), packets)
+        with self.assertRaises(ValueError):
+            demux.add_source(2, "etm")
+
+    def test_demux_schema(self):
+        packets = acd.TpiuDemux({1: "itm"}).decode(read("etm_itm_tpiu.bin"), schema="2 counter u8")
+        writes = [i for i, p in enumerate(packets) if p["type"] == "Software" and p["port"] == 2]
+        self.assertTrue(writes)
+        self.assertEqual([i + 1 for i in writes], [i for i, p in enumerate(packets) if p["type"] == "Signal"])
+        signal = packets[writes[0] + 1]
+        self.assertEqual((signal["source"], signal["signal"]), (1, "counter"))
+        self.assertEqual(signal["signal_value"], packets[writes[0]]["value"])
+
+    def test_frames(self):
+        packets = acd.tpiu_packets(read("etm_itm_tpiu.bin"))
+        self.assertTrue(any(p["type"] == "Data" and p["source"] == 1 for p in packets))
+
+
+if __name__ == "__main__":
+    unittest.main()